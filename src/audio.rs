@@ -0,0 +1,643 @@
+use std::{
+    fs::File,
+    io::{BufReader, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use mp4::{Metadata, Mp4Reader};
+
+use crate::{fallback_title, udta::ilst_artist, Track};
+
+#[derive(Default)]
+struct Tags {
+    artist: Option<String>,
+    title: Option<String>,
+    comment: Option<String>,
+}
+
+impl Tags {
+    fn push_comment(&mut self, comment: &[u8]) {
+        let comment = String::from_utf8_lossy(comment);
+        if let Some((key, value)) = comment.split_once('=') {
+            if key.eq_ignore_ascii_case("artist") && self.artist.is_none() {
+                self.artist = Some(value.into());
+            } else if key.eq_ignore_ascii_case("title") && self.title.is_none() {
+                self.title = Some(value.into());
+            } else if (key.eq_ignore_ascii_case("comment")
+                || key.eq_ignore_ascii_case("description"))
+                && self.comment.is_none()
+            {
+                self.comment = Some(value.into());
+            }
+        }
+    }
+
+    fn title(&self, path: &Path) -> String {
+        match (&self.artist, &self.title) {
+            (Some(artist), Some(title)) => format!("{} - {}", artist, title),
+            (None, Some(title)) => title.clone(),
+            _ => fallback_title(path),
+        }
+    }
+}
+
+pub(crate) fn audio_meta<P: AsRef<Path>>(path: P) -> Option<Track> {
+    let path = path.as_ref();
+    let ext = path.extension()?.to_ascii_lowercase();
+
+    let (duration, tags) = match ext.to_str()? {
+        "mp3" => mp3_meta(path)?,
+        "flac" => flac_meta(path)?,
+        "m4a" => m4a_meta(path)?,
+        "ogg" => ogg_meta(path)?,
+        _ => return None,
+    };
+
+    let title = tags.title(path);
+    let track = Track {
+        creator: tags.artist,
+        annotation: tags.comment,
+        ..Track::new(path.to_path_buf(), title, duration)
+    };
+
+    Some(track)
+}
+
+fn read_u32_le(data: &[u8]) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(..4)?.try_into().ok()?))
+}
+
+// Parses a Vorbis comment block, shared by FLAC and Ogg Vorbis/Opus.
+fn vorbis_comments(data: &[u8], tags: &mut Tags) -> Option<()> {
+    // Lengths come from the file, so every offset is checked against the end of the block
+    let vendor_len = read_u32_le(data)? as usize;
+    let mut offset = vendor_len.checked_add(4)?;
+    let count = read_u32_le(data.get(offset..)?)?;
+    offset += 4;
+
+    for _ in 0..count {
+        let len = read_u32_le(data.get(offset..)?)? as usize;
+        let start = offset + 4;
+        let end = start.checked_add(len)?;
+        tags.push_comment(data.get(start..end)?);
+        offset = end;
+    }
+
+    Some(())
+}
+
+fn flac_meta(path: &Path) -> Option<(u64, Tags)> {
+    let mut reader = BufReader::new(File::open(path).ok()?);
+
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic).ok()?;
+    if &magic != b"fLaC" {
+        return None;
+    }
+
+    let mut duration = None;
+    let mut tags = Tags::default();
+
+    loop {
+        let mut header = [0; 4];
+        reader.read_exact(&mut header).ok()?;
+        let last = header[0] & 0x80 != 0;
+        let block_type = header[0] & 0x7F;
+        let len = u32::from_be_bytes([0, header[1], header[2], header[3]]) as usize;
+
+        match block_type {
+            // STREAMINFO
+            0 => {
+                let mut block = vec![0; len];
+                reader.read_exact(&mut block).ok()?;
+                let packed = u64::from_be_bytes(block.get(10..18)?.try_into().ok()?);
+                let sample_rate = (packed >> 44) & 0xF_FFFF;
+                let total_samples = packed & 0xF_FFFF_FFFF;
+                if sample_rate == 0 {
+                    return None;
+                }
+                // A total of 0 samples means it's unknown, which is a duration of 0 as well
+                duration = Some(
+                    total_samples
+                        .checked_mul(1000)
+                        .map_or(0, |ms| ms / sample_rate),
+                );
+            }
+            // VORBIS_COMMENT
+            4 => {
+                let mut block = vec![0; len];
+                reader.read_exact(&mut block).ok()?;
+                vorbis_comments(&block, &mut tags);
+            }
+            _ => {
+                reader.seek(SeekFrom::Current(len as i64)).ok()?;
+            }
+        }
+
+        if last {
+            break;
+        }
+    }
+
+    Some((duration?, tags))
+}
+
+fn m4a_meta(path: &Path) -> Option<(u64, Tags)> {
+    let file = File::open(path).ok()?;
+    let size = file.metadata().ok()?.len();
+    let reader = BufReader::new(file);
+
+    let mp4 = Mp4Reader::read_header(reader, size).ok()?;
+
+    let metadata = mp4.metadata();
+    let tags = Tags {
+        artist: ilst_artist(path),
+        title: metadata.title().map(|t| t.into_owned()),
+        comment: metadata.summary().map(|s| s.into_owned()),
+    };
+
+    Some((mp4.duration().as_millis() as u64, tags))
+}
+
+struct OggPage {
+    segments: Vec<u8>,
+    data: Vec<u8>,
+}
+
+fn read_ogg_page<R: Read>(reader: &mut R) -> Option<OggPage> {
+    let mut header = [0; 27];
+    reader.read_exact(&mut header).ok()?;
+    if &header[..4] != b"OggS" {
+        return None;
+    }
+
+    let mut segments = vec![0; header[26] as usize];
+    reader.read_exact(&mut segments).ok()?;
+
+    let mut data = vec![0; segments.iter().map(|&s| s as usize).sum()];
+    reader.read_exact(&mut data).ok()?;
+
+    Some(OggPage { segments, data })
+}
+
+// Reads the first `count` packets of the stream, which hold the codec headers.
+fn read_ogg_packets<R: Read>(reader: &mut R, count: usize) -> Option<Vec<Vec<u8>>> {
+    let mut packets = vec![];
+    let mut current = vec![];
+
+    while packets.len() < count {
+        let page = read_ogg_page(reader)?;
+        let mut offset = 0;
+        for &segment in &page.segments {
+            current.extend_from_slice(&page.data[offset..offset + segment as usize]);
+            offset += segment as usize;
+            if segment < 255 {
+                packets.push(std::mem::take(&mut current));
+            }
+        }
+    }
+
+    Some(packets)
+}
+
+// The granule position of the last page that has one is the total sample count of the stream.
+// Pages on which no packet ends have a granule position of -1, pages with only headers one of 0.
+fn last_ogg_granule(file: &mut File) -> Option<u64> {
+    let size = file.metadata().ok()?.len();
+    let tail_len = size.min(65536);
+    file.seek(SeekFrom::Start(size - tail_len)).ok()?;
+
+    let mut tail = vec![0; tail_len as usize];
+    file.read_exact(&mut tail).ok()?;
+
+    let mut end = tail.len();
+    while let Some(pos) = tail[..end].windows(4).rposition(|w| w == b"OggS") {
+        end = pos;
+        let Some(granule) = tail.get(pos + 6..pos + 14) else {
+            continue;
+        };
+        match u64::from_le_bytes(granule.try_into().unwrap()) {
+            0 | u64::MAX => continue,
+            granule => return Some(granule),
+        }
+    }
+
+    None
+}
+
+fn ogg_meta(path: &Path) -> Option<(u64, Tags)> {
+    let mut file = File::open(path).ok()?;
+    let packets = read_ogg_packets(&mut BufReader::new(&mut file), 2)?;
+
+    let mut tags = Tags::default();
+    let (sample_rate, pre_skip) = if packets[0].starts_with(b"\x01vorbis") {
+        if packets[1].starts_with(b"\x03vorbis") {
+            vorbis_comments(&packets[1][7..], &mut tags);
+        }
+        (read_u32_le(packets[0].get(12..)?)? as u64, 0)
+    } else if packets[0].starts_with(b"OpusHead") {
+        if packets[1].starts_with(b"OpusTags") {
+            vorbis_comments(&packets[1][8..], &mut tags);
+        }
+        let pre_skip = u16::from_le_bytes(packets[0].get(10..12)?.try_into().ok()?);
+        // Opus granule positions are always in 48 kHz samples
+        (48000, pre_skip as u64)
+    } else {
+        return None;
+    };
+
+    if sample_rate == 0 {
+        return None;
+    }
+
+    // The duration is unknown if no page has a granule position
+    let duration = last_ogg_granule(&mut file)
+        .and_then(|granule| granule.saturating_sub(pre_skip).checked_mul(1000))
+        .map_or(0, |ms| ms / sample_rate);
+    Some((duration, tags))
+}
+
+fn syncsafe(data: &[u8]) -> usize {
+    data.iter()
+        .fold(0, |acc, &b| (acc << 7) | (b & 0x7F) as usize)
+}
+
+fn id3_text(frame: &[u8]) -> Option<String> {
+    let (&encoding, text) = frame.split_first()?;
+    let text = match encoding {
+        0 => text.iter().map(|&b| b as char).collect(),
+        1 | 2 => {
+            let mut big_endian = encoding == 2;
+            let mut text = text;
+            if text.starts_with(&[0xFE, 0xFF]) {
+                big_endian = true;
+                text = &text[2..];
+            } else if text.starts_with(&[0xFF, 0xFE]) {
+                big_endian = false;
+                text = &text[2..];
+            }
+
+            let units: Vec<u16> = text
+                .chunks_exact(2)
+                .map(|c| {
+                    if big_endian {
+                        u16::from_be_bytes([c[0], c[1]])
+                    } else {
+                        u16::from_le_bytes([c[0], c[1]])
+                    }
+                })
+                .collect();
+            String::from_utf16_lossy(&units)
+        }
+        3 => String::from_utf8_lossy(text).into_owned(),
+        _ => return None,
+    };
+
+    let text = text.trim_end_matches('\0');
+    if text.is_empty() {
+        None
+    } else {
+        Some(text.into())
+    }
+}
+
+// A comment frame has a language and a short description before the text, the description ends
+// with a null character of the size used by the encoding.
+fn id3_comment(frame: &[u8]) -> Option<String> {
+    let (&encoding, rest) = frame.split_first()?;
+    let rest = rest.get(3..)?;
+    let text_start = if matches!(encoding, 1 | 2) {
+        (0..rest.len() / 2).find(|&i| rest[2 * i] == 0 && rest[2 * i + 1] == 0)? * 2 + 2
+    } else {
+        rest.iter().position(|&b| b == 0)? + 1
+    };
+
+    id3_text(&[&[encoding], &rest[text_start..]].concat())
+}
+
+// Returns the size of the ID3v2 tag at the start of the file, including its header.
+fn id3v2_tags(data: &[u8], tags: &mut Tags) -> usize {
+    if data.len() < 10 || &data[..3] != b"ID3" {
+        return 0;
+    }
+
+    let version = data[3];
+    let tag_len = 10 + syncsafe(&data[6..10]);
+    let body = &data[10..tag_len.min(data.len())];
+
+    // ID3v2.2 uses three-character frame ids and is not read for tags
+    if version < 3 {
+        return tag_len;
+    }
+
+    let mut offset = 0;
+    while offset + 10 <= body.len() {
+        let id = &body[offset..offset + 4];
+        if id[0] == 0 {
+            break;
+        }
+
+        let len = if version == 4 {
+            syncsafe(&body[offset + 4..offset + 8])
+        } else {
+            u32::from_be_bytes(body[offset + 4..offset + 8].try_into().unwrap()) as usize
+        };
+
+        let start = offset + 10;
+        let Some(frame) = body.get(start..start + len) else {
+            break;
+        };
+
+        match id {
+            b"TIT2" if tags.title.is_none() => tags.title = id3_text(frame),
+            b"TPE1" if tags.artist.is_none() => tags.artist = id3_text(frame),
+            b"COMM" if tags.comment.is_none() => tags.comment = id3_comment(frame),
+            _ => (),
+        }
+
+        offset = start + len;
+    }
+
+    tag_len
+}
+
+static MPEG1_BITRATES: [u64; 16] = [
+    0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 0,
+];
+static MPEG2_BITRATES: [u64; 16] = [
+    0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160, 0,
+];
+static SAMPLE_RATES: [u64; 3] = [44100, 48000, 32000];
+
+fn mp3_meta(path: &Path) -> Option<(u64, Tags)> {
+    let mut file = File::open(path).ok()?;
+    let size = file.metadata().ok()?.len();
+
+    let mut head = vec![];
+    (&mut file).take(1 << 20).read_to_end(&mut head).ok()?;
+
+    let mut tags = Tags::default();
+    let audio_start = id3v2_tags(&head, &mut tags);
+
+    // An ID3 tag larger than the read buffer holds embedded pictures, skip past it
+    if audio_start + 4 > head.len() {
+        file.seek(SeekFrom::Start(audio_start as u64)).ok()?;
+        head.clear();
+        (&mut file).take(1 << 16).read_to_end(&mut head).ok()?;
+    } else {
+        head.drain(..audio_start);
+    }
+
+    // Find the first MPEG audio layer III frame header
+    let frame_start = head.windows(4).position(|w| {
+        w[0] == 0xFF
+            && w[1] & 0xE0 == 0xE0
+            && (w[1] >> 1) & 0x03 == 0x01
+            && (w[1] >> 3) & 0x03 != 0x01
+            && (w[2] >> 4) != 0x0F
+            && (w[2] >> 4) != 0x00
+            && (w[2] >> 2) & 0x03 != 0x03
+    })?;
+    let frame = &head[frame_start..];
+
+    let version = (frame[1] >> 3) & 0x03;
+    let mpeg1 = version == 0x03;
+    let bitrate = if mpeg1 {
+        MPEG1_BITRATES[(frame[2] >> 4) as usize]
+    } else {
+        MPEG2_BITRATES[(frame[2] >> 4) as usize]
+    };
+    let sample_rate = match version {
+        0x03 => SAMPLE_RATES[((frame[2] >> 2) & 0x03) as usize],
+        0x02 => SAMPLE_RATES[((frame[2] >> 2) & 0x03) as usize] / 2,
+        _ => SAMPLE_RATES[((frame[2] >> 2) & 0x03) as usize] / 4,
+    };
+    let mono = (frame[3] >> 6) == 0x03;
+    let samples_per_frame = if mpeg1 { 1152 } else { 576 };
+
+    // A Xing or Info header in the first frame stores the frame count of VBR files
+    let side_info = match (mpeg1, mono) {
+        (true, false) => 32,
+        (true, true) => 17,
+        (false, false) => 17,
+        (false, true) => 9,
+    };
+    let xing = frame.get(4 + side_info..4 + side_info + 12);
+    if let Some(xing) = xing {
+        if (&xing[..4] == b"Xing" || &xing[..4] == b"Info") && xing[7] & 0x01 != 0 {
+            let frames = u32::from_be_bytes(xing[8..12].try_into().unwrap()) as u64;
+            let duration = frames
+                .checked_mul(samples_per_frame * 1000)
+                .map_or(0, |ms| ms / sample_rate);
+            return Some((duration, tags));
+        }
+    }
+
+    // Otherwise assume a constant bitrate over the rest of the file
+    let audio_len = size.saturating_sub((audio_start + frame_start) as u64);
+    let duration = audio_len.checked_mul(8).map_or(0, |bits| bits / bitrate);
+    Some((duration, tags))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{mp4_box, mp4_with_udta, TempDir};
+
+    // The title, artist, comment and duration read from a file with this data.
+    fn meta(name: &str, data: &[u8]) -> Option<(String, Option<String>, Option<String>, u64)> {
+        let dir = TempDir::new();
+        let track = audio_meta(dir.file(name, data))?;
+        Some((
+            track.title().into(),
+            track.creator().map(String::from),
+            track.annotation().map(String::from),
+            track.duration(),
+        ))
+    }
+
+    fn vorbis_comment_block(comments: &[&str]) -> Vec<u8> {
+        let mut block = 6u32.to_le_bytes().to_vec();
+        block.extend(b"vendor");
+        block.extend((comments.len() as u32).to_le_bytes());
+        for comment in comments {
+            block.extend((comment.len() as u32).to_le_bytes());
+            block.extend(comment.as_bytes());
+        }
+        block
+    }
+
+    fn id3_frame(id: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        [&id[..], &(data.len() as u32).to_be_bytes(), &[0, 0], data].concat()
+    }
+
+    // An ID3v2.3 tag with a title, artist and comment.
+    fn id3_tag() -> Vec<u8> {
+        let frames = [
+            id3_frame(b"TIT2", b"\x03Title"),
+            id3_frame(b"TPE1", b"\x03Artist"),
+            id3_frame(b"COMM", b"\x03engdesc\0Comment"),
+        ]
+        .concat();
+        let len = frames.len();
+        let size = [
+            (len >> 21) as u8 & 0x7f,
+            (len >> 14) as u8 & 0x7f,
+            (len >> 7) as u8 & 0x7f,
+            len as u8 & 0x7f,
+        ];
+        [&b"ID3\x03\0\0"[..], &size, &frames].concat()
+    }
+
+    #[test]
+    fn mp3_tags_and_durations_are_read() {
+        // MPEG-1 layer III at 128 kbit/s and 44.1 kHz, stereo
+        let header = [0xff, 0xfb, 0x90, 0x00];
+        let mut xing = [
+            &header[..],
+            &[0; 32],
+            b"Xing",
+            &1u32.to_be_bytes(),
+            &100u32.to_be_bytes(),
+        ]
+        .concat();
+        xing.resize(417, 0);
+        let expected = (
+            "Artist - Title".to_string(),
+            Some("Artist".to_string()),
+            Some("Comment".to_string()),
+            100 * 1152 * 1000 / 44100,
+        );
+        assert_eq!(meta("a.mp3", &[id3_tag(), xing].concat()), Some(expected));
+
+        // Without a Xing header the bitrate is assumed to be constant
+        let mut cbr = header.to_vec();
+        cbr.resize(16_000, 0);
+        let (title, _, _, duration) = meta("b.mp3", &cbr).unwrap();
+        assert_eq!((title.as_str(), duration), ("b.mp3", 1000));
+        assert_eq!(meta("c.mp3", b"not audio"), None);
+    }
+
+    #[test]
+    fn flac_tags_and_durations_are_read() {
+        let mut streaminfo = vec![0; 34];
+        let packed: u64 = (44100 << 44) | 88200;
+        streaminfo[10..18].copy_from_slice(&packed.to_be_bytes());
+        let comments =
+            vorbis_comment_block(&["ARTIST=Artist", "title=Title", "DESCRIPTION=Comment"]);
+        let block_header = |block_type: u8, len: usize| {
+            [block_type, (len >> 16) as u8, (len >> 8) as u8, len as u8]
+        };
+        let data = [
+            &b"fLaC"[..],
+            &block_header(0, streaminfo.len()),
+            &streaminfo,
+            &block_header(0x84, comments.len()),
+            &comments,
+        ]
+        .concat();
+        let expected = (
+            "Artist - Title".to_string(),
+            Some("Artist".to_string()),
+            Some("Comment".to_string()),
+            2000,
+        );
+        assert_eq!(meta("a.flac", &data), Some(expected));
+        assert_eq!(meta("b.flac", &data[..50]), None);
+    }
+
+    #[test]
+    fn m4a_artists_are_read() {
+        let text_item = |kind: &[u8], text: &str| {
+            mp4_box(
+                kind,
+                &mp4_box(
+                    b"data",
+                    &[&[0, 0, 0, 1, 0, 0, 0, 0][..], text.as_bytes()].concat(),
+                ),
+            )
+        };
+        let ilst = mp4_box(
+            b"ilst",
+            &[
+                text_item(b"\xa9nam", "Title"),
+                text_item(b"\xa9ART", "Artist"),
+            ]
+            .concat(),
+        );
+        let meta_box = mp4_box(b"meta", &[&[0; 4][..], &ilst].concat());
+        let expected = (
+            "Artist - Title".to_string(),
+            Some("Artist".to_string()),
+            None,
+            3000,
+        );
+        assert_eq!(
+            meta("a.m4a", &mp4_with_udta(3000, &meta_box)),
+            Some(expected)
+        );
+    }
+
+    // An Ogg page with a single packet, which must be shorter than 255 bytes.
+    fn ogg_packet_page(granule: u64, packet: &[u8]) -> Vec<u8> {
+        let mut page = b"OggS\0\0".to_vec();
+        page.extend(granule.to_le_bytes());
+        page.extend([0; 12]);
+        page.extend([1, packet.len() as u8]);
+        page.extend(packet);
+        page
+    }
+
+    #[test]
+    fn ogg_tags_and_durations_are_read() {
+        let mut identification = b"\x01vorbis\0\0\0\0\x02".to_vec();
+        identification.extend(48000u32.to_le_bytes());
+        identification.resize(30, 0);
+        let comments = [&b"\x03vorbis"[..], &vorbis_comment_block(&["TITLE=Title"])].concat();
+        let data = [
+            ogg_packet_page(0, &identification),
+            ogg_packet_page(0, &comments),
+            ogg_page(96000),
+        ]
+        .concat();
+        assert_eq!(
+            meta("a.ogg", &data),
+            Some(("Title".into(), None, None, 2000))
+        );
+    }
+
+    #[test]
+    fn vorbis_comment_lengths_are_checked() {
+        let mut tags = Tags::default();
+        let mut block = vorbis_comment_block(&["TITLE=Title"]);
+        assert_eq!(vorbis_comments(&block, &mut tags), Some(()));
+        assert_eq!(tags.title.as_deref(), Some("Title"));
+
+        let comment_len = block.len() - 15;
+        block[comment_len..comment_len + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(vorbis_comments(&block, &mut Tags::default()), None);
+        block[..4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(vorbis_comments(&block, &mut Tags::default()), None);
+        assert_eq!(vorbis_comments(&[1, 0], &mut Tags::default()), None);
+    }
+
+    // The start of an Ogg page with this granule position, without any segments.
+    fn ogg_page(granule: u64) -> Vec<u8> {
+        [&b"OggS\0\0"[..], &granule.to_le_bytes(), &[0; 13]].concat()
+    }
+
+    #[test]
+    fn pages_without_a_granule_position_are_skipped() {
+        let dir = TempDir::new();
+        let pages = [ogg_page(0), ogg_page(96000), ogg_page(u64::MAX)].concat();
+        let path = dir.file("a.ogg", &pages);
+        assert_eq!(
+            last_ogg_granule(&mut File::open(path).unwrap()),
+            Some(96000)
+        );
+
+        let pages = [ogg_page(0), ogg_page(u64::MAX)].concat();
+        let path = dir.file("b.ogg", &pages);
+        assert_eq!(last_ogg_granule(&mut File::open(path).unwrap()), None);
+    }
+}
@@ -0,0 +1,221 @@
+use std::{
+    fs::File,
+    io::{BufReader, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use crate::{fallback_title, Track};
+
+// The header, OpenDML and INFO lists are at most two levels deep in valid files
+static MAX_LIST_DEPTH: usize = 4;
+// Header chunks are small, only this much of a chunk is read and the rest is skipped
+static MAX_CHUNK_READ: u32 = 64 * 1024;
+
+// Reads the id and size of the next RIFF chunk.
+fn read_chunk_header<R: Read>(reader: &mut R) -> Option<([u8; 4], u32)> {
+    let mut header = [0; 8];
+    reader.read_exact(&mut header).ok()?;
+    let size = u32::from_le_bytes(header[4..].try_into().unwrap());
+    Some((header[..4].try_into().unwrap(), size))
+}
+
+// Chunks are padded to an even size.
+fn padded(size: u32) -> u64 {
+    size as u64 + (size as u64 & 1)
+}
+
+#[derive(Default)]
+struct AviHeader {
+    micro_secs_per_frame: Option<u32>,
+    total_frames: Option<u32>,
+    // Total number of frames of an OpenDML file, the main header only counts those in the first
+    // RIFF chunk
+    odml_frames: Option<u32>,
+    title: Option<String>,
+}
+
+// Walks the chunks in a LIST of `size` bytes, descending into the header, OpenDML and INFO lists.
+// Lists nested deeper than `MAX_LIST_DEPTH` are skipped.
+fn read_list<R: Read + Seek>(
+    reader: &mut R,
+    size: u64,
+    depth: usize,
+    header: &mut AviHeader,
+) -> Option<()> {
+    let mut offset = 0;
+    while offset + 8 <= size {
+        let (id, chunk_size) = read_chunk_header(reader)?;
+        offset += 8 + padded(chunk_size);
+
+        match &id {
+            b"LIST" if chunk_size >= 4 => {
+                let mut list_type = [0; 4];
+                reader.read_exact(&mut list_type).ok()?;
+                match &list_type {
+                    b"hdrl" | b"odml" | b"INFO" if depth < MAX_LIST_DEPTH => {
+                        read_list(reader, chunk_size as u64 - 4, depth + 1, header)?;
+                        if chunk_size & 1 == 1 {
+                            reader.seek(SeekFrom::Current(1)).ok()?;
+                        }
+                    }
+                    _ => {
+                        reader
+                            .seek(SeekFrom::Current(padded(chunk_size) as i64 - 4))
+                            .ok()?;
+                    }
+                }
+            }
+            b"avih" | b"dmlh" | b"INAM" => {
+                let read_size = chunk_size.min(MAX_CHUNK_READ);
+                let mut data = vec![0; read_size as usize];
+                reader.read_exact(&mut data).ok()?;
+                reader
+                    .seek(SeekFrom::Current(
+                        (padded(chunk_size) - read_size as u64) as i64,
+                    ))
+                    .ok()?;
+
+                let read_u32 = |offset: usize| {
+                    Some(u32::from_le_bytes(
+                        data.get(offset..offset + 4)?.try_into().ok()?,
+                    ))
+                };
+                match &id {
+                    b"avih" => {
+                        header.micro_secs_per_frame = read_u32(0);
+                        header.total_frames = read_u32(16);
+                    }
+                    b"dmlh" => header.odml_frames = read_u32(0),
+                    _ => {
+                        let title = String::from_utf8_lossy(&data);
+                        let title = title.trim_end_matches('\0').trim();
+                        if !title.is_empty() {
+                            header.title = Some(title.into());
+                        }
+                    }
+                }
+            }
+            _ => {
+                reader
+                    .seek(SeekFrom::Current(padded(chunk_size) as i64))
+                    .ok()?;
+            }
+        }
+    }
+
+    Some(())
+}
+
+// Reads the duration from the main AVI header and the title from the INFO list, if there is one.
+pub(crate) fn avi_meta<P: AsRef<Path>>(path: P) -> Option<Track> {
+    let path = path.as_ref();
+    let file = File::open(path).ok()?;
+    let size = file.metadata().ok()?.len();
+    let mut reader = BufReader::new(file);
+
+    let (id, riff_size) = read_chunk_header(&mut reader)?;
+    let mut form_type = [0; 4];
+    reader.read_exact(&mut form_type).ok()?;
+    if &id != b"RIFF" || &form_type != b"AVI " {
+        return None;
+    }
+
+    let mut header = AviHeader::default();
+    let riff_size = (riff_size as u64).min(size - 8);
+    read_list(&mut reader, riff_size.saturating_sub(4), 0, &mut header);
+
+    let micro_secs_per_frame = header.micro_secs_per_frame?;
+    let frames = header.odml_frames.or(header.total_frames)?;
+
+    let duration = frames as u64 * micro_secs_per_frame as u64 / 1000;
+    let title = header.title.unwrap_or_else(|| fallback_title(path));
+    Some(Track::new(path.to_path_buf(), title, duration))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    fn chunk(id: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut chunk = id.to_vec();
+        chunk.extend((data.len() as u32).to_le_bytes());
+        chunk.extend(data);
+        if data.len() % 2 == 1 {
+            chunk.push(0);
+        }
+        chunk
+    }
+
+    fn list(list_type: &[u8; 4], chunks: &[Vec<u8>]) -> Vec<u8> {
+        chunk(b"LIST", &[&list_type[..], &chunks.concat()].concat())
+    }
+
+    // A main header of `frames` frames of 40 ms.
+    fn avih(frames: u32) -> Vec<u8> {
+        let mut data = vec![0; 56];
+        data[..4].copy_from_slice(&40_000u32.to_le_bytes());
+        data[16..20].copy_from_slice(&frames.to_le_bytes());
+        chunk(b"avih", &data)
+    }
+
+    fn avi(chunks: &[Vec<u8>]) -> Vec<u8> {
+        chunk(b"RIFF", &[&b"AVI "[..], &chunks.concat()].concat())
+    }
+
+    fn meta(data: &[u8]) -> Option<(u64, String)> {
+        let dir = TempDir::new();
+        let track = avi_meta(dir.file("a.avi", data))?;
+        Some((track.duration, track.title))
+    }
+
+    #[test]
+    fn headers_are_read() {
+        let data = avi(&[
+            list(
+                b"hdrl",
+                &[
+                    avih(250),
+                    list(b"odml", &[chunk(b"dmlh", &500u32.to_le_bytes())]),
+                ],
+            ),
+            list(b"INFO", &[chunk(b"INAM", b"Title\0")]),
+            list(b"movi", &[chunk(b"00dc", &[0; 99])]),
+        ]);
+        assert_eq!(meta(&data), Some((20_000, "Title".into())));
+    }
+
+    #[test]
+    fn truncated_files_keep_what_was_read() {
+        let data = avi(&[
+            list(b"hdrl", &[avih(250)]),
+            list(b"INFO", &[chunk(b"INAM", b"Title")]),
+        ]);
+        assert_eq!(
+            meta(&data[..data.len() - 4]),
+            Some((10_000, "a.avi".into()))
+        );
+        assert_eq!(meta(&data[..40]), None);
+        assert_eq!(meta(&data[..10]), None);
+    }
+
+    #[test]
+    fn oversized_chunks_and_deep_lists_are_skipped() {
+        // A title that claims to be 4 GiB is only read as far as the cap, which fails here
+        let mut title = chunk(b"INAM", &[b'a'; 100]);
+        title[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+        let data = avi(&[list(b"hdrl", &[avih(250)]), list(b"INFO", &[title])]);
+        assert_eq!(meta(&data), Some((10_000, "a.avi".into())));
+
+        let mut deep = list(b"hdrl", &[avih(250)]);
+        for _ in 0..10_000 {
+            deep = list(b"hdrl", &[deep]);
+        }
+        assert_eq!(meta(&avi(&[deep])), None);
+        let mut shallow = list(b"hdrl", &[avih(250)]);
+        for _ in 0..MAX_LIST_DEPTH - 1 {
+            shallow = list(b"hdrl", &[shallow]);
+        }
+        assert_eq!(meta(&avi(&[shallow])), Some((10_000, "a.avi".into())));
+    }
+}
@@ -0,0 +1,97 @@
+use std::path::PathBuf;
+
+use crate::{Playlist, PlaylistNode, Track, TrackList};
+
+/// Builds a playlist without scanning the file system, e.g. from the results of a database query.
+/// Tracks and directories are added to the directory that was opened last, in the order they're
+/// added.
+///
+/// # Example
+///
+/// ```
+/// use vlc_playlist_generator::{into_xml, PlaylistBuilder, XmlOptions};
+///
+/// let mut builder = PlaylistBuilder::new();
+/// builder
+///     .add_directory("Series")
+///     .add_track("/media/series/S01E01.mkv", "Pilot", 2_580_000)
+///     .end_directory()
+///     .add_track("/media/movie.mp4", "Movie", 0);
+///
+/// let mut xml = vec![];
+/// into_xml(&mut xml, builder.build(), &XmlOptions::default()).unwrap();
+/// ```
+#[derive(Default)]
+pub struct PlaylistBuilder {
+    tracks: Vec<Track>,
+    // The open directories with their title and nodes, the first entry is the top level
+    dirs: Vec<(String, Vec<PlaylistNode>)>,
+}
+
+impl PlaylistBuilder {
+    pub fn new() -> Self {
+        PlaylistBuilder::default()
+    }
+
+    fn nodes(&mut self) -> &mut Vec<PlaylistNode> {
+        if self.dirs.is_empty() {
+            self.dirs.push((String::new(), vec![]));
+        }
+        &mut self.dirs.last_mut().unwrap().1
+    }
+
+    /// Adds a track to the current directory. The duration is in milliseconds, 0 if it's unknown.
+    pub fn add_track<P: Into<PathBuf>, S: Into<String>>(
+        &mut self,
+        location: P,
+        title: S,
+        duration: u64,
+    ) -> &mut Self {
+        let location = location.into();
+        let name = location
+            .file_name()
+            .map(|name| name.to_os_string())
+            .unwrap_or_default();
+
+        let idx = self.tracks.len();
+        self.tracks
+            .push(Track::new(location, title.into(), duration));
+        self.nodes().push(PlaylistNode::File(idx, name));
+        self
+    }
+
+    /// Opens a directory in the current directory, the tracks and directories that are added
+    /// until [`end_directory`](Self::end_directory) is called are added to it.
+    pub fn add_directory<S: Into<String>>(&mut self, title: S) -> &mut Self {
+        self.nodes();
+        self.dirs.push((title.into(), vec![]));
+        self
+    }
+
+    /// Closes the current directory, nothing happens at the top level.
+    pub fn end_directory(&mut self) -> &mut Self {
+        if self.dirs.len() > 1 {
+            let (title, nodes) = self.dirs.pop().unwrap();
+            self.nodes().push(PlaylistNode::Dir {
+                title,
+                path: PathBuf::new(),
+                nodes,
+            });
+        }
+        self
+    }
+
+    /// Closes all open directories and returns the playlist.
+    pub fn build(mut self) -> Playlist {
+        while self.dirs.len() > 1 {
+            self.end_directory();
+        }
+
+        Playlist {
+            track_list: TrackList {
+                tracks: self.tracks,
+            },
+            nodes: self.dirs.pop().map(|(_, nodes)| nodes).unwrap_or_default(),
+        }
+    }
+}
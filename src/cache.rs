@@ -0,0 +1,136 @@
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Chapter, Track};
+
+// The modification time and size of a file, a cached track is only used if these are unchanged.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+pub(crate) struct FileStamp {
+    mtime_secs: u64,
+    mtime_nanos: u32,
+    size: u64,
+}
+
+impl FileStamp {
+    pub(crate) fn of(path: &Path) -> Option<Self> {
+        let meta = fs::metadata(path).ok()?;
+        let mtime = meta.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+
+        Some(FileStamp {
+            mtime_secs: mtime.as_secs(),
+            mtime_nanos: mtime.subsec_nanos(),
+            size: meta.len(),
+        })
+    }
+
+    pub(crate) fn modified(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::new(self.mtime_secs, self.mtime_nanos)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheEntry {
+    stamp: FileStamp,
+    title: String,
+    duration: u64,
+    #[serde(default)]
+    chapters: Vec<Chapter>,
+    #[serde(default)]
+    creator: Option<String>,
+    #[serde(default)]
+    annotation: Option<String>,
+}
+
+/// Metadata of previously scanned files, so unchanged files don't have to be parsed again.
+#[derive(Serialize, Deserialize, Default)]
+pub struct MetadataCache {
+    entries: HashMap<String, CacheEntry>,
+}
+
+impl MetadataCache {
+    /// Loads a cache written by [`MetadataCache::save`]. A missing or invalid cache file results
+    /// in an empty cache.
+    pub fn load<P: AsRef<Path>>(path: P) -> Self {
+        fs::read(path)
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let data = serde_json::to_vec(self)?;
+        fs::write(path, data)
+    }
+
+    pub(crate) fn get(&self, path: &Path, stamp: FileStamp) -> Option<Track> {
+        let entry = self.entries.get(path.to_str()?)?;
+        if entry.stamp != stamp {
+            return None;
+        }
+
+        Some(Track {
+            chapters: entry.chapters.clone(),
+            creator: entry.creator.clone(),
+            annotation: entry.annotation.clone(),
+            ..Track::new(path.to_path_buf(), entry.title.clone(), entry.duration)
+        })
+    }
+
+    // Paths that aren't valid UTF-8 can't be stored as JSON keys and are never cached.
+    pub(crate) fn insert(&mut self, track: &Track, stamp: FileStamp) {
+        if let Some(path) = track.location().to_str() {
+            let entry = CacheEntry {
+                stamp,
+                title: track.title().into(),
+                duration: track.duration(),
+                chapters: track.chapters().to_vec(),
+                creator: track.creator().map(String::from),
+                annotation: track.annotation().map(String::from),
+            };
+            self.entries.insert(path.into(), entry);
+        }
+    }
+
+    // Drops the entries of files that no longer exist.
+    pub(crate) fn remove_missing(&mut self) {
+        self.entries.retain(|path, _| Path::new(path).exists());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        mkv_meta,
+        test_util::{mkv, TempDir},
+    };
+
+    #[test]
+    fn changed_files_miss() {
+        let dir = TempDir::new();
+        let path = dir.file("a.mkv", &mkv(1000.0));
+        let stamp = FileStamp::of(&path).unwrap();
+        let mut cache = MetadataCache::default();
+        assert!(cache.get(&path, stamp).is_none());
+
+        cache.insert(&mkv_meta(&path).unwrap(), stamp);
+        let cache_path = dir.path().join("cache.json");
+        cache.save(&cache_path).unwrap();
+        let cache = MetadataCache::load(&cache_path);
+        let track = cache.get(&path, stamp).unwrap();
+        assert_eq!(track.location(), path);
+        assert_eq!(track.duration(), 1000);
+
+        // A file of another size has another stamp, even if its time didn't change
+        dir.file("a.mkv", &[mkv(1000.0), vec![0]].concat());
+        let changed = FileStamp::of(&path).unwrap();
+        assert!(changed != stamp);
+        assert!(cache.get(&path, changed).is_none());
+    }
+}
@@ -0,0 +1,266 @@
+use std::{fmt, iter::Peekable, str::Chars};
+
+// A value in a configuration file. Only the parts of TOML that map onto command-line arguments
+// are supported, i.e. strings, integers, booleans and arrays of these.
+#[derive(Debug, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+    Array(Vec<Value>),
+}
+
+#[derive(Debug)]
+pub struct ConfigError {
+    line: usize,
+    message: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+    line: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error<T>(&self, message: impl Into<String>) -> Result<T, ConfigError> {
+        Err(ConfigError {
+            line: self.line,
+            message: message.into(),
+        })
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next();
+        if c == Some('\n') {
+            self.line += 1;
+        }
+        c
+    }
+
+    fn skip_comment(&mut self) {
+        while self.chars.next_if(|&c| c != '\n').is_some() {}
+    }
+
+    // Skips spaces and tabs, and also line breaks and comments if `newlines` is set.
+    fn skip_whitespace(&mut self, newlines: bool) {
+        loop {
+            match self.chars.peek() {
+                Some(' ' | '\t' | '\r') => {
+                    self.next();
+                }
+                Some('\n') if newlines => {
+                    self.next();
+                }
+                Some('#') if newlines => self.skip_comment(),
+                _ => return,
+            }
+        }
+    }
+
+    fn key(&mut self) -> Result<String, ConfigError> {
+        match self.chars.peek() {
+            Some('"' | '\'') => self.string(),
+            Some('[') => self.error("tables are not supported"),
+            _ => {
+                let mut key = String::new();
+                while let Some(c) = self
+                    .chars
+                    .next_if(|&c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+                {
+                    key.push(c);
+                }
+
+                if key.is_empty() {
+                    return self.error("expected a key");
+                }
+                Ok(key)
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, ConfigError> {
+        let quote = self.next().unwrap();
+        let mut string = String::new();
+        loop {
+            match self.next() {
+                Some(c) if c == quote => return Ok(string),
+                None | Some('\n') => return self.error("unterminated string"),
+                // Literal strings surrounded by single quotes have no escapes
+                Some('\\') if quote == '"' => {
+                    let escaped = match self.next() {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some(u @ ('u' | 'U')) => {
+                            let len = if u == 'u' { 4 } else { 8 };
+                            let hex: String = (0..len).filter_map(|_| self.next()).collect();
+                            match u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32) {
+                                Some(c) => c,
+                                None => {
+                                    return self.error(format!("invalid escape \\{}{}", u, hex))
+                                }
+                            }
+                        }
+                        Some(c) => return self.error(format!("invalid escape \\{}", c)),
+                        None => return self.error("unterminated string"),
+                    };
+                    string.push(escaped);
+                }
+                Some(c) => string.push(c),
+            }
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, ConfigError> {
+        match self.chars.peek() {
+            Some('"' | '\'') => self.string().map(Value::String),
+            Some('[') => {
+                self.next();
+                let mut values = vec![];
+                loop {
+                    self.skip_whitespace(true);
+                    if self.chars.next_if_eq(&']').is_some() {
+                        return Ok(Value::Array(values));
+                    }
+
+                    values.push(self.value()?);
+                    self.skip_whitespace(true);
+                    match self.next() {
+                        Some(',') => (),
+                        Some(']') => return Ok(Value::Array(values)),
+                        _ => return self.error("expected `,` or `]` in array"),
+                    }
+                }
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(c) = self
+                    .chars
+                    .next_if(|&c| c.is_ascii_alphanumeric() || "+-_".contains(c))
+                {
+                    word.push(c);
+                }
+
+                match word.as_str() {
+                    "true" => Ok(Value::Boolean(true)),
+                    "false" => Ok(Value::Boolean(false)),
+                    _ => match word.replace('_', "").parse() {
+                        Ok(integer) => Ok(Value::Integer(integer)),
+                        Err(_) if word.is_empty() => self.error("expected a value"),
+                        Err(_) => self.error(format!("unsupported value `{}`", word)),
+                    },
+                }
+            }
+        }
+    }
+}
+
+// Parses the `key = value` pairs of a configuration file in order. Tables, floats and dates
+// aren't supported.
+pub fn parse_config(contents: &str) -> Result<Vec<(String, Value)>, ConfigError> {
+    let mut parser = Parser {
+        chars: contents.chars().peekable(),
+        line: 1,
+    };
+
+    let mut pairs: Vec<(String, Value)> = vec![];
+    loop {
+        parser.skip_whitespace(true);
+        if parser.chars.peek().is_none() {
+            return Ok(pairs);
+        }
+
+        let key = parser.key()?;
+        if pairs.iter().any(|(k, _)| *k == key) {
+            return parser.error(format!("duplicate key `{}`", key));
+        }
+
+        parser.skip_whitespace(false);
+        if parser.next() != Some('=') {
+            return parser.error(format!("expected `=` after `{}`", key));
+        }
+
+        parser.skip_whitespace(false);
+        let value = parser.value()?;
+        parser.skip_whitespace(false);
+        match parser.chars.peek() {
+            None | Some('\n') => (),
+            Some('#') => parser.skip_comment(),
+            Some(_) => return parser.error("expected a line break after the value"),
+        }
+
+        pairs.push((key, value));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error_line(contents: &str) -> usize {
+        parse_config(contents).unwrap_err().line
+    }
+
+    #[test]
+    fn values_are_parsed() {
+        let contents = r#"
+# Libraries
+root = ["/media/movies", '/media/C:\series', ] # trailing comma
+title = "Tab\t\"quoted\" \u00e9\U0001F600"
+"max-depth" = 1_000
+hidden = true
+skip = [
+    # comments and line breaks in arrays
+    "a",
+    [1, -2],
+]
+"#;
+        let string = |s: &str| Value::String(s.into());
+        assert_eq!(
+            parse_config(contents).unwrap(),
+            [
+                (
+                    "root".into(),
+                    Value::Array(vec![string("/media/movies"), string("/media/C:\\series")])
+                ),
+                ("title".into(), string("Tab\t\"quoted\" \u{e9}\u{1f600}")),
+                ("max-depth".into(), Value::Integer(1000)),
+                ("hidden".into(), Value::Boolean(true)),
+                (
+                    "skip".into(),
+                    Value::Array(vec![
+                        string("a"),
+                        Value::Array(vec![Value::Integer(1), Value::Integer(-2)])
+                    ])
+                ),
+            ]
+        );
+        assert_eq!(parse_config("").unwrap(), []);
+    }
+
+    #[test]
+    fn errors_have_their_line() {
+        assert_eq!(error_line("a = 1\na = 2"), 2);
+        assert_eq!(error_line("a = 1\n\n[table]"), 3);
+        assert_eq!(error_line("a = \"unterminated\nb = 1"), 2);
+        assert_eq!(error_line("a = \"\\q\""), 1);
+        assert_eq!(error_line("a = \"\\uzzzz\""), 1);
+        assert_eq!(error_line("a = 1.5"), 1);
+        assert_eq!(error_line("a = 1 b = 2"), 1);
+        assert_eq!(error_line("a 1"), 1);
+        assert_eq!(error_line("a ="), 1);
+        assert_eq!(error_line("a = [1 2]"), 1);
+        assert_eq!(error_line("= 1"), 1);
+
+        let err = parse_config("a = 1\na = 2").unwrap_err();
+        assert_eq!(err.to_string(), "line 2: duplicate key `a`");
+    }
+}
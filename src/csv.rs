@@ -0,0 +1,66 @@
+use std::{
+    borrow::Cow,
+    io::{self, Write},
+};
+
+use crate::Playlist;
+
+static CSV_HEADER: &str = "path,title,duration_seconds,format,size_bytes";
+
+// Quotes a field if it contains a separator, quote or line break, quotes are escaped by doubling
+// them.
+fn csv_field(field: &str) -> Cow<'_, str> {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\"")).into()
+    } else {
+        field.into()
+    }
+}
+
+/// Writes the tracks of the playlist as CSV, one row per track in the order they were scanned.
+/// The directory tree isn't part of the output.
+pub fn into_csv<W: Write>(writer: &mut W, playlist: Playlist) -> io::Result<()> {
+    writeln!(writer, "{}", CSV_HEADER)?;
+    for track in playlist.tracks() {
+        writeln!(
+            writer,
+            "{},{},{},{},{}",
+            csv_field(&track.location().to_string_lossy()),
+            csv_field(track.title()),
+            track.duration() / 1000,
+            csv_field(track.format().unwrap_or_default()),
+            track
+                .size()
+                .map(|size| size.to_string())
+                .unwrap_or_default()
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PlaylistBuilder;
+
+    #[test]
+    fn fields_are_quoted() {
+        assert_eq!(csv_field("plain title"), "plain title");
+        assert_eq!(csv_field("a, b"), "\"a, b\"");
+        assert_eq!(csv_field("12\" single"), "\"12\"\" single\"");
+        assert_eq!(csv_field("line\r\nbreak"), "\"line\r\nbreak\"");
+
+        let mut builder = PlaylistBuilder::new();
+        builder.add_track("/media/a,b.mkv", "Say \"hi\",\nthen leave", 90_500);
+        let mut csv = vec![];
+        into_csv(&mut csv, builder.build()).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            format!(
+                "{}\n\"/media/a,b.mkv\",\"Say \"\"hi\"\",\nthen leave\",90,,\n",
+                CSV_HEADER
+            )
+        );
+    }
+}
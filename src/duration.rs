@@ -0,0 +1,23 @@
+/// Formats a duration in milliseconds as `MM:SS`, or `HH:MM:SS` if it's an hour or longer.
+/// Partial seconds are dropped.
+///
+/// # Example
+///
+/// ```
+/// use vlc_playlist_generator::format_duration;
+///
+/// assert_eq!(format_duration(0), "00:00");
+/// assert_eq!(format_duration(999), "00:00");
+/// assert_eq!(format_duration(61_500), "01:01");
+/// assert_eq!(format_duration(3_599_999), "59:59");
+/// assert_eq!(format_duration(3_600_000), "01:00:00");
+/// assert_eq!(format_duration(360_000_000), "100:00:00");
+/// ```
+pub fn format_duration(millis: u64) -> String {
+    let secs = millis / 1000;
+    if secs >= 3600 {
+        format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+    } else {
+        format!("{:02}:{:02}", secs / 60, secs % 60)
+    }
+}
@@ -0,0 +1,133 @@
+use std::{
+    error::Error,
+    fmt, io,
+    path::{Path, PathBuf},
+};
+
+/// Errors that can occur while generating a playlist.
+#[derive(Debug)]
+pub enum PlaylistError {
+    /// The file with roots can't be read.
+    ReadRoots(PathBuf, io::Error),
+    /// A file with ignore patterns can't be read.
+    ReadIgnoreFile(PathBuf, io::Error),
+    /// The playlist to merge with can't be read.
+    ReadMerge(PathBuf, io::Error),
+    /// The playlist to check can't be read.
+    ReadCheck(PathBuf, io::Error),
+    /// The metadata cache can't be written.
+    WriteCache(PathBuf, io::Error),
+    /// The output file can't be created.
+    CreateOutput(PathBuf, io::Error),
+    /// The output file can't be replaced with the written playlist.
+    ReplaceOutput(PathBuf, io::Error),
+    /// The playlist can't be written.
+    Write(io::Error),
+}
+
+impl fmt::Display for PlaylistError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlaylistError::ReadRoots(path, _) => {
+                write!(f, "roots cannot be read from {}", path.display())
+            }
+            PlaylistError::ReadIgnoreFile(path, _) => {
+                write!(f, "ignore patterns cannot be read from {}", path.display())
+            }
+            PlaylistError::ReadMerge(path, _) | PlaylistError::ReadCheck(path, _) => {
+                write!(f, "playlist {} cannot be read", path.display())
+            }
+            PlaylistError::WriteCache(path, _) => {
+                write!(f, "metadata cache {} cannot be written", path.display())
+            }
+            PlaylistError::CreateOutput(path, _) => {
+                write!(f, "playlist file {} cannot be created", path.display())
+            }
+            PlaylistError::ReplaceOutput(path, _) => {
+                write!(f, "playlist file {} cannot be replaced", path.display())
+            }
+            PlaylistError::Write(_) => write!(f, "playlist cannot be written"),
+        }
+    }
+}
+
+impl Error for PlaylistError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PlaylistError::ReadRoots(_, e)
+            | PlaylistError::ReadIgnoreFile(_, e)
+            | PlaylistError::ReadMerge(_, e)
+            | PlaylistError::ReadCheck(_, e)
+            | PlaylistError::WriteCache(_, e)
+            | PlaylistError::CreateOutput(_, e)
+            | PlaylistError::ReplaceOutput(_, e)
+            | PlaylistError::Write(e) => Some(e),
+        }
+    }
+}
+
+impl From<io::Error> for PlaylistError {
+    fn from(e: io::Error) -> Self {
+        PlaylistError::Write(e)
+    }
+}
+
+/// Why a file or directory that was found while scanning couldn't be scanned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScanErrorKind {
+    /// The file system returned an error, e.g. because a directory can't be read.
+    Io(io::ErrorKind),
+    /// The file is a symbolic link whose target doesn't exist.
+    BrokenLink,
+    /// The directory is a symbolic link to one of the directories it's in.
+    LinkLoop,
+    /// The media file has a supported extension but its metadata can't be read, e.g. because
+    /// it's damaged.
+    Metadata,
+    /// Reading the metadata took longer than the file timeout of the scan options.
+    Timeout,
+}
+
+impl fmt::Display for ScanErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScanErrorKind::Io(kind) => write!(f, "{}", kind),
+            ScanErrorKind::BrokenLink => write!(f, "broken symbolic link"),
+            ScanErrorKind::LinkLoop => write!(f, "loop of symbolic links"),
+            ScanErrorKind::Metadata => write!(f, "metadata can't be read"),
+            ScanErrorKind::Timeout => write!(f, "reading metadata took too long"),
+        }
+    }
+}
+
+/// A file or directory that was skipped because it couldn't be scanned.
+#[derive(Debug, Clone)]
+pub struct ScanError {
+    path: PathBuf,
+    kind: ScanErrorKind,
+}
+
+impl ScanError {
+    pub(crate) fn new(path: &Path, kind: ScanErrorKind) -> Self {
+        ScanError {
+            path: path.to_path_buf(),
+            kind,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn kind(&self) -> ScanErrorKind {
+        self.kind
+    }
+}
+
+impl fmt::Display for ScanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.kind)
+    }
+}
+
+impl Error for ScanError {}
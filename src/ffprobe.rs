@@ -0,0 +1,79 @@
+use std::{
+    collections::HashMap,
+    path::Path,
+    process::{Command, Stdio},
+    sync::OnceLock,
+};
+
+use serde::Deserialize;
+
+use crate::{fallback_title, Track};
+
+#[derive(Deserialize)]
+struct Probe {
+    format: ProbeFormat,
+}
+
+#[derive(Deserialize)]
+struct ProbeFormat {
+    duration: Option<String>,
+    #[serde(default)]
+    tags: HashMap<String, String>,
+}
+
+// Whether ffprobe can be run at all, checked once per run.
+pub(crate) fn ffprobe_available() -> bool {
+    static AVAILABLE: OnceLock<bool> = OnceLock::new();
+    *AVAILABLE.get_or_init(|| {
+        Command::new("ffprobe")
+            .arg("-version")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success())
+    })
+}
+
+pub(crate) fn ffprobe_meta<P: AsRef<Path>>(path: P) -> Option<Track> {
+    let path = path.as_ref();
+    let output = Command::new("ffprobe")
+        .args(["-v", "quiet", "-show_format", "-of", "json"])
+        .arg(path)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+
+    if !output.status.success() {
+        return None;
+    }
+
+    let probe: Probe = serde_json::from_slice(&output.stdout).ok()?;
+    let duration = probe
+        .format
+        .duration
+        .and_then(|d| d.parse::<f64>().ok())
+        .map(|secs| (secs * 1000.0) as u64);
+    let mut tags = probe.format.tags;
+    let mut tag = |names: &[&str]| {
+        names.iter().find_map(|name| {
+            let key = tags
+                .keys()
+                .find(|key| key.eq_ignore_ascii_case(name))?
+                .clone();
+            tags.remove(&key)
+        })
+    };
+    let title = tag(&["title"]);
+    let creator = tag(&["artist"]);
+    let annotation = tag(&["comment", "description"]);
+
+    let title = title.unwrap_or_else(|| fallback_title(path));
+    let track = Track {
+        creator,
+        annotation,
+        ..Track::new(path.to_path_buf(), title, duration.unwrap_or(0))
+    };
+
+    Some(track)
+}
@@ -0,0 +1,152 @@
+use std::{
+    fs::{self, ReadDir},
+    io,
+    path::{Component, Path, PathBuf},
+};
+
+fn is_pattern(component: &str) -> bool {
+    component.contains(['*', '?', '['])
+}
+
+// Matches a `[...]` character class at the start of `pattern`, returns whether `c` matches and
+// the remainder of the pattern. An unclosed class is treated as a literal `[`.
+fn match_class(pattern: &[char], c: char) -> Option<(bool, &[char])> {
+    let mut idx = 1;
+    let negated = matches!(pattern.get(idx), Some('!' | '^'));
+    if negated {
+        idx += 1;
+    }
+
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let start = *pattern.get(idx)?;
+        if start == ']' && !first {
+            break;
+        }
+
+        if pattern.get(idx + 1) == Some(&'-') && pattern.get(idx + 2).is_some_and(|&e| e != ']') {
+            let end = pattern[idx + 2];
+            matched |= start <= c && c <= end;
+            idx += 3;
+        } else {
+            matched |= start == c;
+            idx += 1;
+        }
+
+        first = false;
+    }
+
+    Some((matched != negated, &pattern[idx + 1..]))
+}
+
+pub(crate) fn matches(pattern: &[char], name: &[char]) -> bool {
+    match pattern.first() {
+        None => name.is_empty(),
+        Some('*') => (0..=name.len()).any(|skip| matches(&pattern[1..], &name[skip..])),
+        Some('?') => !name.is_empty() && matches(&pattern[1..], &name[1..]),
+        Some('[') => {
+            let Some(&c) = name.first() else {
+                return false;
+            };
+
+            match match_class(pattern, c) {
+                Some((matched, rest)) => matched && matches(rest, &name[1..]),
+                None => c == '[' && matches(&pattern[1..], &name[1..]),
+            }
+        }
+        Some(&p) => name.first() == Some(&p) && matches(&pattern[1..], &name[1..]),
+    }
+}
+
+// Relative patterns start out in an empty path, which is read as the current directory.
+fn read_dir(dir: &Path) -> io::Result<ReadDir> {
+    if dir.as_os_str().is_empty() {
+        fs::read_dir(".")
+    } else {
+        fs::read_dir(dir)
+    }
+}
+
+fn matching_entries(dir: &Path, pattern: &str) -> Vec<PathBuf> {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let Ok(entries) = read_dir(dir) else {
+        return vec![];
+    };
+
+    entries
+        .filter_map(Result::ok)
+        .filter(|entry| {
+            let name = entry.file_name();
+            let Some(name) = name.to_str() else {
+                return false;
+            };
+
+            // Hidden entries are only matched by patterns that start with a dot
+            if name.starts_with('.') && pattern.first() != Some(&'.') {
+                return false;
+            }
+
+            matches(&pattern, &name.chars().collect::<Vec<_>>())
+        })
+        .map(|entry| dir.join(entry.file_name()))
+        .collect()
+}
+
+// Collects `dir` and the directories below it, with an explicit stack of the directories left to
+// read so deeply nested directories can't overflow the call stack.
+fn recursive_dirs(dir: &Path, dirs: &mut Vec<PathBuf>) {
+    let mut stack = vec![dir.to_path_buf()];
+    while let Some(dir) = stack.pop() {
+        if let Ok(entries) = read_dir(&dir) {
+            for entry in entries.filter_map(Result::ok) {
+                let hidden = entry.file_name().to_string_lossy().starts_with('.');
+                if !hidden && entry.file_type().is_ok_and(|t| t.is_dir()) {
+                    stack.push(dir.join(entry.file_name()));
+                }
+            }
+        }
+        dirs.push(dir);
+    }
+}
+
+/// Expands a glob pattern into the paths that match it. `*` and `?` match any sequence of
+/// characters or a single character in a file name, `[...]` matches a set of characters, and `**`
+/// matches any number of nested directories. Paths without any of these are returned unchanged,
+/// whether they exist or not. The matches are sorted, a pattern that matches nothing expands to
+/// no paths at all.
+pub fn expand_glob(pattern: &Path) -> Vec<PathBuf> {
+    let components: Vec<Component> = pattern.components().collect();
+    let is_glob = components
+        .iter()
+        .any(|c| c.as_os_str().to_str().is_some_and(is_pattern));
+    if !is_glob {
+        return vec![pattern.to_path_buf()];
+    }
+
+    let mut paths = vec![PathBuf::new()];
+    for component in components {
+        let Some(part) = component.as_os_str().to_str().filter(|p| is_pattern(p)) else {
+            for path in paths.iter_mut() {
+                path.push(component);
+            }
+            continue;
+        };
+
+        let mut next = vec![];
+        for path in &paths {
+            if part == "**" {
+                recursive_dirs(path, &mut next);
+            } else {
+                next.extend(matching_entries(path, part));
+            }
+        }
+
+        paths = next;
+    }
+
+    paths.sort();
+    paths.dedup();
+    paths.retain(|path| path.exists());
+    paths
+}
@@ -0,0 +1,143 @@
+use std::io::{self, Write};
+
+use crate::{
+    format_duration,
+    xml::{archive_uri, file_uri},
+    Playlist, PlaylistNode,
+};
+
+static HTML_HEADER: &str = "<!DOCTYPE html>";
+static STYLESHEET: &str = "body { font-family: sans-serif; margin: 2em; }
+ul { list-style: none; padding-left: 1.5em; }
+li { margin: 0.2em 0; }
+.dir > span { font-weight: bold; }
+.duration { color: #777; margin-left: 0.5em; font-variant-numeric: tabular-nums; }
+a { color: inherit; text-decoration: none; }
+a:hover { text-decoration: underline; }";
+
+// Directories are written with an explicit stack of the nodes left in each open list, so deeply
+// nested directories can't overflow the call stack.
+fn nodes_into_html<W: Write>(
+    writer: &mut W,
+    playlist: &Playlist,
+    nodes: &[PlaylistNode],
+    indent: usize,
+) -> io::Result<()> {
+    writeln!(writer, "{}<ul>", "\t".repeat(indent))?;
+    let mut stack = vec![nodes.iter()];
+    while !stack.is_empty() {
+        // Each nested list is indented by two tabs more than the list it's in
+        let list_indent = indent + 2 * (stack.len() - 1);
+        let remaining = stack.last_mut().unwrap();
+        let Some(node) = remaining.next() else {
+            stack.pop();
+            writeln!(writer, "{}</ul>", "\t".repeat(list_indent))?;
+            if !stack.is_empty() {
+                writeln!(writer, "{}</li>", "\t".repeat(list_indent - 1))?;
+            }
+            continue;
+        };
+
+        let tabs = "\t".repeat(list_indent + 1);
+        match node {
+            PlaylistNode::File(idx, _) => {
+                let track = playlist.track(*idx);
+                let uri = match track.archive() {
+                    Some(archive) => archive_uri(archive, track.location()),
+                    None => file_uri(track.location()),
+                };
+                write!(
+                    writer,
+                    "{}<li><a href=\"{}\">{}</a>",
+                    tabs,
+                    html_escape::encode_double_quoted_attribute(&uri),
+                    html_escape::encode_text(track.title())
+                )?;
+                // An unknown duration is left out rather than shown as 0:00
+                if track.duration() > 0 {
+                    write!(
+                        writer,
+                        "<span class=\"duration\">{}</span>",
+                        format_duration(track.duration())
+                    )?;
+                }
+                writeln!(writer, "</li>")?;
+            }
+            PlaylistNode::Dir {
+                ref title,
+                ref nodes,
+                ..
+            } => {
+                writeln!(
+                    writer,
+                    "{}<li class=\"dir\"><span>{}</span>",
+                    tabs,
+                    html_escape::encode_text(title)
+                )?;
+                writeln!(writer, "{}<ul>", "\t".repeat(list_indent + 2))?;
+                stack.push(nodes.iter());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes the playlist as a static HTML page, the directory tree is written as nested lists and
+/// every file links to its location.
+pub fn into_html<W: Write>(writer: &mut W, playlist: Playlist, title: &str) -> io::Result<()> {
+    let title = html_escape::encode_text(title);
+    writeln!(writer, "{}", HTML_HEADER)?;
+    writeln!(writer, "<html>")?;
+    writeln!(writer, "<head>")?;
+    writeln!(writer, "\t<meta charset=\"utf-8\">")?;
+    writeln!(writer, "\t<title>{}</title>", title)?;
+    writeln!(writer, "\t<style>\n{}\n\t</style>", STYLESHEET)?;
+    writeln!(writer, "</head>")?;
+    writeln!(writer, "<body>")?;
+    writeln!(writer, "\t<h1>{}</h1>", title)?;
+    nodes_into_html(writer, &playlist, playlist.nodes(), 1)?;
+    writeln!(writer, "</body>")?;
+    writeln!(writer, "</html>")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PlaylistBuilder;
+
+    #[test]
+    fn directories_are_nested_lists() {
+        let mut builder = PlaylistBuilder::new();
+        builder
+            .add_directory("A & B")
+            .add_directory("E")
+            .end_directory()
+            .add_track("/media/a b.mkv", "<a>", 90_000)
+            .end_directory()
+            .add_track("/media/b.mkv", "b", 0);
+
+        let mut html = vec![];
+        into_html(&mut html, builder.build(), "Films & more").unwrap();
+        let html = String::from_utf8(html).unwrap();
+        assert!(html.contains("\t<title>Films &amp; more</title>\n"));
+        let body = "<body>
+\t<h1>Films &amp; more</h1>
+\t<ul>
+\t\t<li class=\"dir\"><span>A &amp; B</span>
+\t\t\t<ul>
+\t\t\t\t<li class=\"dir\"><span>E</span>
+\t\t\t\t\t<ul>
+\t\t\t\t\t</ul>
+\t\t\t\t</li>
+\t\t\t\t<li><a href=\"file:///media/a%20b.mkv\">&lt;a&gt;</a><span class=\"duration\">01:30</span></li>
+\t\t\t</ul>
+\t\t</li>
+\t\t<li><a href=\"file:///media/b.mkv\">b</a></li>
+\t</ul>
+</body>
+</html>
+";
+        assert!(html.ends_with(body), "{}", html);
+    }
+}
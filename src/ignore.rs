@@ -0,0 +1,124 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::glob::matches;
+
+// Name of the files with ignore patterns for the directory they're in and its subdirectories.
+pub(crate) static IGNORE_FILE: &str = ".ignore";
+// Directories that contain a file with this name are skipped entirely.
+pub(crate) static NOMEDIA_FILE: &str = ".nomedia";
+
+struct IgnorePattern {
+    segments: Vec<Vec<char>>,
+    // Patterns without a slash match the name of an entry at any depth
+    anchored: bool,
+    dir_only: bool,
+    negated: bool,
+}
+
+impl IgnorePattern {
+    fn parse(line: &str) -> Option<Self> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line.strip_prefix('\\').unwrap_or(line)),
+        };
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let anchored = line.contains('/');
+        let line = line.strip_prefix('/').unwrap_or(line);
+        if line.is_empty() {
+            return None;
+        }
+
+        Some(IgnorePattern {
+            segments: line.split('/').map(|s| s.chars().collect()).collect(),
+            anchored,
+            dir_only,
+            negated,
+        })
+    }
+
+    fn matches(&self, relative: &[Vec<char>], is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+
+        if self.anchored {
+            match_segments(&self.segments, relative)
+        } else {
+            relative
+                .last()
+                .is_some_and(|name| matches(&self.segments[0], name))
+        }
+    }
+}
+
+// Matches the segments of a path against those of a pattern, where a `**` segment matches any
+// number of segments.
+fn match_segments(pattern: &[Vec<char>], path: &[Vec<char>]) -> bool {
+    match pattern.first() {
+        None => path.is_empty(),
+        Some(segment) if *segment == ['*', '*'] => {
+            (0..=path.len()).any(|skip| match_segments(&pattern[1..], &path[skip..]))
+        }
+        Some(segment) => {
+            !path.is_empty()
+                && matches(segment, &path[0])
+                && match_segments(&pattern[1..], &path[1..])
+        }
+    }
+}
+
+// A set of gitignore-style patterns relative to a base directory.
+pub(crate) struct IgnoreRules {
+    base: PathBuf,
+    patterns: Vec<IgnorePattern>,
+}
+
+impl IgnoreRules {
+    pub(crate) fn new<S: AsRef<str>>(base: &Path, lines: &[S]) -> Self {
+        IgnoreRules {
+            base: base.to_path_buf(),
+            patterns: lines
+                .iter()
+                .filter_map(|line| IgnorePattern::parse(line.as_ref()))
+                .collect(),
+        }
+    }
+
+    // Reads the ignore file in `dir`, `None` if there is none or it can't be read.
+    pub(crate) fn read(dir: &Path) -> Option<Self> {
+        let contents = fs::read_to_string(dir.join(IGNORE_FILE)).ok()?;
+        let lines: Vec<_> = contents.lines().collect();
+        Some(IgnoreRules::new(dir, &lines))
+    }
+
+    // Whether `path` is ignored by the last pattern that matches it, `Some(false)` if that pattern
+    // is negated and `None` if no pattern matches.
+    pub(crate) fn ignores(&self, path: &Path, is_dir: bool) -> Option<bool> {
+        let relative: Vec<Vec<char>> = path
+            .strip_prefix(&self.base)
+            .ok()?
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy().chars().collect())
+            .collect();
+        if relative.is_empty() {
+            return None;
+        }
+
+        self.patterns
+            .iter()
+            .rev()
+            .find(|pattern| pattern.matches(&relative, is_dir))
+            .map(|pattern| !pattern.negated)
+    }
+}
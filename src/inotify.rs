@@ -0,0 +1,171 @@
+use std::{
+    collections::HashMap,
+    ffi::{CString, OsStr},
+    io,
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd},
+        unix::ffi::OsStrExt,
+    },
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+// Everything that changes the entries of a directory or the contents of a file in it
+static WATCH_MASK: u32 = libc::IN_ATTRIB
+    | libc::IN_CLOSE_WRITE
+    | libc::IN_CREATE
+    | libc::IN_DELETE
+    | libc::IN_DELETE_SELF
+    | libc::IN_MODIFY
+    | libc::IN_MOVE_SELF
+    | libc::IN_MOVED_FROM
+    | libc::IN_MOVED_TO;
+// The size of an event without its name
+static EVENT_HEADER_SIZE: usize = 16;
+
+// File system events of the watched directories, reported by inotify.
+pub(crate) struct Watcher {
+    fd: OwnedFd,
+    dirs: HashMap<i32, PathBuf>,
+}
+
+impl Watcher {
+    pub(crate) fn new() -> io::Result<Self> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC | libc::IN_NONBLOCK) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Watcher {
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+            dirs: HashMap::new(),
+        })
+    }
+
+    // Watches the entries of a directory, or a single file. Watching a path again has no effect.
+    // Fails if the limit on the number of watches is reached.
+    pub(crate) fn add(&mut self, path: &Path) -> io::Result<()> {
+        let c_path = CString::new(path.as_os_str().as_bytes())?;
+        let wd =
+            unsafe { libc::inotify_add_watch(self.fd.as_raw_fd(), c_path.as_ptr(), WATCH_MASK) };
+        if wd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        self.dirs.insert(wd, path.to_path_buf());
+        Ok(())
+    }
+
+    // Waits for events for at most `timeout`, or until one arrives without it. Returns whether any
+    // of the events was about a path for which `relevant` holds, all pending events are consumed.
+    pub(crate) fn wait(
+        &mut self,
+        timeout: Option<Duration>,
+        relevant: impl Fn(&Path) -> bool,
+    ) -> io::Result<bool> {
+        let mut pollfd = libc::pollfd {
+            fd: self.fd.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let timeout = timeout.map_or(-1, |timeout| {
+            timeout.as_millis().min(i32::MAX as u128) as i32
+        });
+        let ready = unsafe { libc::poll(&mut pollfd, 1, timeout) };
+        if ready < 0 {
+            let e = io::Error::last_os_error();
+            return match e.kind() {
+                io::ErrorKind::Interrupted => Ok(false),
+                _ => Err(e),
+            };
+        }
+
+        let mut found = false;
+        let mut buffer = vec![0u8; 64 * 1024];
+        loop {
+            let len = unsafe {
+                libc::read(
+                    self.fd.as_raw_fd(),
+                    buffer.as_mut_ptr().cast(),
+                    buffer.len(),
+                )
+            };
+            if len < 0 {
+                let e = io::Error::last_os_error();
+                if e.kind() == io::ErrorKind::WouldBlock {
+                    return Ok(found);
+                }
+                return Err(e);
+            }
+            if len == 0 {
+                return Ok(found);
+            }
+
+            let mut events = &buffer[..len as usize];
+            while events.len() >= EVENT_HEADER_SIZE {
+                let field = |offset: usize| events[offset..offset + 4].try_into().unwrap();
+                let wd = i32::from_ne_bytes(field(0));
+                let mask = u32::from_ne_bytes(field(4));
+                let name_len = u32::from_ne_bytes(field(12)) as usize;
+                let name = events
+                    .get(EVENT_HEADER_SIZE..EVENT_HEADER_SIZE + name_len)
+                    .unwrap_or_default();
+                let name = &name[..name.iter().position(|&b| b == 0).unwrap_or(name.len())];
+                events = events
+                    .get(EVENT_HEADER_SIZE + name_len..)
+                    .unwrap_or_default();
+
+                // A full queue loses events, so anything may have changed
+                if mask & libc::IN_Q_OVERFLOW != 0 {
+                    found = true;
+                    continue;
+                }
+                if mask & libc::IN_IGNORED != 0 {
+                    self.dirs.remove(&wd);
+                }
+                let Some(dir) = self.dirs.get(&wd) else {
+                    continue;
+                };
+                let path = match name {
+                    [] => dir.clone(),
+                    name => dir.join(OsStr::from_bytes(name)),
+                };
+                found |= relevant(&path);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, process};
+
+    #[test]
+    fn changes_are_reported() {
+        let dir =
+            std::env::temp_dir().join(format!("vlc-playlist-generator-inotify-{}", process::id()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        let mut watcher = Watcher::new().unwrap();
+        watcher.add(&dir).unwrap();
+        watcher.add(&dir.join("sub")).unwrap();
+        let timeout = Some(Duration::from_secs(5));
+        let quiet = Some(Duration::from_millis(10));
+
+        fs::write(dir.join("sub/a.mkv"), b"a").unwrap();
+        assert!(watcher
+            .wait(timeout, |path| path == dir.join("sub/a.mkv"))
+            .unwrap());
+        assert!(!watcher.wait(quiet, |_| true).unwrap());
+
+        // Events of paths that aren't relevant are consumed too
+        fs::write(dir.join("b.xspf"), b"b").unwrap();
+        assert!(!watcher
+            .wait(timeout, |path| !path.ends_with("b.xspf"))
+            .unwrap());
+        assert!(!watcher.wait(quiet, |_| true).unwrap());
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(watcher.wait(timeout, |_| true).unwrap());
+    }
+}
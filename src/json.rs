@@ -0,0 +1,191 @@
+use std::{
+    borrow::Cow,
+    io::{self, Write},
+};
+
+use serde::Serialize;
+
+use crate::{Chapter, Playlist, PlaylistNode, Track};
+
+#[derive(Serialize)]
+struct JsonTrack<'a> {
+    location: Cow<'a, str>,
+    title: &'a str,
+    duration: u64,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    subtitles: Vec<Cow<'a, str>>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    chapters: &'a [Chapter],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    start_time: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stop_time: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    image: Option<Cow<'a, str>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    format: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    creator: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    annotation: Option<&'a str>,
+}
+
+impl<'a> From<&'a Track> for JsonTrack<'a> {
+    fn from(track: &'a Track) -> Self {
+        JsonTrack {
+            location: track.location().to_string_lossy(),
+            title: track.title(),
+            duration: track.duration(),
+            subtitles: track
+                .subtitles()
+                .iter()
+                .map(|sub| sub.to_string_lossy())
+                .collect(),
+            chapters: track.chapters(),
+            start_time: track.start_time(),
+            stop_time: track.stop_time(),
+            image: track.image().map(|image| image.to_string_lossy()),
+            format: track.format(),
+            size: track.size(),
+            creator: track.creator(),
+            annotation: track.annotation(),
+        }
+    }
+}
+
+// Indentation of serde_json's pretty printer
+static INDENT: &str = "  ";
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum JsonNode<'a> {
+    File { track: usize, name: Cow<'a, str> },
+}
+
+// Writes a value that's been pretty printed on its own at `level` levels of indentation.
+fn write_indented<W: Write>(writer: &mut W, json: &str, level: usize) -> io::Result<()> {
+    let indent = format!("\n{}", INDENT.repeat(level));
+    write!(writer, "{}", json.replace('\n', &indent))
+}
+
+// Writes the node tree as an array in the layout of serde_json's pretty printer. Directories are
+// written with an explicit stack of the nodes left in each open directory instead of serializing
+// nested values, so deeply nested directories can't overflow the call stack. The array is at
+// level 1, in the playlist object.
+fn nodes_into_json<W: Write>(writer: &mut W, nodes: &[PlaylistNode]) -> io::Result<()> {
+    if nodes.is_empty() {
+        return write!(writer, "[]");
+    }
+
+    write!(writer, "[")?;
+    let mut stack = vec![(nodes.iter(), true)];
+    loop {
+        // The nodes of each array are two levels below the nodes of its parent
+        let level = 2 * stack.len();
+        let Some((remaining, first)) = stack.last_mut() else {
+            return Ok(());
+        };
+        let Some(node) = remaining.next() else {
+            stack.pop();
+            write!(writer, "\n{}]", INDENT.repeat(level - 1))?;
+            if !stack.is_empty() {
+                write!(writer, "\n{}}}", INDENT.repeat(level - 2))?;
+            }
+            continue;
+        };
+
+        write!(
+            writer,
+            "{}\n{}",
+            if *first { "" } else { "," },
+            INDENT.repeat(level)
+        )?;
+        *first = false;
+        match node {
+            PlaylistNode::Dir {
+                ref title,
+                ref nodes,
+                ..
+            } => {
+                let field_indent = INDENT.repeat(level + 1);
+                write!(writer, "{{\n{}\"type\": \"dir\",", field_indent)?;
+                write!(writer, "\n{}\"title\": ", field_indent)?;
+                serde_json::to_writer(&mut *writer, title)?;
+                write!(writer, ",\n{}\"nodes\": [", field_indent)?;
+                if nodes.is_empty() {
+                    write!(writer, "]\n{}}}", INDENT.repeat(level))?;
+                } else {
+                    stack.push((nodes.iter(), true));
+                }
+            }
+            PlaylistNode::File(idx, ref name) => {
+                let file = JsonNode::File {
+                    track: *idx,
+                    name: name.to_string_lossy(),
+                };
+                write_indented(writer, &serde_json::to_string_pretty(&file)?, level)?;
+            }
+        }
+    }
+}
+
+pub fn into_json<W: Write>(writer: &mut W, playlist: Playlist) -> io::Result<()> {
+    let tracks: Vec<JsonTrack> = playlist.tracks().map(JsonTrack::from).collect();
+
+    write!(writer, "{{\n{}\"tracks\": ", INDENT)?;
+    write_indented(writer, &serde_json::to_string_pretty(&tracks)?, 1)?;
+    write!(writer, ",\n{}\"nodes\": ", INDENT)?;
+    nodes_into_json(writer, playlist.nodes())?;
+    writeln!(writer, "\n}}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PlaylistBuilder;
+
+    #[test]
+    fn nodes_are_pretty_printed() {
+        let mut builder = PlaylistBuilder::new();
+        builder
+            .add_directory("A")
+            .add_directory("E")
+            .end_directory()
+            .add_track("/a.mkv", "a", 0)
+            .end_directory()
+            .add_track("/b.mkv", "b", 0);
+
+        let mut json = vec![];
+        into_json(&mut json, builder.build()).unwrap();
+        let json = String::from_utf8(json).unwrap();
+        let nodes = r#"  "nodes": [
+    {
+      "type": "dir",
+      "title": "A",
+      "nodes": [
+        {
+          "type": "dir",
+          "title": "E",
+          "nodes": []
+        },
+        {
+          "type": "file",
+          "track": 0,
+          "name": "a.mkv"
+        }
+      ]
+    },
+    {
+      "type": "file",
+      "track": 1,
+      "name": "b.mkv"
+    }
+  ]
+}
+"#;
+        assert!(json.ends_with(nodes), "{}", json);
+        serde_json::from_str::<serde_json::Value>(&json).unwrap();
+    }
+}
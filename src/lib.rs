@@ -0,0 +1,2262 @@
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    ffi::{OsStr, OsString},
+    fs::{self, File},
+    io,
+    io::BufReader,
+    iter, mem,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    slice,
+    sync::{
+        atomic::{self, AtomicUsize},
+        mpsc,
+    },
+    thread,
+    time::{Duration, SystemTime},
+    vec,
+};
+
+use matroska::{ChapterEdition, Matroska, Tag, TagValue};
+use mp4::{Metadata, Mp4Reader};
+use serde::{Deserialize, Serialize};
+use walkdir::{DirEntry, WalkDir};
+
+use crate::{
+    audio::audio_meta,
+    avi::avi_meta,
+    cache::FileStamp,
+    ffprobe::{ffprobe_available, ffprobe_meta},
+    ignore::{IgnoreRules, NOMEDIA_FILE},
+    log::{verbose, warning},
+    mkv::cluster_duration,
+    nfo::{nfo_title, MOVIE_NFO, NFO_EXTENSION},
+    progress::Progress,
+    sidecar::{read_time_options, sidecar_path, OPTIONS_EXTENSION},
+    ts::ts_meta,
+    udta::udta_title,
+    zip::zip_entries,
+};
+pub use crate::{
+    builder::PlaylistBuilder,
+    cache::MetadataCache,
+    csv::into_csv,
+    duration::format_duration,
+    error::{PlaylistError, ScanError, ScanErrorKind},
+    glob::expand_glob,
+    html::into_html,
+    json::into_json,
+    log::{set_verbosity, Verbosity},
+    m3u::into_m3u,
+    pls::into_pls,
+    regex::Regex,
+    title::clean_title,
+    xml::{into_xml, DurationUnit, XmlOptions},
+    xml_reader::parse_xml,
+};
+
+mod audio;
+mod avi;
+mod builder;
+mod cache;
+mod csv;
+mod duration;
+mod error;
+mod ffprobe;
+mod glob;
+mod html;
+mod ignore;
+mod json;
+mod log;
+mod m3u;
+mod mkv;
+mod nfo;
+mod pls;
+mod progress;
+mod regex;
+mod sidecar;
+#[cfg(test)]
+mod test_util;
+mod title;
+mod ts;
+mod udta;
+mod xml;
+mod xml_reader;
+mod zip;
+
+/// A media file and the metadata read from it.
+#[derive(Clone)]
+pub struct Track {
+    location: PathBuf,
+    title: String,
+    duration: u64,
+    subtitles: Vec<PathBuf>,
+    chapters: Vec<Chapter>,
+    start_time: Option<u64>,
+    stop_time: Option<u64>,
+    image: Option<PathBuf>,
+    modified: Option<SystemTime>,
+    format: Option<String>,
+    size: Option<u64>,
+    archive: Option<PathBuf>,
+    creator: Option<String>,
+    annotation: Option<String>,
+}
+
+/// A chapter of a track.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Chapter {
+    title: String,
+    start: u64,
+}
+
+impl Chapter {
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    /// Start of the chapter in milliseconds.
+    pub fn start(&self) -> u64 {
+        self.start
+    }
+}
+
+impl Track {
+    // A track without any of the metadata that only some files have.
+    pub(crate) fn new(location: PathBuf, title: String, duration: u64) -> Self {
+        Track {
+            location,
+            title,
+            duration,
+            subtitles: vec![],
+            chapters: vec![],
+            start_time: None,
+            stop_time: None,
+            image: None,
+            modified: None,
+            format: None,
+            size: None,
+            archive: None,
+            creator: None,
+            annotation: None,
+        }
+    }
+
+    pub fn location(&self) -> &Path {
+        &self.location
+    }
+
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    /// Duration in milliseconds, 0 if unknown.
+    pub fn duration(&self) -> u64 {
+        self.duration
+    }
+
+    /// Subtitle files next to the media file, if they were searched for.
+    pub fn subtitles(&self) -> &[PathBuf] {
+        &self.subtitles
+    }
+
+    /// Chapters embedded in the media file, ordered by their start.
+    pub fn chapters(&self) -> &[Chapter] {
+        &self.chapters
+    }
+
+    /// Time in milliseconds at which playback should start, from a `.vlcopts` file next to the
+    /// media file.
+    pub fn start_time(&self) -> Option<u64> {
+        self.start_time
+    }
+
+    /// Time in milliseconds at which playback should stop, from a `.vlcopts` file next to the
+    /// media file.
+    pub fn stop_time(&self) -> Option<u64> {
+        self.stop_time
+    }
+
+    /// Cover art for the media file, if images next to it were searched for.
+    pub fn image(&self) -> Option<&Path> {
+        self.image.as_deref()
+    }
+
+    /// Modification time of the media file, unknown for tracks read from a playlist.
+    pub fn modified(&self) -> Option<SystemTime> {
+        self.modified
+    }
+
+    /// Container format of the media file, its lowercase extension like `mkv` or `mp3`. Unknown
+    /// for tracks read from a playlist or added to a [`PlaylistBuilder`].
+    pub fn format(&self) -> Option<&str> {
+        self.format.as_deref()
+    }
+
+    /// Size of the media file in bytes, unknown for tracks read from a playlist or added to a
+    /// [`PlaylistBuilder`].
+    pub fn size(&self) -> Option<u64> {
+        self.size
+    }
+
+    /// The zip archive that contains the media file, its location is the path inside the
+    /// archive appended to the path of the archive.
+    pub fn archive(&self) -> Option<&Path> {
+        self.archive.as_deref()
+    }
+
+    /// The artist from the tags of the file, written as the creator of the track.
+    pub fn creator(&self) -> Option<&str> {
+        self.creator.as_deref()
+    }
+
+    /// The comment or description from the tags of the file.
+    pub fn annotation(&self) -> Option<&str> {
+        self.annotation.as_deref()
+    }
+}
+
+// The title of a file that has none in its metadata, its file name.
+pub(crate) fn fallback_title(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "<No title available>".into())
+}
+
+/// The tracks of a playlist in the order they were scanned.
+pub struct TrackList {
+    tracks: Vec<Track>,
+}
+
+/// A node in the directory tree of a playlist. Files refer to a track by its index in the track
+/// list. The path of a directory is empty if it's unknown, e.g. in a playlist read from XSPF.
+#[derive(PartialEq, Eq)]
+pub enum PlaylistNode {
+    Dir {
+        title: String,
+        path: PathBuf,
+        nodes: Vec<PlaylistNode>,
+    },
+    File(usize, OsString),
+}
+
+// Compares strings with runs of digits compared by their numeric value, so `Episode 2` sorts before
+// `Episode 10`. Strings that only differ in leading zeros are ordered by plain string comparison.
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a_chars = a.chars().peekable();
+    let mut b_chars = b.chars().peekable();
+
+    loop {
+        match (a_chars.peek(), b_chars.peek()) {
+            (None, None) => return a.cmp(b),
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(ca), Some(cb)) if ca.is_ascii_digit() && cb.is_ascii_digit() => {
+                let mut a_digits = String::new();
+                while let Some(c) = a_chars.next_if(char::is_ascii_digit) {
+                    a_digits.push(c);
+                }
+
+                let mut b_digits = String::new();
+                while let Some(c) = b_chars.next_if(char::is_ascii_digit) {
+                    b_digits.push(c);
+                }
+
+                let a_num = a_digits.trim_start_matches('0');
+                let b_num = b_digits.trim_start_matches('0');
+                let ordering = a_num.len().cmp(&b_num.len()).then_with(|| a_num.cmp(b_num));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+            (Some(ca), Some(cb)) => {
+                let ordering = ca.cmp(cb);
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+
+                a_chars.next();
+                b_chars.next();
+            }
+        }
+    }
+}
+
+impl PartialOrd for PlaylistNode {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// Natural order of file names, names that aren't valid UTF-8 are ordered by their raw bytes if
+// they're otherwise equal.
+fn name_cmp(a: &OsStr, b: &OsStr) -> Ordering {
+    natural_cmp(&a.to_string_lossy(), &b.to_string_lossy()).then_with(|| a.cmp(b))
+}
+
+// Directories sort before files, both in natural order of their title or name. The remaining
+// fields break ties, so the order agrees with `Eq`.
+impl Ord for PlaylistNode {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (PlaylistNode::Dir { .. }, PlaylistNode::File(_, _)) => Ordering::Less,
+            (PlaylistNode::File(_, _), PlaylistNode::Dir { .. }) => Ordering::Greater,
+            (
+                PlaylistNode::Dir {
+                    ref title,
+                    ref path,
+                    ref nodes,
+                },
+                PlaylistNode::Dir {
+                    title: ref title2,
+                    path: ref path2,
+                    nodes: ref nodes2,
+                },
+            ) => natural_cmp(title, title2)
+                .then_with(|| path.cmp(path2))
+                .then_with(|| nodes.cmp(nodes2)),
+            (PlaylistNode::File(idx, p1), PlaylistNode::File(idx2, p2)) => {
+                name_cmp(p1, p2).then_with(|| idx.cmp(idx2))
+            }
+        }
+    }
+}
+
+impl PlaylistNode {
+    // The tree is built with an explicit stack of the directories that are still being built,
+    // so deeply nested directories can't overflow the call stack.
+    fn new(root: &Path, map: &PendingNodeMap) -> Self {
+        let mut stack: Vec<(&Path, &String, slice::Iter<PathBuf>, Vec<PlaylistNode>)> = vec![];
+        let mut path = root;
+
+        loop {
+            let mut built = match map.nodes.get(path).unwrap() {
+                PendingNode::Dir { title, node_paths } => {
+                    let nodes = Vec::with_capacity(node_paths.len());
+                    stack.push((path, title, node_paths.iter(), nodes));
+                    None
+                }
+                PendingNode::File(idx, s) => Some(PlaylistNode::File(*idx, s.clone())),
+            };
+
+            // Completed directories are added to their parent until one has nodes left to build
+            loop {
+                let Some((_, _, node_paths, nodes)) = stack.last_mut() else {
+                    return built.unwrap();
+                };
+                nodes.extend(built.take());
+                if let Some(next) = node_paths.next() {
+                    path = next;
+                    break;
+                }
+
+                let (dir_path, title, _, nodes) = stack.pop().unwrap();
+                built = Some(PlaylistNode::Dir {
+                    title: title.clone(),
+                    path: dir_path.to_path_buf(),
+                    nodes,
+                });
+            }
+        }
+    }
+
+    fn shuffle(&mut self, shuffler: &mut Shuffler) {
+        let mut stack = vec![self];
+        while let Some(node) = stack.pop() {
+            if let Self::Dir { ref mut nodes, .. } = node {
+                shuffler.shuffle(nodes);
+                stack.extend(nodes.iter_mut());
+            }
+        }
+    }
+
+    fn sort_by<F: Fn(&Self, &Self) -> Ordering>(&mut self, compare: &F) {
+        let mut stack = vec![self];
+        while let Some(node) = stack.pop() {
+            if let Self::Dir { ref mut nodes, .. } = node {
+                nodes.sort_by(compare);
+                stack.extend(nodes.iter_mut());
+            }
+        }
+    }
+
+    // Compares nodes of the same kind by `order`, ties are broken by their natural order and then
+    // by the location of the track. Directories always sort before files.
+    fn cmp_by(&self, other: &Self, order: SortOrder, reverse: bool, tracks: &[Track]) -> Ordering {
+        let ordering = match (self, other) {
+            (PlaylistNode::File(idx, name), PlaylistNode::File(idx2, name2)) => {
+                let (track, track2) = (&tracks[*idx], &tracks[*idx2]);
+                match order {
+                    SortOrder::Name => Ordering::Equal,
+                    SortOrder::Title => natural_cmp(&track.title, &track2.title),
+                    SortOrder::Duration => track.duration.cmp(&track2.duration),
+                    SortOrder::Path => track.location.cmp(&track2.location),
+                    SortOrder::Modified => track2.modified.cmp(&track.modified),
+                }
+                .then_with(|| name_cmp(name, name2))
+                .then_with(|| track.location.cmp(&track2.location))
+                .then_with(|| idx.cmp(idx2))
+            }
+            (PlaylistNode::Dir { .. }, PlaylistNode::Dir { .. })
+                if order == SortOrder::Modified =>
+            {
+                other
+                    .newest(tracks)
+                    .cmp(&self.newest(tracks))
+                    .then_with(|| self.cmp(other))
+            }
+            (PlaylistNode::Dir { .. }, PlaylistNode::Dir { .. }) => self.cmp(other),
+            _ => return self.cmp(other),
+        };
+
+        if reverse {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    }
+
+    // The nodes in the subtree of this node in playlist order, starting with the node itself.
+    pub(crate) fn descendants(&self) -> impl Iterator<Item = &PlaylistNode> {
+        let mut stack = vec![self];
+        iter::from_fn(move || {
+            let node = stack.pop()?;
+            if let Self::Dir { ref nodes, .. } = node {
+                stack.extend(nodes.iter().rev());
+            }
+            Some(node)
+        })
+    }
+
+    // The track indices of the files in the subtree of this node in playlist order.
+    pub(crate) fn track_indices(&self) -> impl Iterator<Item = usize> + '_ {
+        self.descendants().filter_map(|node| match node {
+            Self::File(idx, _) => Some(*idx),
+            Self::Dir { .. } => None,
+        })
+    }
+
+    // Calls `f` with every node in the subtree of this node, parents before their children.
+    fn for_each_mut<F: FnMut(&mut PlaylistNode)>(&mut self, mut f: F) {
+        let mut stack = vec![self];
+        while let Some(node) = stack.pop() {
+            f(node);
+            if let Self::Dir { ref mut nodes, .. } = node {
+                stack.extend(nodes.iter_mut());
+            }
+        }
+    }
+
+    fn renumber(&mut self, renumbered: &HashMap<usize, usize>) {
+        self.for_each_mut(|node| {
+            if let Self::File(ref mut idx, _) = node {
+                *idx = renumbered[idx];
+            }
+        });
+    }
+
+    // The modification time of the newest file in the subtree of this node.
+    fn newest(&self, tracks: &[Track]) -> Option<SystemTime> {
+        self.track_indices()
+            .filter_map(|idx| tracks[idx].modified)
+            .max()
+    }
+
+    // Replaces the titles of all directories in the subtree of this node with their cleaned up
+    // version.
+    fn clean_titles(&mut self) {
+        self.for_each_mut(|node| {
+            if let Self::Dir { ref mut title, .. } = node {
+                *title = clean_title(title);
+            }
+        });
+    }
+
+    // Rebuilds the subtree of this node with `f` applied to its files, files it returns `None`
+    // for are removed and so are the directories that are left without files. `None` if this
+    // node is removed itself. Like `new`, the subtree is rebuilt with an explicit stack.
+    fn filter_files<F>(self, mut f: F) -> Option<Self>
+    where
+        F: FnMut(usize, OsString) -> Option<(usize, OsString)>,
+    {
+        let mut stack: Vec<(
+            String,
+            PathBuf,
+            vec::IntoIter<PlaylistNode>,
+            Vec<PlaylistNode>,
+        )> = vec![];
+        let mut node = self;
+
+        loop {
+            let mut built = match node {
+                Self::Dir { title, path, nodes } => {
+                    stack.push((title, path, nodes.into_iter(), vec![]));
+                    None
+                }
+                Self::File(idx, name) => f(idx, name).map(|(idx, name)| Self::File(idx, name)),
+            };
+
+            loop {
+                let Some((_, _, remaining, nodes)) = stack.last_mut() else {
+                    return built;
+                };
+                nodes.extend(built.take());
+                if let Some(next) = remaining.next() {
+                    node = next;
+                    break;
+                }
+
+                let (title, path, _, nodes) = stack.pop().unwrap();
+                built = (!nodes.is_empty()).then_some(Self::Dir { title, path, nodes });
+            }
+        }
+    }
+
+    // Removes all directories without any files from the subtree of this node, `None` if this
+    // node is such a directory itself.
+    fn prune(self) -> Option<Self> {
+        self.filter_files(|idx, name| Some((idx, name)))
+    }
+
+    // Removes the files whose track isn't renumbered and the directories that are left empty,
+    // `None` if this node is removed itself.
+    fn retain_renumbered(self, renumbered: &HashMap<usize, usize>) -> Option<Self> {
+        self.filter_files(|idx, name| Some((*renumbered.get(&idx)?, name)))
+    }
+}
+
+/// A list of tracks and the directory tree they were found in.
+pub struct Playlist {
+    track_list: TrackList,
+    nodes: Vec<PlaylistNode>,
+}
+
+impl Playlist {
+    pub fn tracks(&self) -> impl Iterator<Item = &Track> {
+        self.track_list.tracks.iter()
+    }
+
+    pub fn track(&self, idx: usize) -> &Track {
+        &self.track_list.tracks[idx]
+    }
+
+    pub fn nodes(&self) -> &[PlaylistNode] {
+        &self.nodes
+    }
+
+    /// Splits the playlist into one playlist for each top-level node, i.e. for each root. Every
+    /// playlist only contains the tracks of its node, they're renumbered in their original order.
+    pub fn split(mut self) -> Vec<Playlist> {
+        mem::take(&mut self.nodes)
+            .into_iter()
+            .map(|mut node| {
+                let mut indices: Vec<usize> = node.track_indices().collect();
+                indices.sort_unstable();
+                indices.dedup();
+
+                let renumbered: HashMap<usize, usize> = indices
+                    .iter()
+                    .enumerate()
+                    .map(|(new_idx, &idx)| (idx, new_idx))
+                    .collect();
+                node.renumber(&renumbered);
+
+                let tracks = indices
+                    .iter()
+                    .map(|&idx| self.track_list.tracks[idx].clone())
+                    .collect();
+                Playlist {
+                    track_list: TrackList { tracks },
+                    nodes: vec![node],
+                }
+            })
+            .collect()
+    }
+
+    /// Removes the tracks for which `keep` returns `false`, directories that are left without
+    /// tracks are removed too. The other tracks keep their order.
+    pub fn retain_tracks<F: FnMut(&Track) -> bool>(&mut self, mut keep: F) {
+        let mut renumbered = HashMap::new();
+        let mut tracks = vec![];
+        for (idx, track) in mem::take(&mut self.track_list.tracks)
+            .into_iter()
+            .enumerate()
+        {
+            if keep(&track) {
+                renumbered.insert(idx, tracks.len());
+                tracks.push(track);
+            }
+        }
+
+        self.track_list.tracks = tracks;
+        self.nodes = mem::take(&mut self.nodes)
+            .into_iter()
+            .filter_map(|node| node.retain_renumbered(&renumbered))
+            .collect();
+    }
+
+    /// Adds the tracks of `other` whose location isn't part of this playlist yet. Directories
+    /// with the same title are merged, new tracks are appended to the track list. Directories
+    /// without tracks are removed.
+    pub fn merge(&mut self, mut other: Playlist) {
+        let mut known: HashMap<PathBuf, usize> = self
+            .tracks()
+            .enumerate()
+            .map(|(idx, track)| (track.location.clone(), idx))
+            .collect();
+
+        let mut renumbered = HashMap::new();
+        for (idx, track) in mem::take(&mut other.track_list.tracks)
+            .into_iter()
+            .enumerate()
+        {
+            if known.contains_key(&track.location) {
+                continue;
+            }
+
+            let new_idx = self.track_list.tracks.len();
+            known.insert(track.location.clone(), new_idx);
+            renumbered.insert(idx, new_idx);
+            self.track_list.tracks.push(track);
+        }
+
+        merge_nodes(&mut self.nodes, mem::take(&mut other.nodes), &renumbered);
+    }
+}
+
+// The tree is taken apart with an explicit stack, dropping it recursively can overflow the stack
+// for deeply nested directories.
+impl Drop for Playlist {
+    fn drop(&mut self) {
+        let mut stack = mem::take(&mut self.nodes);
+        while let Some(node) = stack.pop() {
+            if let PlaylistNode::Dir { nodes, .. } = node {
+                stack.extend(nodes);
+            }
+        }
+    }
+}
+
+// Inserts the nodes that refer to a renumbered track into `nodes`, before the first node that
+// sorts after them. Directories are merged into the first directory with the same title, or into
+// a new empty one, with an explicit stack of the directories that are left to merge. Directories
+// that are left without files are removed afterwards.
+fn merge_nodes(
+    nodes: &mut Vec<PlaylistNode>,
+    other: Vec<PlaylistNode>,
+    renumbered: &HashMap<usize, usize>,
+) {
+    let mut stack = vec![(&mut *nodes, other)];
+    while let Some((nodes, other)) = stack.pop() {
+        // The nodes to merge into the directories of this level, by their title
+        let mut dirs: Vec<(String, PathBuf, Vec<PlaylistNode>)> = vec![];
+
+        for node in other {
+            let node = match node {
+                PlaylistNode::File(idx, name) => match renumbered.get(&idx) {
+                    Some(&idx) => PlaylistNode::File(idx, name),
+                    None => continue,
+                },
+                PlaylistNode::Dir {
+                    title,
+                    path,
+                    nodes: other_nodes,
+                } => {
+                    if let Some((_, _, pending)) = dirs.iter_mut().find(|(t, ..)| *t == title) {
+                        pending.extend(other_nodes);
+                        continue;
+                    }
+
+                    let exists = nodes.iter().any(|node| {
+                        matches!(node, PlaylistNode::Dir { title: existing, .. } if *existing == title)
+                    });
+                    dirs.push((title.clone(), path.clone(), other_nodes));
+                    if exists {
+                        continue;
+                    }
+                    PlaylistNode::Dir {
+                        title,
+                        path,
+                        nodes: vec![],
+                    }
+                }
+            };
+
+            let pos = nodes.partition_point(|existing| *existing <= node);
+            nodes.insert(pos, node);
+        }
+
+        for node in nodes.iter_mut() {
+            let PlaylistNode::Dir {
+                title,
+                path: existing_path,
+                nodes,
+            } = node
+            else {
+                continue;
+            };
+            let Some(pos) = dirs.iter().position(|(other, ..)| other == title) else {
+                continue;
+            };
+
+            // Directories read from XSPF don't know their path
+            let (_, path, other_nodes) = dirs.swap_remove(pos);
+            if existing_path.as_os_str().is_empty() {
+                *existing_path = path;
+            }
+            stack.push((nodes, other_nodes));
+        }
+    }
+
+    *nodes = mem::take(nodes)
+        .into_iter()
+        .filter_map(PlaylistNode::prune)
+        .collect();
+}
+
+enum PendingNode {
+    Dir {
+        title: String,
+        node_paths: Vec<PathBuf>,
+    },
+    File(usize, OsString),
+}
+
+// The name of a root, roots like `.` or `..` are named after the directory they refer to. The root
+// of the file system is titled with its path.
+fn root_title(root: &Path) -> String {
+    root.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .or_else(|| {
+            let canonical = root.canonicalize().ok()?;
+            Some(canonical.file_name()?.to_string_lossy().into_owned())
+        })
+        .unwrap_or_else(|| root.display().to_string())
+}
+
+struct PendingNodeMap {
+    nodes: HashMap<PathBuf, PendingNode>,
+    roots: Vec<PathBuf>,
+}
+
+impl PendingNodeMap {
+    fn new(roots: Vec<PathBuf>) -> Self {
+        let mut nodes = HashMap::new();
+
+        // Files that are roots themselves are added to the top level when they're pushed
+        let dirs: Vec<&PathBuf> = roots.iter().filter(|root| !root.is_file()).collect();
+        let titles: Vec<String> = dirs.iter().map(|root| root_title(root)).collect();
+        for (path, title) in dirs.iter().copied().zip(&titles) {
+            // Different roots with the same name are titled with their full path instead
+            let shared = dirs
+                .iter()
+                .copied()
+                .zip(&titles)
+                .any(|(other, other_title)| other != path && other_title == title);
+            let title = if shared {
+                path.display().to_string()
+            } else {
+                title.clone()
+            };
+
+            nodes.insert(
+                path.clone(),
+                PendingNode::Dir {
+                    title,
+                    node_paths: vec![],
+                },
+            );
+        }
+
+        PendingNodeMap { nodes, roots }
+    }
+
+    // Adds `path` to the node of its directory, the directories up to the closest one that has a
+    // node already are added as well.
+    fn node_for_dir_of(&mut self, path: &Path) {
+        let mut child = path;
+        loop {
+            let parent_path = child.parent().unwrap();
+            let known = self.nodes.contains_key(parent_path);
+            let node = self
+                .nodes
+                .entry(parent_path.to_path_buf())
+                .or_insert_with(|| {
+                    let name = parent_path.file_name().unwrap_or(OsStr::new("?"));
+                    PendingNode::Dir {
+                        title: name.to_string_lossy().into(),
+                        node_paths: vec![],
+                    }
+                });
+            match node {
+                PendingNode::Dir {
+                    ref mut node_paths, ..
+                } => node_paths.push(child.into()),
+                _ => unreachable!(),
+            }
+
+            if known {
+                return;
+            }
+            child = parent_path;
+        }
+    }
+
+    fn push_file(&mut self, path: &Path, index: usize) {
+        if !self.roots.iter().any(|root| root == path) {
+            self.node_for_dir_of(path);
+        }
+        self.nodes.insert(
+            path.into(),
+            PendingNode::File(index, path.file_name().unwrap().to_os_string()),
+        );
+    }
+
+    fn into_nodes(self) -> Vec<PlaylistNode> {
+        let mut nodes = Vec::with_capacity(self.roots.len());
+
+        // File roots that weren't included have no node
+        for root in self
+            .roots
+            .iter()
+            .filter(|root| self.nodes.contains_key(*root))
+        {
+            let node = PlaylistNode::new(root.as_path(), &self);
+            nodes.push(node)
+        }
+
+        nodes
+    }
+}
+
+fn mkv_meta<P: AsRef<Path>>(path: P) -> Option<Track> {
+    let path = path.as_ref();
+    let file = File::open(path).ok()?;
+    let mkv = Matroska::open(file).ok()?;
+
+    let duration = mkv.info.duration;
+    let title = mkv.info.title;
+    let chapters = mkv_chapters(&mkv.chapters);
+    let creator = mkv_tag(&mkv.tags, &["ARTIST"]);
+    let annotation = mkv_tag(&mkv.tags, &["COMMENT", "DESCRIPTION"]);
+
+    let duration = duration
+        .map(|d| d.as_millis() as u64)
+        .or_else(|| cluster_duration(path))
+        .unwrap_or(0);
+    let title = title.unwrap_or_else(|| fallback_title(path));
+    let track = Track {
+        chapters,
+        creator,
+        annotation,
+        ..Track::new(path.to_path_buf(), title, duration)
+    };
+
+    Some(track)
+}
+
+// The value of the first tag with one of these names, they're checked in order.
+fn mkv_tag(tags: &[Tag], names: &[&str]) -> Option<String> {
+    names.iter().find_map(|name| {
+        tags.iter()
+            .flat_map(|tag| &tag.simple)
+            .filter(|simple| simple.name.eq_ignore_ascii_case(name))
+            .find_map(|simple| match simple.value {
+                Some(TagValue::String(ref value)) if !value.trim().is_empty() => {
+                    Some(value.trim().to_string())
+                }
+                _ => None,
+            })
+    })
+}
+
+// Reads the chapters of the default edition, or the first one if no edition is marked as default.
+// Hidden and disabled chapters are skipped.
+fn mkv_chapters(editions: &[ChapterEdition]) -> Vec<Chapter> {
+    let Some(edition) = editions
+        .iter()
+        .find(|edition| edition.default)
+        .or_else(|| editions.first())
+    else {
+        return vec![];
+    };
+
+    let mut chapters: Vec<Chapter> = edition
+        .chapters
+        .iter()
+        .filter(|chapter| chapter.enabled && !chapter.hidden)
+        .enumerate()
+        .map(|(idx, chapter)| Chapter {
+            title: chapter
+                .display
+                .first()
+                .map(|display| display.string.clone())
+                .unwrap_or_else(|| format!("Chapter {}", idx + 1)),
+            start: chapter.time_start.as_millis() as u64,
+        })
+        .collect();
+
+    chapters.sort_by_key(|chapter| chapter.start);
+    chapters
+}
+
+fn mp4_meta<P: AsRef<Path>>(path: P) -> Option<Track> {
+    let path = path.as_ref();
+    let file = File::open(path).ok()?;
+    let size = file.metadata().ok()?.len();
+    let reader = BufReader::new(file);
+
+    let mp4 = Mp4Reader::read_header(reader, size).ok()?;
+
+    let duration = mp4.duration();
+    let title = mp4
+        .metadata()
+        .title()
+        .map(|t| t.into_owned())
+        .filter(|t| !t.is_empty())
+        .or_else(|| udta_title(path));
+    let annotation = mp4
+        .metadata()
+        .summary()
+        .map(|s| s.into_owned())
+        .filter(|s| !s.is_empty());
+
+    let title = title.unwrap_or_else(|| fallback_title(path));
+    let track = Track {
+        annotation,
+        ..Track::new(path.to_path_buf(), title, duration.as_millis() as u64)
+    };
+
+    Some(track)
+}
+
+type MetaReader = fn(&Path) -> Option<Track>;
+
+fn builtin_meta_reader(path: &Path) -> Option<MetaReader> {
+    let file_ext = path.extension().map(OsStr::to_ascii_lowercase);
+    match file_ext.as_ref().and_then(|ext| ext.to_str()) {
+        Some("mkv" | "webm") => Some(|path| mkv_meta(path)),
+        Some("mp4" | "m4v" | "m4b" | "mov" | "3gp") => Some(|path| mp4_meta(path)),
+        Some("avi") => Some(|path| avi_meta(path)),
+        Some("ts" | "m2ts" | "mts") => Some(|path| ts_meta(path)),
+        Some("mp3" | "flac" | "m4a" | "ogg") => Some(|path| audio_meta(path)),
+        _ => None,
+    }
+}
+
+// Formats without a built-in parser that are only scanned when ffprobe is used.
+static FFPROBE_EXTENSIONS: &[&str] = &["flv"];
+
+// Reads metadata with ffprobe, falling back to the built-in parser if ffprobe fails.
+fn ffprobe_or_builtin_meta(path: &Path) -> Option<Track> {
+    ffprobe_meta(path).or_else(|| builtin_meta_reader(path)?(path))
+}
+
+fn meta_reader(path: &Path, options: &ScanOptions) -> Option<MetaReader> {
+    let file_ext = path.extension().map(OsStr::to_ascii_lowercase);
+    let file_ext = file_ext.as_ref().and_then(|ext| ext.to_str())?;
+    if !options.scans_extension(file_ext) {
+        return None;
+    }
+
+    if !options.use_ffprobe || !ffprobe_available() {
+        return builtin_meta_reader(path);
+    }
+
+    let ffprobe_only = FFPROBE_EXTENSIONS.contains(&file_ext);
+
+    if ffprobe_only || builtin_meta_reader(path).is_some() {
+        Some(ffprobe_or_builtin_meta)
+    } else {
+        None
+    }
+}
+
+static SUBTITLE_EXTENSIONS: &[&str] = &["srt", "ass", "ssa", "vtt", "sub"];
+static ARCHIVE_EXTENSION: &str = "zip";
+static IMAGE_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png"];
+// Names of images that are the art of every file in their directory, in order of preference
+static FOLDER_ART_NAMES: &[&str] = &["poster", "cover", "folder"];
+
+// Files found while walking the directory tree.
+#[derive(Default)]
+struct WalkedFiles {
+    // Media files with the reader for their format and their size
+    candidates: Vec<(PathBuf, MetaReader, u64)>,
+    // Subtitle files by the directory they're in
+    subtitles: HashMap<PathBuf, Vec<PathBuf>>,
+    // Ignore patterns by the directory they apply to
+    ignore_rules: HashMap<PathBuf, Vec<IgnoreRules>>,
+    // Files with VLC options for the media file they're named after
+    option_files: HashSet<PathBuf>,
+    // Images by the directory they're in
+    images: HashMap<PathBuf, Vec<PathBuf>>,
+    nfo_files: HashSet<PathBuf>,
+    // Zip archives with their modification time, their entries are listed after the walk
+    archives: Vec<(PathBuf, Option<SystemTime>)>,
+    errors: Vec<ScanError>,
+}
+
+impl WalkedFiles {
+    // Patterns in deeper directories take precedence, like with gitignore. The `.ignore` file of
+    // a root takes precedence over the ignore patterns of the scan options.
+    fn ignores(&self, path: &Path, depth: usize, is_dir: bool) -> bool {
+        path.ancestors()
+            .skip(1)
+            .take(depth)
+            .flat_map(|dir| self.ignore_rules.get(dir).into_iter().flatten().rev())
+            .find_map(|rules| rules.ignores(path, is_dir))
+            .unwrap_or(false)
+    }
+
+    // Subtitles belong to a media file if their name is its stem followed by an extension, like
+    // `Movie.srt` or `Movie.en.srt` for `Movie.mkv`.
+    fn subtitles_of(&self, path: &Path) -> Vec<PathBuf> {
+        let (Some(dir), Some(stem)) = (path.parent(), path.file_stem()) else {
+            return vec![];
+        };
+        let Some(subtitles) = self.subtitles.get(dir) else {
+            return vec![];
+        };
+
+        let mut prefix = stem.to_os_string();
+        prefix.push(".");
+        subtitles
+            .iter()
+            .filter(|sub| {
+                sub.file_name().is_some_and(|name| {
+                    name.as_encoded_bytes()
+                        .starts_with(prefix.as_encoded_bytes())
+                })
+            })
+            .cloned()
+            .collect()
+    }
+
+    // An NFO file with the same stem as the media file, like `Movie.nfo` for `Movie.mkv`, or the
+    // `movie.nfo` file in its directory.
+    fn nfo_of(&self, path: &Path) -> Option<&PathBuf> {
+        let own = path.with_extension(NFO_EXTENSION);
+        let movie = path.with_file_name(MOVIE_NFO);
+        self.nfo_files
+            .get(&own)
+            .or_else(|| self.nfo_files.get(&movie))
+    }
+
+    // An image with the same stem as the media file is preferred, like `Movie.jpg` or
+    // `Movie-poster.jpg` for `Movie.mkv`, otherwise the art of the directory is used.
+    fn image_of(&self, path: &Path) -> Option<PathBuf> {
+        let (dir, stem) = (path.parent()?, path.file_stem()?.to_str()?);
+        let images = self.images.get(dir)?;
+        let image_stem = |image: &PathBuf| {
+            image
+                .file_stem()
+                .and_then(OsStr::to_str)
+                .map(str::to_lowercase)
+        };
+
+        let stem = stem.to_lowercase();
+        let names = [stem.clone(), format!("{}-poster", stem)];
+        names
+            .iter()
+            .map(String::as_str)
+            .chain(FOLDER_ART_NAMES.iter().copied())
+            .find_map(|name| {
+                images
+                    .iter()
+                    .find(|image| image_stem(image).as_deref() == Some(name))
+            })
+            .cloned()
+    }
+}
+
+// Collect candidate media files while walking the directory tree, their metadata is read
+// afterwards by `read_tracks`.
+fn filter<'a>(
+    skip: &'a [PathBuf],
+    options: &'a ScanOptions,
+    files: &'a mut WalkedFiles,
+    progress: &'a mut Progress,
+) -> impl 'a + FnMut(&DirEntry) -> bool {
+    |entry| {
+        let path = entry.path();
+        progress.walking(
+            files.candidates.len(),
+            path.parent().filter(|_| entry.depth() > 0).unwrap_or(path),
+        );
+
+        // Roots are always scanned, even if they're hidden
+        let hidden = entry.file_name().to_string_lossy().starts_with('.');
+        if hidden && entry.depth() > 0 && !options.hidden {
+            verbose!("Skipped {}: hidden", path.display());
+            return false;
+        }
+
+        if files.ignores(path, entry.depth(), entry.file_type().is_dir()) {
+            verbose!("Skipped {}: ignored", path.display());
+            return false;
+        }
+
+        match entry.metadata() {
+            Ok(meta) => {
+                if meta.is_file() {
+                    if let Some(pattern) = options.excluded_by(entry.file_name()) {
+                        verbose!(
+                            "Skipped {}: matches exclude pattern `{}`",
+                            path.display(),
+                            pattern
+                        );
+                        return false;
+                    }
+
+                    if let Some(reader) = meta_reader(path, options) {
+                        // Usually left behind by an interrupted download or copy
+                        if meta.len() == 0 {
+                            verbose!("Skipped {}: empty file", path.display());
+                            return false;
+                        }
+
+                        if options.min_file_size.is_some_and(|min| meta.len() < min) {
+                            verbose!(
+                                "Skipped {}: size of {} bytes is below the minimum",
+                                path.display(),
+                                meta.len()
+                            );
+                            return false;
+                        }
+
+                        if !options.modified_in_range(meta.modified().ok()) {
+                            verbose!(
+                                "Skipped {}: modified outside of the date range",
+                                path.display()
+                            );
+                            return false;
+                        }
+
+                        files
+                            .candidates
+                            .push((path.to_path_buf(), reader, meta.len()));
+                        return true;
+                    }
+
+                    let file_ext = path.extension().map(OsStr::to_ascii_lowercase);
+                    let is_subtitle = file_ext
+                        .as_ref()
+                        .and_then(|ext| ext.to_str())
+                        .is_some_and(|ext| SUBTITLE_EXTENSIONS.contains(&ext));
+                    if file_ext.as_deref() == Some(OsStr::new(OPTIONS_EXTENSION)) {
+                        files.option_files.insert(path.to_path_buf());
+                    } else if options.nfo_titles
+                        && file_ext.as_deref() == Some(OsStr::new(NFO_EXTENSION))
+                    {
+                        files.nfo_files.insert(path.to_path_buf());
+                    } else if options.scan_archives
+                        && file_ext.as_deref() == Some(OsStr::new(ARCHIVE_EXTENSION))
+                    {
+                        if options.modified_in_range(meta.modified().ok()) {
+                            files
+                                .archives
+                                .push((path.to_path_buf(), meta.modified().ok()));
+                        } else {
+                            verbose!(
+                                "Skipped {}: modified outside of the date range",
+                                path.display()
+                            );
+                        }
+                    } else if options.art
+                        && file_ext
+                            .as_ref()
+                            .and_then(|ext| ext.to_str())
+                            .is_some_and(|ext| IMAGE_EXTENSIONS.contains(&ext))
+                    {
+                        if let Some(dir) = path.parent() {
+                            let images = files.images.entry(dir.to_path_buf()).or_default();
+                            images.push(path.to_path_buf());
+                        }
+                    } else if options.subtitles && is_subtitle {
+                        if let Some(dir) = path.parent() {
+                            let subtitles = files.subtitles.entry(dir.to_path_buf()).or_default();
+                            subtitles.push(path.to_path_buf());
+                        }
+                    } else if file_ext
+                        .as_ref()
+                        .and_then(|ext| ext.to_str())
+                        .is_some_and(|ext| !options.scans_extension(ext))
+                    {
+                        verbose!("Skipped {}: excluded extension", path.display());
+                    } else {
+                        verbose!("Skipped {}: unsupported format", path.display());
+                    }
+
+                    false
+                } else if meta.is_dir() {
+                    if skip.iter().any(|e| e.as_path() == path) {
+                        verbose!("Skipped {}: skipped directory", path.display());
+                        return false;
+                    }
+
+                    if entry.depth() > 0
+                        && options
+                            .skip_names
+                            .iter()
+                            .any(|name| name == entry.file_name())
+                    {
+                        verbose!("Skipped {}: skipped directory name", path.display());
+                        return false;
+                    }
+
+                    if !options.no_ignore && path.join(NOMEDIA_FILE).exists() {
+                        verbose!("Skipped {}: contains {}", path.display(), NOMEDIA_FILE);
+                        return false;
+                    }
+
+                    let mut rules = vec![];
+                    if entry.depth() == 0 && !options.ignore_patterns.is_empty() {
+                        rules.push(IgnoreRules::new(path, &options.ignore_patterns));
+                    }
+                    if !options.no_ignore {
+                        rules.extend(IgnoreRules::read(path));
+                    }
+                    if !rules.is_empty() {
+                        files.ignore_rules.insert(path.to_path_buf(), rules);
+                    }
+
+                    true
+                } else {
+                    // Links are only followed with `follow_symlinks`
+                    if warn_broken_link(path) {
+                        files
+                            .errors
+                            .push(ScanError::new(path, ScanErrorKind::BrokenLink));
+                    } else if meta.file_type().is_symlink() {
+                        verbose!("Skipped {}: symbolic link", path.display());
+                    } else {
+                        verbose!("Skipped {}: not a file or directory", path.display());
+                    }
+                    false
+                }
+            }
+            Err(e) => {
+                verbose!("Skipped {}: {}", path.display(), e);
+                files.errors.push(ScanError::new(path, walk_error_kind(&e)));
+                false
+            }
+        }
+    }
+}
+
+fn walk_error_kind(e: &walkdir::Error) -> ScanErrorKind {
+    match e.io_error() {
+        _ if e.loop_ancestor().is_some() => ScanErrorKind::LinkLoop,
+        Some(e) => ScanErrorKind::Io(e.kind()),
+        None => ScanErrorKind::Io(io::ErrorKind::Other),
+    }
+}
+
+// Warns about a symbolic link whose target doesn't exist, returns `false` if `path` isn't one.
+fn warn_broken_link(path: &Path) -> bool {
+    let is_link = fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_symlink());
+    if !is_link || fs::metadata(path).is_ok() {
+        return false;
+    }
+
+    match fs::read_link(path) {
+        Ok(target) => warning!(
+            "Skipped {}: broken symbolic link to {}",
+            path.display(),
+            target.display()
+        ),
+        Err(_) => warning!("Skipped {}: broken symbolic link", path.display()),
+    }
+    true
+}
+
+// Reads the metadata on a separate thread and gives up if it takes longer than `timeout`, so a
+// file on an unresponsive network share can't stall the scan. The thread is left running until
+// the read completes or the process exits.
+fn read_with_timeout(
+    path: &Path,
+    reader: MetaReader,
+    timeout: Duration,
+) -> Result<Track, ScanErrorKind> {
+    let (sender, receiver) = mpsc::channel();
+    let owned_path = path.to_path_buf();
+    thread::spawn(move || {
+        // The receiver is gone if the read timed out
+        let _ = sender.send(reader(&owned_path));
+    });
+
+    match receiver.recv_timeout(timeout) {
+        Ok(track) => track.ok_or(ScanErrorKind::Metadata),
+        Err(_) => {
+            warning!(
+                "Skipped {}: reading metadata took longer than {:.1} s",
+                path.display(),
+                timeout.as_secs_f64()
+            );
+            Err(ScanErrorKind::Timeout)
+        }
+    }
+}
+
+// Read the metadata of all candidates in parallel, unless it's cached. The returned tracks are in
+// the same order as the candidates, with the reason for files that can't be read.
+fn read_tracks(
+    candidates: &[(PathBuf, MetaReader, u64)],
+    cache: &mut MetadataCache,
+    timeout: Option<Duration>,
+    threads: Option<NonZeroUsize>,
+    progress: &mut Progress,
+) -> Vec<Result<Track, ScanErrorKind>> {
+    let n_threads = threads
+        .or_else(|| thread::available_parallelism().ok())
+        .map(NonZeroUsize::get)
+        .unwrap_or(1)
+        .min(candidates.len())
+        .max(1);
+    let next = AtomicUsize::new(0);
+
+    let mut tracks: Vec<Result<Track, ScanErrorKind>> = Vec::with_capacity(candidates.len());
+    tracks.resize_with(candidates.len(), || Err(ScanErrorKind::Metadata));
+
+    let mut stamps: Vec<Option<FileStamp>> = vec![None; candidates.len()];
+
+    thread::scope(|s| {
+        let cache = &*cache;
+        let next = &next;
+        let (sender, receiver) = mpsc::channel();
+        for _ in 0..n_threads {
+            let sender = sender.clone();
+            s.spawn(move || loop {
+                let idx = next.fetch_add(1, atomic::Ordering::Relaxed);
+                let Some((path, reader, _)) = candidates.get(idx) else {
+                    break;
+                };
+
+                let stamp = FileStamp::of(path);
+                let track = match stamp.and_then(|stamp| cache.get(path, stamp)) {
+                    Some(track) => Ok(track),
+                    None => match timeout {
+                        Some(timeout) => read_with_timeout(path, *reader, timeout),
+                        None => reader(path).ok_or(ScanErrorKind::Metadata),
+                    },
+                };
+                if sender.send((idx, stamp, track)).is_err() {
+                    break;
+                }
+            });
+        }
+        // The receiver ends once every worker has dropped its sender
+        drop(sender);
+
+        for (done, (idx, stamp, mut track)) in receiver.into_iter().enumerate() {
+            if let (Ok(track), Some(stamp)) = (track.as_mut(), stamp) {
+                track.modified = Some(stamp.modified());
+            }
+            stamps[idx] = stamp;
+            tracks[idx] = track;
+            progress.reading(done + 1, candidates.len());
+        }
+    });
+
+    for (track, stamp) in tracks.iter().zip(stamps) {
+        if let (Ok(track), Some(stamp)) = (track, stamp) {
+            cache.insert(track, stamp);
+        }
+    }
+
+    tracks
+}
+
+/// The order of the files in each directory of a playlist.
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SortOrder {
+    /// Natural order of the file names.
+    #[default]
+    Name,
+    /// Natural order of the track titles.
+    Title,
+    /// Shortest tracks first.
+    Duration,
+    /// Order of the full paths.
+    Path,
+    /// Most recently modified files first, directories are ordered by their newest file.
+    Modified,
+}
+
+/// Options that control how the roots are scanned.
+#[derive(Default)]
+pub struct ScanOptions {
+    /// Maximum depth to descend into each root, 1 only includes the files directly in a root.
+    pub max_depth: Option<usize>,
+    /// Tracks shorter than this duration in milliseconds are skipped.
+    pub min_duration: Option<u64>,
+    /// Tracks longer than this duration in milliseconds are skipped.
+    pub max_duration: Option<u64>,
+    /// Skip tracks whose duration is unknown, i.e. 0. These are often broken files.
+    pub skip_unknown_duration: bool,
+    /// Skip directories with one of these names at any depth, unlike the skipped paths these
+    /// apply to every root. The roots themselves are always scanned.
+    pub skip_names: Vec<OsString>,
+    /// Follow symbolic links, links that form a loop are skipped. The track location is the path
+    /// of the link, metadata is read from its target.
+    pub follow_symlinks: bool,
+    /// Include files and directories whose name starts with a dot.
+    pub hidden: bool,
+    /// Read metadata with ffprobe, which also adds support for flv files. The
+    /// built-in parsers are used if ffprobe isn't installed or can't read a file.
+    pub use_ffprobe: bool,
+    /// Only scan files with these extensions instead of all supported formats. Extensions without
+    /// a supported format are ignored.
+    pub include_extensions: Option<Vec<String>>,
+    /// Never scan files with these extensions.
+    pub exclude_extensions: Vec<String>,
+    /// Skip files whose name matches one of these regular expressions anywhere, ignoring case,
+    /// e.g. `sample|trailer`. They're checked before the extensions.
+    pub exclude_patterns: Vec<Regex>,
+    /// Search for subtitle files next to each media file.
+    pub subtitles: bool,
+    /// Use images next to each media file as its cover art, either an image with the same name
+    /// or `poster`, `cover` or `folder` in jpg or png format.
+    pub art: bool,
+    /// Use the title from a Kodi-style NFO file as the title of a track, either the NFO file with
+    /// the same name as the media file or `movie.nfo` in its directory.
+    pub nfo_titles: bool,
+    /// Report progress on stderr while scanning, if it's a terminal.
+    pub progress: bool,
+    /// Order of the files in each directory. Directories are sorted by title, unless files are
+    /// sorted by modification time.
+    pub sort: SortOrder,
+    /// Reverse the order of the files and directories, directories are still sorted before files.
+    pub reverse: bool,
+    /// Shuffle the files and directories in each directory with this seed instead of sorting
+    /// them, the track list follows the shuffled order. The same seed gives the same order.
+    pub shuffle: Option<u64>,
+    /// Keep the roots in the order they're given instead of sorting or shuffling them, the
+    /// contents of each root are still sorted.
+    pub preserve_root_order: bool,
+    /// Files that are found more than once, e.g. through overlapping roots or links, share a
+    /// single track. Files are compared by their canonical path, files whose path can't be
+    /// canonicalized are never considered duplicates.
+    pub dedupe: bool,
+    /// Clean up the titles of directories with [`clean_title`], the title of files is unchanged.
+    pub clean_titles: bool,
+    /// Append a counter to the title of directories and files that have the same title as an
+    /// earlier entry in the same directory, e.g. `Extras (2)`. Only titles change, not locations.
+    pub disambiguate_titles: bool,
+    /// Use the file name without extension as the title of files without an embedded title,
+    /// instead of the full file name.
+    pub strip_title_extension: bool,
+    /// Replace dots and underscores with spaces in the titles from `strip_title_extension`.
+    pub title_spaces: bool,
+    /// Resolve the roots and skipped directories to absolute paths without symbolic links before
+    /// scanning, paths that can't be resolved are used as they are.
+    pub canonicalize: bool,
+    /// Gitignore-style patterns of files and directories that are skipped, relative to each root.
+    /// A pattern without a slash matches the name of a file or directory at any depth, e.g.
+    /// `*.sample.mkv` or `Extras/`, and patterns that start with `!` include matches again.
+    pub ignore_patterns: Vec<String>,
+    /// Don't skip directories that contain a `.nomedia` file, and ignore the patterns in `.ignore`
+    /// files. These files otherwise apply to the directory they're in and its subdirectories.
+    pub no_ignore: bool,
+    /// Files whose metadata isn't read within this time are skipped with a warning, e.g. because
+    /// they're on a network share that stopped responding.
+    pub file_timeout: Option<Duration>,
+    /// Number of threads that read metadata, by default the number of logical cores. The order
+    /// of the tracks doesn't depend on it.
+    pub threads: Option<NonZeroUsize>,
+    /// Stop once this many tracks were included. Files that are skipped, e.g. because their
+    /// duration is out of range, and duplicates don't count, and files after the last included
+    /// one aren't read.
+    pub limit: Option<usize>,
+    /// Skip media files smaller than this number of bytes, e.g. samples. Their metadata isn't
+    /// read.
+    pub min_file_size: Option<u64>,
+    /// List the media files in zip archives, the archive is added as a directory. Their
+    /// duration is unknown, as they'd have to be extracted to read it.
+    pub scan_archives: bool,
+    /// Skip files last modified before this time.
+    pub newer_than: Option<SystemTime>,
+    /// Skip files last modified at or after this time.
+    pub older_than: Option<SystemTime>,
+}
+
+impl ScanOptions {
+    fn scans_extension(&self, ext: &str) -> bool {
+        let matches = |e: &String| e.trim_start_matches('.').eq_ignore_ascii_case(ext);
+        self.include_extensions
+            .as_ref()
+            .is_none_or(|include| include.iter().any(matches))
+            && !self.exclude_extensions.iter().any(matches)
+    }
+
+    // The first exclude pattern that matches the file name, ignoring case.
+    fn excluded_by(&self, name: &OsStr) -> Option<&Regex> {
+        let name = name.to_string_lossy();
+        self.exclude_patterns
+            .iter()
+            .find(|pattern| pattern.is_match_ignoring_case(&name))
+    }
+
+    // Whether a file with this modification time is in the range of `newer_than` and
+    // `older_than`, files whose modification time is unknown are only included without a range.
+    fn modified_in_range(&self, modified: Option<SystemTime>) -> bool {
+        if self.newer_than.is_none() && self.older_than.is_none() {
+            return true;
+        }
+
+        modified.is_some_and(|modified| {
+            self.newer_than.is_none_or(|newer| modified >= newer)
+                && self.older_than.is_none_or(|older| modified < older)
+        })
+    }
+
+    // The file stem as the title of a file without an embedded title, whose title is its file
+    // name. Dots and underscores are replaced with spaces if `title_spaces` is set.
+    fn stem_title(&self, path: &Path, title: &str) -> Option<String> {
+        if path.file_name()?.to_str()? != title {
+            return None;
+        }
+
+        let stem = path.file_stem()?.to_str()?;
+        if self.title_spaces {
+            Some(stem.replace(['.', '_'], " "))
+        } else {
+            Some(stem.to_string())
+        }
+    }
+
+    fn includes(&self, track: &Track) -> bool {
+        !(self.skip_unknown_duration && track.duration() == 0)
+            && self.min_duration.is_none_or(|min| track.duration() >= min)
+            && self.max_duration.is_none_or(|max| track.duration() <= max)
+    }
+}
+
+// Appends a counter to the titles of directories and files that have the same title as an earlier
+// sibling, e.g. the second `Extras` becomes `Extras (2)`. Nested directories are handled the same
+// way.
+fn disambiguate_titles(nodes: &mut [PlaylistNode], tracks: &mut [Track]) {
+    let title = |node: &PlaylistNode, tracks: &[Track]| match node {
+        PlaylistNode::Dir { title, .. } => title.clone(),
+        PlaylistNode::File(idx, _) => tracks[*idx].title.clone(),
+    };
+    let titles = |nodes: &[PlaylistNode], tracks: &[Track]| -> HashSet<String> {
+        nodes.iter().map(|node| title(node, tracks)).collect()
+    };
+
+    // The nodes left in each open directory, with the titles that are taken and seen in it
+    let mut stack = vec![(titles(nodes, tracks), HashSet::new(), nodes.iter_mut())];
+    while let Some((taken, seen, remaining)) = stack.last_mut() {
+        let Some(node) = remaining.next() else {
+            stack.pop();
+            continue;
+        };
+
+        let current = title(node, tracks);
+        if !seen.insert(current.clone()) {
+            let unique = (2..)
+                .map(|n| format!("{} ({})", current, n))
+                .find(|candidate| !taken.contains(candidate))
+                .unwrap();
+            taken.insert(unique.clone());
+            seen.insert(unique.clone());
+            verbose!("Renamed duplicate title {} to {}", current, unique);
+
+            match node {
+                PlaylistNode::Dir { title, .. } => *title = unique,
+                PlaylistNode::File(idx, _) => tracks[*idx].title = unique,
+            }
+        }
+
+        if let PlaylistNode::Dir { nodes, .. } = node {
+            stack.push((titles(nodes, tracks), HashSet::new(), nodes.iter_mut()));
+        }
+    }
+}
+
+// A splitmix64 generator, which is good enough to shuffle a playlist and gives the same order
+// for the same seed on every platform.
+struct Shuffler(u64);
+
+impl Shuffler {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // Fisher-Yates shuffle, the index is taken from the high bits to avoid modulo bias
+    fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            let j = ((self.next() as u128 * (i as u128 + 1)) >> 64) as usize;
+            items.swap(i, j);
+        }
+    }
+}
+
+// Reorders the tracks in the order they appear in the directory tree and renumbers the nodes,
+// tracks that are shared by several nodes are placed at their first occurrence.
+fn into_tree_order(nodes: &mut [PlaylistNode], tracks: Vec<Track>) -> Vec<Track> {
+    let mut renumbered = HashMap::new();
+    for idx in nodes.iter().flat_map(PlaylistNode::track_indices) {
+        let new_idx = renumbered.len();
+        renumbered.entry(idx).or_insert(new_idx);
+    }
+    nodes.iter_mut().for_each(|node| node.renumber(&renumbered));
+
+    let mut tracks: Vec<_> = tracks.into_iter().map(Some).collect();
+    let mut ordered = vec![None; renumbered.len()];
+    for (idx, new_idx) in renumbered {
+        ordered[new_idx] = tracks[idx].take();
+    }
+    ordered.into_iter().flatten().collect()
+}
+
+// The media files in a zip archive as tracks of unknown duration, their metadata can't be read
+// without extracting them. The archive is a directory of the playlist.
+fn archive_tracks(
+    archive: &Path,
+    modified: Option<SystemTime>,
+    options: &ScanOptions,
+) -> io::Result<Vec<Track>> {
+    let mut tracks = vec![];
+    for entry in zip_entries(archive)? {
+        let location = archive.join(&entry.path);
+        let name = location.file_name().unwrap_or_default();
+        if !options.hidden && name.to_string_lossy().starts_with('.') {
+            verbose!("Skipped {}: hidden", location.display());
+            continue;
+        }
+        if let Some(pattern) = options.excluded_by(name) {
+            verbose!(
+                "Skipped {}: matches exclude pattern `{}`",
+                location.display(),
+                pattern
+            );
+            continue;
+        }
+        if meta_reader(&location, options).is_none() {
+            continue;
+        }
+        if options.min_file_size.is_some_and(|min| entry.size < min) {
+            verbose!(
+                "Skipped {}: size of {} bytes is below the minimum",
+                location.display(),
+                entry.size
+            );
+            continue;
+        }
+
+        let title = fallback_title(&location);
+        let format = location
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_ascii_lowercase());
+        tracks.push(Track {
+            modified,
+            format,
+            size: Some(entry.size),
+            archive: Some(archive.to_path_buf()),
+            ..Track::new(location, title, 0)
+        });
+    }
+
+    Ok(tracks)
+}
+
+fn canonicalize_all(paths: &[PathBuf]) -> Vec<PathBuf> {
+    paths
+        .iter()
+        .map(|path| {
+            path.canonicalize().unwrap_or_else(|e| {
+                warning!(
+                    "{} can't be resolved, using it as is: {}",
+                    path.display(),
+                    e
+                );
+                path.clone()
+            })
+        })
+        .collect()
+}
+
+// Removes the roots that are inside another root, their files are found while walking that root.
+// Roots that the walk of the other root doesn't fully cover, because of a skipped or hidden
+// directory in between or the maximum depth, are kept with a warning.
+fn remove_nested_roots(roots: &[PathBuf], skip: &[PathBuf], options: &ScanOptions) -> Vec<PathBuf> {
+    let resolve = |path: &PathBuf| path.canonicalize().unwrap_or_else(|_| path.clone());
+    let canonical: Vec<PathBuf> = roots.iter().map(resolve).collect();
+    let skip: Vec<PathBuf> = skip.iter().map(resolve).collect();
+
+    let mut kept = vec![];
+    for (idx, (root, path)) in roots.iter().zip(&canonical).enumerate() {
+        // Of identical roots the first one is kept
+        let outer = canonical
+            .iter()
+            .enumerate()
+            .filter(|&(other_idx, other)| {
+                other_idx != idx && path.starts_with(other) && (path != other || other_idx < idx)
+            })
+            .min_by_key(|(_, other)| other.components().count());
+        let Some((outer_idx, outer)) = outer else {
+            kept.push(root.clone());
+            continue;
+        };
+
+        let relative = path.strip_prefix(outer).unwrap();
+        let hidden = relative
+            .components()
+            .any(|c| c.as_os_str().to_string_lossy().starts_with('.'));
+        let skipped = skip
+            .iter()
+            .any(|skip| path.starts_with(skip) && skip.starts_with(outer));
+        let too_deep = options.max_depth.is_some() && relative.components().next().is_some();
+
+        if skipped || too_deep || (hidden && !options.hidden) {
+            warning!(
+                "root {} is inside root {} but isn't fully scanned as part of it, its files can \
+                 be added twice",
+                root.display(),
+                roots[outer_idx].display()
+            );
+            kept.push(root.clone());
+        } else {
+            verbose!(
+                "Skipped root {}: it's scanned as part of root {}",
+                root.display(),
+                roots[outer_idx].display()
+            );
+        }
+    }
+
+    kept
+}
+
+/// Recursively scans `roots` for media files, skipping the directories in `skip`. Directories
+/// are sorted before files, both in natural order unless another order is set in `options`.
+///
+/// The result only depends on the scanned tree and the arguments, not on the order in which the
+/// file system lists directory entries: each directory is walked in file name order, so track
+/// indices are assigned in that order, and the nodes are sorted afterwards.
+///
+/// Files and directories that can't be scanned are skipped and returned with the reason, in the
+/// order they were found. Files that are skipped on purpose, e.g. because of their extension,
+/// aren't errors.
+pub fn generate_playlist(
+    roots: &[PathBuf],
+    skip: &[PathBuf],
+    options: &ScanOptions,
+) -> (Playlist, Vec<ScanError>) {
+    generate_playlist_cached(roots, skip, options, &mut MetadataCache::default())
+}
+
+/// Like [`generate_playlist`], but the metadata of files that haven't changed since they were
+/// added to `cache` is read from it. The cache is updated with the scanned files.
+pub fn generate_playlist_cached(
+    roots: &[PathBuf],
+    skip: &[PathBuf],
+    options: &ScanOptions,
+    cache: &mut MetadataCache,
+) -> (Playlist, Vec<ScanError>) {
+    let (roots, skip) = if options.canonicalize {
+        (canonicalize_all(roots), canonicalize_all(skip))
+    } else {
+        (roots.to_vec(), skip.to_vec())
+    };
+    let roots = remove_nested_roots(&roots, &skip, options);
+    let (roots, skip) = (roots.as_slice(), skip.as_slice());
+
+    let mut files = WalkedFiles::default();
+    let mut nodes = PendingNodeMap::new(roots.to_vec());
+    let mut progress = Progress::new(options.progress);
+    let mut walk_errors = vec![];
+
+    {
+        let mut filter = filter(skip, options, &mut files, &mut progress);
+
+        for root in roots {
+            let mut walker = WalkDir::new(root)
+                .follow_links(options.follow_symlinks)
+                .sort_by_file_name();
+            if let Some(max_depth) = options.max_depth {
+                walker = walker.max_depth(max_depth);
+            }
+
+            // Errors, like a loop of symbolic links, skip the entry. Links that are followed fail
+            // if their target is missing.
+            let walker = walker.into_iter();
+            for entry in walker.filter_entry(&mut filter) {
+                let Err(e) = entry else {
+                    continue;
+                };
+                let Some(path) = e.path() else {
+                    continue;
+                };
+
+                let kind = if warn_broken_link(path) {
+                    ScanErrorKind::BrokenLink
+                } else {
+                    verbose!("Skipped {}: {}", path.display(), e);
+                    walk_error_kind(&e)
+                };
+                walk_errors.push(ScanError::new(path, kind));
+            }
+        }
+    }
+
+    // Tracks are indexed in walk order, skipping files whose metadata can't be read or that are
+    // filtered out. Directories are only created for included files, so none are left empty.
+    let mut tracks = vec![];
+    let mut canonical_tracks = HashMap::new();
+    let mut errors = mem::take(&mut files.errors);
+    errors.append(&mut walk_errors);
+    cache.remove_missing();
+
+    // With a limit, metadata is read in batches of the number of tracks that are still missing,
+    // so files after the limit aren't read while skipped files and duplicates don't count
+    let limited = |tracks: &[Track]| options.limit.is_some_and(|limit| tracks.len() >= limit);
+    let mut start = 0;
+    while start < files.candidates.len() && !limited(&tracks) {
+        let end = match options.limit {
+            Some(limit) => start + (limit - tracks.len()),
+            None => files.candidates.len(),
+        }
+        .min(files.candidates.len());
+        let batch = &files.candidates[start..end];
+        start = end;
+
+        let read = read_tracks(
+            batch,
+            cache,
+            options.file_timeout,
+            options.threads,
+            &mut progress,
+        );
+        for ((path, _, size), track) in batch.iter().zip(read) {
+            let mut track = match track {
+                Ok(track) => track,
+                Err(kind) => {
+                    if kind == ScanErrorKind::Metadata {
+                        verbose!("Skipped {}: metadata can't be read", path.display());
+                    }
+                    errors.push(ScanError::new(path, kind));
+                    continue;
+                }
+            };
+
+            if !options.includes(&track) {
+                if track.duration() == 0 && options.skip_unknown_duration {
+                    verbose!("Skipped {}: duration is unknown", path.display());
+                } else {
+                    verbose!(
+                        "Skipped {}: duration of {} ms is out of range",
+                        path.display(),
+                        track.duration()
+                    );
+                }
+                continue;
+            }
+
+            if options.dedupe {
+                if let Ok(canonical) = path.canonicalize() {
+                    if let Some(&idx) = canonical_tracks.get(&canonical) {
+                        nodes.push_file(path, idx);
+                        continue;
+                    }
+
+                    canonical_tracks.insert(canonical, tracks.len());
+                }
+            }
+
+            if limited(&tracks) {
+                break;
+            }
+
+            track.subtitles = files.subtitles_of(path);
+            track.image = files.image_of(path);
+            track.format = path
+                .extension()
+                .map(|ext| ext.to_string_lossy().to_ascii_lowercase());
+            track.size = Some(*size);
+            if options.strip_title_extension {
+                if let Some(title) = options.stem_title(path, track.title()) {
+                    track.title = title;
+                }
+            }
+            if let Some(title) = files.nfo_of(path).and_then(|nfo| nfo_title(nfo)) {
+                track.title = title;
+            }
+            let options_path = sidecar_path(path);
+            if files.option_files.contains(&options_path) {
+                (track.start_time, track.stop_time) = read_time_options(&options_path);
+            }
+
+            nodes.push_file(path, tracks.len());
+            tracks.push(track);
+        }
+    }
+    progress.finish();
+
+    for (archive, modified) in &files.archives {
+        let archived = match archive_tracks(archive, *modified, options) {
+            Ok(archived) => archived,
+            Err(e) => {
+                verbose!("Skipped {}: {}", archive.display(), e);
+                errors.push(ScanError::new(archive, ScanErrorKind::Io(e.kind())));
+                continue;
+            }
+        };
+
+        for mut track in archived {
+            if !options.includes(&track) {
+                verbose!("Skipped {}: duration is unknown", track.location.display());
+                continue;
+            }
+
+            if limited(&tracks) {
+                break;
+            }
+
+            if options.strip_title_extension {
+                if let Some(title) = options.stem_title(&track.location, track.title()) {
+                    track.title = title;
+                }
+            }
+            nodes.push_file(&track.location, tracks.len());
+            tracks.push(track);
+        }
+    }
+
+    let mut nodes = nodes.into_nodes();
+    if options.clean_titles {
+        nodes.iter_mut().for_each(PlaylistNode::clean_titles);
+    }
+
+    if let Some(seed) = options.shuffle {
+        let mut shuffler = Shuffler(seed);
+        if !options.preserve_root_order {
+            shuffler.shuffle(&mut nodes);
+        }
+        for n in nodes.as_mut_slice() {
+            n.shuffle(&mut shuffler);
+        }
+    } else {
+        // Sort nodes in the requested order, files after dirs
+        let compare = |a: &PlaylistNode, b: &PlaylistNode| {
+            a.cmp_by(b, options.sort, options.reverse, &tracks)
+        };
+        if !options.preserve_root_order {
+            nodes.sort_by(compare);
+        }
+        for n in nodes.as_mut_slice() {
+            n.sort_by(&compare);
+        }
+    }
+
+    let mut nodes: Vec<PlaylistNode> = nodes.into_iter().filter_map(PlaylistNode::prune).collect();
+    if options.disambiguate_titles {
+        disambiguate_titles(&mut nodes, &mut tracks);
+    }
+    // The flat track list is shuffled too, by putting it in the order of the shuffled tree
+    if options.shuffle.is_some() {
+        tracks = into_tree_order(&mut nodes, tracks);
+    }
+
+    let track_list = TrackList { tracks };
+    (Playlist { track_list, nodes }, errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{mkv, mp4, TempDir};
+
+    // The file names of the tracks of a playlist generated from `roots`, the scan must succeed.
+    fn scanned_names(roots: &[PathBuf], options: &ScanOptions) -> Vec<String> {
+        let (playlist, errors) = generate_playlist(roots, &[], options);
+        assert!(errors.is_empty());
+        let mut names: Vec<_> = playlist
+            .tracks()
+            .map(|track| {
+                track
+                    .location()
+                    .file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .into()
+            })
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn extensions_match_in_any_case() {
+        let dir = TempDir::new();
+        dir.file("A.MKV", &mkv(1000.0));
+        dir.file("b.Mp4", &mp4(2000));
+        let roots = [dir.path().to_path_buf()];
+        assert_eq!(
+            scanned_names(&roots, &ScanOptions::default()),
+            ["A.MKV", "b.Mp4"]
+        );
+    }
+
+    #[test]
+    fn hidden_files_are_opt_in() {
+        let dir = TempDir::new();
+        dir.file("a.mkv", &mkv(1000.0));
+        dir.file(".hidden.mkv", &mkv(1000.0));
+        dir.file(".trash/b.mkv", &mkv(1000.0));
+        let roots = [dir.path().to_path_buf()];
+        assert_eq!(scanned_names(&roots, &ScanOptions::default()), ["a.mkv"]);
+
+        let options = ScanOptions {
+            hidden: true,
+            ..ScanOptions::default()
+        };
+        assert_eq!(
+            scanned_names(&roots, &options),
+            [".hidden.mkv", "a.mkv", "b.mkv"]
+        );
+    }
+
+    #[test]
+    fn limit_counts_included_tracks() {
+        let dir = TempDir::new();
+        dir.file("1.mkv", &mkv(500.0));
+        dir.file("2.mkv", &mkv(2000.0));
+        dir.file("4.mkv", &mkv(2000.0));
+        dir.file("5.mkv", &mkv(2000.0));
+        let roots = [dir.path().to_path_buf()];
+        let options = ScanOptions {
+            min_duration: Some(1000),
+            limit: Some(2),
+            ..ScanOptions::default()
+        };
+        assert_eq!(scanned_names(&roots, &options), ["2.mkv", "4.mkv"]);
+
+        // A duplicate refers to a track that's already included
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(dir.path().join("2.mkv"), dir.path().join("3.mkv")).unwrap();
+            let options = ScanOptions {
+                dedupe: true,
+                follow_symlinks: true,
+                ..options
+            };
+            assert_eq!(scanned_names(&roots, &options), ["2.mkv", "4.mkv"]);
+        }
+    }
+
+    #[test]
+    fn tracks_are_read_in_order_by_all_threads() {
+        let dir = TempDir::new();
+        let candidates: Vec<_> = (0..50)
+            .map(|n| {
+                let path = dir.file(&format!("{}.mkv", n), &mkv(1000.0 * (n + 1) as f32));
+                (path, (|path| mkv_meta(path)) as MetaReader, 0)
+            })
+            .collect();
+
+        let tracks = read_tracks(
+            &candidates,
+            &mut MetadataCache::default(),
+            None,
+            NonZeroUsize::new(4),
+            &mut Progress::new(false),
+        );
+        let durations: Vec<_> = tracks
+            .iter()
+            .map(|track| track.as_ref().unwrap().duration())
+            .collect();
+        let expected: Vec<_> = (1..=50).map(|n| n * 1000).collect();
+        assert_eq!(durations, expected);
+    }
+
+    #[test]
+    fn output_is_deterministic() {
+        static PATHS: [&str; 6] = [
+            "b/2.mkv",
+            "b/10.mkv",
+            "a/x.mkv",
+            "a/c/y.mkv",
+            "z.mkv",
+            "B.mkv",
+        ];
+
+        // The same tree twice, with the files created in opposite orders
+        let xml = |reverse: bool| {
+            let dir = TempDir::new();
+            let mut paths = PATHS.to_vec();
+            if reverse {
+                paths.reverse();
+            }
+            for path in paths {
+                dir.file(&format!("library/{}", path), &mkv(1000.0));
+            }
+
+            let roots = [dir.path().join("library")];
+            let (playlist, _) = generate_playlist(&roots, &[], &ScanOptions::default());
+            let [root] = roots;
+            let options = XmlOptions {
+                relative_to: Some(root),
+                ..XmlOptions::default()
+            };
+            let mut xml = vec![];
+            into_xml(&mut xml, playlist, &options).unwrap();
+            xml
+        };
+        assert_eq!(xml(false), xml(true));
+    }
+
+    #[test]
+    fn nodes_sort_naturally_with_dirs_first() {
+        assert_eq!(natural_cmp("Episode 2", "Episode 10"), Ordering::Less);
+        assert_eq!(natural_cmp("Episode 02", "Episode 2"), Ordering::Less);
+        // Case is significant, upper case letters sort before lower case ones
+        assert_eq!(natural_cmp("B", "a"), Ordering::Less);
+        assert_eq!(natural_cmp("a 2", "A 10"), Ordering::Greater);
+
+        let file = |idx, name: &str| PlaylistNode::File(idx, name.into());
+        let dir = |title: &str| PlaylistNode::Dir {
+            title: title.into(),
+            path: PathBuf::from(title),
+            nodes: vec![],
+        };
+        let mut nodes = vec![
+            file(0, "b 10.mkv"),
+            dir("z"),
+            file(1, "b 2.mkv"),
+            file(2, "B.mkv"),
+            dir("Season 10"),
+            dir("Season 2"),
+        ];
+        nodes.sort();
+
+        let names: Vec<_> = nodes
+            .iter()
+            .map(|node| match node {
+                PlaylistNode::Dir { title, .. } => title.clone(),
+                PlaylistNode::File(_, name) => name.to_string_lossy().into(),
+            })
+            .collect();
+        assert_eq!(
+            names,
+            ["Season 2", "Season 10", "z", "B.mkv", "b 2.mkv", "b 10.mkv"]
+        );
+
+        // The order agrees with equality
+        for a in &nodes {
+            for b in &nodes {
+                assert_eq!(a.cmp(b) == Ordering::Equal, a == b);
+                assert_eq!(a.partial_cmp(b), Some(a.cmp(b)));
+            }
+        }
+        assert_eq!(file(0, "a.mkv").cmp(&file(1, "a.mkv")), Ordering::Less);
+    }
+
+    #[test]
+    fn file_roots_are_single_tracks() {
+        let dir = TempDir::new();
+        let roots = [dir.file("movies/a.mkv", &mkv(1000.0))];
+        dir.file("movies/b.mkv", &mkv(1000.0));
+
+        let (playlist, errors) = generate_playlist(&roots, &[], &ScanOptions::default());
+        assert!(errors.is_empty());
+        let tracks: Vec<_> = playlist.tracks().collect();
+        assert_eq!(tracks.len(), 1);
+        assert_eq!(tracks[0].location(), roots[0]);
+        assert!(matches!(playlist.nodes(), [PlaylistNode::File(0, _)]));
+    }
+
+    #[test]
+    fn nested_roots_are_scanned_once() {
+        let dir = TempDir::new();
+        let file = dir.file("movies/a.mkv", &mkv(1000.0));
+        dir.file("b.mkv", &mkv(1000.0));
+        let roots = [
+            dir.path().join("movies"),
+            dir.path().to_path_buf(),
+            dir.path().join("movies/../movies"),
+            dir.path().to_path_buf(),
+            file,
+        ];
+
+        let options = ScanOptions::default();
+        assert_eq!(
+            remove_nested_roots(&roots, &[], &options),
+            [dir.path().to_path_buf()]
+        );
+        assert_eq!(scanned_names(&roots, &options), ["a.mkv", "b.mkv"]);
+    }
+
+    #[test]
+    fn exclude_patterns_skip_matching_names() {
+        let dir = TempDir::new();
+        for path in [
+            "Movie.mkv",
+            "Movie-SAMPLE.mkv",
+            "Trailer.mkv",
+            "Movie trailer.mkv",
+        ] {
+            dir.file(path, &mkv(1000.0));
+        }
+        let roots = [dir.path().to_path_buf()];
+        let options = ScanOptions {
+            exclude_patterns: vec![Regex::new("sample|^trailer").unwrap()],
+            ..ScanOptions::default()
+        };
+        let (playlist, _) = generate_playlist(&roots, &[], &options);
+
+        let mut names: Vec<_> = playlist
+            .tracks()
+            .map(|track| track.location().file_name().unwrap().to_owned())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["Movie trailer.mkv", "Movie.mkv"]);
+    }
+
+    #[test]
+    fn deep_trees_are_walked_without_recursion() {
+        // As deep as the file system allows below the temporary directory, the walk runs with a
+        // stack that's too small to recurse once per directory
+        static DEPTH: usize = 2000;
+        static STACK_SIZE: usize = 256 * 1024;
+
+        let dir = TempDir::new();
+        let deep = dir.file(&format!("{}x.mkv", "d/".repeat(DEPTH)), &mkv(1000.0));
+        dir.file("y.mkv", &mkv(1000.0));
+        let root = dir.path().to_path_buf();
+
+        let walk = move || {
+            let roots = [root];
+            let scan = || generate_playlist(&roots, &[], &ScanOptions::default()).0;
+
+            let mut xml = vec![];
+            into_xml(&mut xml, scan(), &XmlOptions::default()).unwrap();
+            let mut merged = parse_xml(xml.as_slice(), None, None).unwrap();
+            merged.merge(scan());
+            merged.retain_tracks(|track| track.location() != roots[0].join("y.mkv"));
+            assert_eq!(merged.tracks().count(), 1);
+            let split = merged.split();
+            assert_eq!(split.len(), 1);
+
+            let mut out = vec![];
+            into_json(&mut out, scan()).unwrap();
+            into_m3u(&mut out, scan()).unwrap();
+            into_pls(&mut out, scan()).unwrap();
+            into_html(&mut out, scan(), "Deep").unwrap();
+        };
+        thread::Builder::new()
+            .stack_size(STACK_SIZE)
+            .spawn(walk)
+            .unwrap()
+            .join()
+            .unwrap();
+        assert!(deep.exists());
+    }
+}
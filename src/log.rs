@@ -0,0 +1,45 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// How much is reported on stderr while a playlist is generated.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[repr(u8)]
+pub enum Verbosity {
+    /// Only errors are reported.
+    Quiet,
+    /// Warnings are reported, e.g. about tracks outside of the directory locations are written
+    /// relative to.
+    Normal,
+    /// Every skipped file is reported along with the reason it was skipped.
+    Verbose,
+}
+
+static VERBOSITY: AtomicU8 = AtomicU8::new(Verbosity::Normal as u8);
+
+/// Sets the verbosity for everything that is reported afterwards.
+pub fn set_verbosity(verbosity: Verbosity) {
+    VERBOSITY.store(verbosity as u8, Ordering::Relaxed);
+}
+
+pub(crate) fn enabled(verbosity: Verbosity) -> bool {
+    VERBOSITY.load(Ordering::Relaxed) >= verbosity as u8
+}
+
+// Prints a warning on stderr, unless the verbosity is quiet.
+macro_rules! warning {
+    ($($arg:tt)*) => {
+        if $crate::log::enabled($crate::log::Verbosity::Normal) {
+            eprintln!("Warning: {}", format_args!($($arg)*));
+        }
+    };
+}
+
+// Prints a message on stderr if the verbosity is verbose.
+macro_rules! verbose {
+    ($($arg:tt)*) => {
+        if $crate::log::enabled($crate::log::Verbosity::Verbose) {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+pub(crate) use {verbose, warning};
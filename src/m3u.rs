@@ -0,0 +1,88 @@
+use std::io::{self, Write};
+
+use crate::{
+    xml::{option_secs, plain_location},
+    Playlist, PlaylistNode,
+};
+
+static M3U_HEADER: &str = "#EXTM3U";
+static EXTINF_TAG: &str = "#EXTINF:";
+// VLC reads options for the next entry from these lines
+static EXTVLCOPT_TAG: &str = "#EXTVLCOPT:";
+
+// M3U has no notion of nodes, so the tree is flattened in playlist order.
+fn nodes_into_m3u<W: Write>(
+    writer: &mut W,
+    playlist: &Playlist,
+    nodes: &[PlaylistNode],
+) -> io::Result<()> {
+    for idx in nodes.iter().flat_map(PlaylistNode::track_indices) {
+        let track = playlist.track(idx);
+        // A line break in the title would end the tag, the rest would be read as a location
+        let title = track.title().replace(['\r', '\n'], " ");
+        writeln!(
+            writer,
+            "{}{},{}",
+            EXTINF_TAG,
+            track.duration() / 1000,
+            title
+        )?;
+        if let Some(start) = track.start_time() {
+            writeln!(writer, "{}start-time={}", EXTVLCOPT_TAG, option_secs(start))?;
+        }
+        if let Some(stop) = track.stop_time() {
+            writeln!(writer, "{}stop-time={}", EXTVLCOPT_TAG, option_secs(stop))?;
+        }
+        writeln!(writer, "{}", plain_location(track))?;
+    }
+
+    Ok(())
+}
+
+pub fn into_m3u<W: Write>(writer: &mut W, playlist: Playlist) -> io::Result<()> {
+    writeln!(writer, "{}", M3U_HEADER)?;
+    nodes_into_m3u(writer, &playlist, playlist.nodes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PlaylistBuilder;
+
+    #[test]
+    fn line_breaks_in_titles_are_spaces() {
+        let mut builder = PlaylistBuilder::new();
+        builder.add_track("/media/a.mkv", "Line\r\nbreak\nhere", 90_000);
+
+        let mut m3u = vec![];
+        into_m3u(&mut m3u, builder.build()).unwrap();
+        let text = String::from_utf8(m3u).unwrap();
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(lines[1], "#EXTINF:90,Line  break here");
+        assert_eq!(lines.len(), 3);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn locations_are_file_uris() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt, path::Path};
+
+        let names = [OsStr::new("a\nb.mkv"), OsStr::from_bytes(b"c\xff.mkv")];
+        let mut builder = PlaylistBuilder::new();
+        for name in names {
+            builder.add_track(Path::new("/media").join(name), "Title", 90_000);
+        }
+
+        let mut out = vec![];
+        into_m3u(&mut out, builder.build()).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let locations: Vec<_> = text
+            .lines()
+            .filter(|line| line.starts_with("file:"))
+            .collect();
+        assert_eq!(
+            locations,
+            ["file:///media/a%0Ab.mkv", "file:///media/c%FF.mkv"]
+        );
+    }
+}
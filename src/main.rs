@@ -1,353 +1,1625 @@
 use std::{
-    collections::HashMap,
-    ffi::{OsStr, OsString},
+    collections::{hash_map::RandomState, BTreeMap, HashSet},
+    env,
+    error::Error,
+    ffi::OsString,
     fmt::Debug,
-    fs::File,
-    io::{BufReader, BufWriter},
-    path::{Path, PathBuf},
+    fs::{self, File},
+    hash::{BuildHasher, Hasher},
+    io::{self, BufReader, BufWriter, Write},
+    num::NonZeroUsize,
+    path::{self, Component, Path, PathBuf},
+    process, thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-use clap::Parser;
-use matroska::Matroska;
-use mp4::Mp4Reader;
-use walkdir::{DirEntry, WalkDir};
+use clap::{
+    error::ErrorKind, parser::ValueSource, Arg, ArgAction, ArgMatches, Command, CommandFactory,
+    Parser, ValueEnum,
+};
+use vlc_playlist_generator::{
+    expand_glob, format_duration, generate_playlist_cached, into_csv, into_html, into_json,
+    into_m3u, into_pls, into_xml, parse_xml, set_verbosity, DurationUnit, MetadataCache, Playlist,
+    PlaylistError, PlaylistNode, Regex, ScanError, ScanOptions, SortOrder, Track, Verbosity,
+    XmlOptions,
+};
+use walkdir::WalkDir;
 
-use crate::xml::into_xml;
+use crate::config::{parse_config, Value};
 
-mod xml;
+mod config;
+#[cfg(target_os = "linux")]
+mod inotify;
 
-struct Track {
-    location: PathBuf,
-    title: String,
-    duration: usize,
+static DEFAULT_CONFIG: &str = ".vlc-playlist-gen.toml";
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Format {
+    Xspf,
+    M3u8,
+    Pls,
+    Json,
+    Html,
+    Csv,
 }
 
-impl Track {
-    fn location(&self) -> &Path {
-        &self.location
+impl Format {
+    fn extension(self) -> &'static str {
+        match self {
+            Format::Xspf => "xspf",
+            Format::M3u8 => "m3u8",
+            Format::Pls => "pls",
+            Format::Json => "json",
+            Format::Html => "html",
+            Format::Csv => "csv",
+        }
     }
+}
 
-    fn title(&self) -> &str {
-        &self.title
-    }
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Sort {
+    Name,
+    Title,
+    Duration,
+    Path,
+    Mtime,
+}
 
-    fn duration(&self) -> usize {
-        self.duration
+impl From<Sort> for SortOrder {
+    fn from(sort: Sort) -> Self {
+        match sort {
+            Sort::Name => SortOrder::Name,
+            Sort::Title => SortOrder::Title,
+            Sort::Duration => SortOrder::Duration,
+            Sort::Path => SortOrder::Path,
+            Sort::Mtime => SortOrder::Modified,
+        }
     }
 }
 
-struct TrackList {
-    tracks: Vec<Track>,
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq, Debug)]
+enum OutputEncoding {
+    Utf8,
+    Ascii,
 }
 
-#[derive(PartialEq, Eq, Ord)]
-enum PlaylistNode {
-    Dir {
-        title: String,
-        nodes: Vec<PlaylistNode>,
-    },
-    File(usize, OsString),
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Indent {
+    Tabs,
+    Spaces,
 }
 
-impl PartialOrd for PlaylistNode {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        match (self, other) {
-            (PlaylistNode::Dir { .. }, PlaylistNode::File(_, _)) => Some(std::cmp::Ordering::Less),
-            (PlaylistNode::File(_, _), PlaylistNode::Dir { .. }) => {
-                Some(std::cmp::Ordering::Greater)
-            }
-            (
-                PlaylistNode::Dir { ref title, .. },
-                PlaylistNode::Dir {
-                    title: ref title2, ..
-                },
-            ) => title.partial_cmp(title2),
-            (PlaylistNode::File(_, p1), PlaylistNode::File(_, p2)) => p1.partial_cmp(p2),
+impl Indent {
+    // The indentation of a single level.
+    fn level(self, width: Option<usize>) -> String {
+        match self {
+            Indent::Tabs => "\t".repeat(width.unwrap_or(1)),
+            Indent::Spaces => " ".repeat(width.unwrap_or(4)),
         }
     }
 }
 
-impl PlaylistNode {
-    fn new(root: &Path, map: &PendingNodeMap) -> Self {
-        match map.nodes.get(root).unwrap() {
-            PendingNode::Dir { title, node_paths } => {
-                let mut nodes = Vec::with_capacity(node_paths.len());
-                for path in node_paths {
-                    let inner = PlaylistNode::new(path.as_path(), map);
-                    nodes.push(inner)
-                }
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Unit {
+    Milliseconds,
+    Seconds,
+}
 
-                PlaylistNode::Dir {
-                    title: title.clone(),
-                    nodes,
-                }
-            }
-            PendingNode::File(idx, s) => PlaylistNode::File(*idx, s.clone()),
+impl From<Unit> for DurationUnit {
+    fn from(unit: Unit) -> Self {
+        match unit {
+            Unit::Milliseconds => DurationUnit::Milliseconds,
+            Unit::Seconds => DurationUnit::Seconds,
         }
     }
+}
 
-    fn sort(&mut self) {
-        match self {
-            Self::Dir { ref mut nodes, .. } => {
-                nodes.sort();
-                for n in nodes {
-                    n.sort();
-                }
-            }
-            _ => (),
+// The directory locations are written relative to, with `--portable` the directory the playlist
+// is written to.
+fn relative_to(args: &Args) -> Option<PathBuf> {
+    if !args.portable {
+        return args.relative_to.clone();
+    }
+
+    let output = args.output.as_deref()?;
+    let dir = if args.split {
+        output
+    } else {
+        output
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+            .unwrap_or(Path::new("."))
+    };
+    dir.canonicalize().or_else(|_| path::absolute(dir)).ok()
+}
+
+fn write_playlist<W: Write>(writer: &mut W, playlist: Playlist, args: &Args) -> io::Result<()> {
+    match args.format {
+        Format::Xspf => {
+            let options = XmlOptions {
+                title: args.title.clone(),
+                relative_to: relative_to(args),
+                base_url: args.base_url.clone(),
+                duration_unit: args.duration_unit.into(),
+                compact: args.compact,
+                indent: args.indent.level(args.indent_width),
+                vlc_extensions: !args.no_vlc_extensions,
+                flatten: args.flatten,
+                escape_non_ascii: args.output_encoding == OutputEncoding::Ascii,
+            };
+            into_xml(writer, playlist, &options)
         }
+        Format::M3u8 => into_m3u(writer, playlist),
+        Format::Pls => into_pls(writer, playlist),
+        Format::Json => into_json(writer, playlist),
+        Format::Html => into_html(writer, playlist, &args.title),
+        Format::Csv => into_csv(writer, playlist),
     }
 }
 
-struct Playlist {
-    track_list: TrackList,
-    nodes: Vec<PlaylistNode>,
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    #[arg(
+        short,
+        long,
+        help = "Starting points for the scanner, glob patterns are expanded
+Each root is recursively scanned for mkv, webm, mp4, m4v, m4b, mov, 3gp, avi, ts, m2ts, mp3, flac, m4a and ogg files"
+    )]
+    root: Vec<PathBuf>,
+
+    #[arg(short, long, help = "Skipped directories, glob patterns are expanded")]
+    skip: Vec<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Skip directories with this name at any depth in every root, e.g. Extras"
+    )]
+    skip_name: Vec<OsString>,
+
+    #[arg(
+        long,
+        help = "File with gitignore-style patterns of files and directories to skip
+the patterns are relative to each root, e.g. *.sample.mkv or /Movies/Extras/"
+    )]
+    ignore_file: Vec<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Don't skip directories with a .nomedia file or honor the patterns in .ignore files"
+    )]
+    no_ignore: bool,
+
+    #[arg(
+        short,
+        long,
+        help = "File to write playlist to
+if no file or - is provided the playlist is printed to stdout"
+    )]
+    output: Option<PathBuf>,
+
+    #[arg(
+        short,
+        long,
+        value_enum,
+        default_value_t = Format::Xspf,
+        help = "Format of the generated playlist"
+    )]
+    format: Format,
+
+    #[arg(
+        long,
+        help = "Maximum depth to scan each root for files
+a depth of 1 only includes the files directly in each root"
+    )]
+    max_depth: Option<usize>,
+
+    #[arg(
+        long,
+        value_parser = parse_duration,
+        help = "Skip tracks shorter than this duration, e.g. 30s, 5m or 1h30m
+tracks with an unknown duration have a duration of 0"
+    )]
+    min_duration: Option<u64>,
+
+    #[arg(
+        long,
+        value_parser = parse_duration,
+        help = "Skip tracks longer than this duration, e.g. 30s, 5m or 1h30m"
+    )]
+    max_duration: Option<u64>,
+
+    #[arg(
+        long,
+        value_parser = parse_time_point,
+        help = "Skip files modified before this time, either an age like 7d, 2w or 12h
+or a date like 2024-01-01, which is midnight UTC"
+    )]
+    newer_than: Option<SystemTime>,
+
+    #[arg(
+        long,
+        value_parser = parse_time_point,
+        help = "Skip files modified at or after this time, in the same forms as --newer-than"
+    )]
+    older_than: Option<SystemTime>,
+
+    #[arg(
+        long,
+        value_parser = parse_size,
+        help = "Skip files smaller than this size, e.g. 50M to leave out samples
+sizes are in bytes or have a K, M, G or T suffix for multiples of 1024"
+    )]
+    min_file_size: Option<u64>,
+
+    #[arg(
+        long,
+        help = "Add the media files in zip archives, each archive is listed as a directory
+their duration is unknown, as they aren't extracted"
+    )]
+    scan_archives: bool,
+
+    #[arg(
+        long,
+        help = "Stop scanning once this many tracks were included, skipped files don't count"
+    )]
+    limit: Option<usize>,
+
+    #[arg(
+        long,
+        help = "Skip tracks whose duration is unknown, which are often broken files"
+    )]
+    skip_unknown_duration: bool,
+
+    #[arg(
+        long,
+        help = "Follow symbolic links while scanning, links that form a loop are skipped
+the metadata of a track is read from the target of the link"
+    )]
+    follow_symlinks: bool,
+
+    #[arg(
+        long,
+        help = "Include hidden files and directories, whose name starts with a dot"
+    )]
+    hidden: bool,
+
+    #[arg(
+        short,
+        long,
+        default_value = "Media Library",
+        help = "Title of the playlist"
+    )]
+    title: String,
+
+    #[arg(
+        long,
+        help = "Write the locations of tracks relative to this directory
+tracks outside of it keep an absolute location"
+    )]
+    relative_to: Option<PathBuf>,
+
+    #[arg(
+        long,
+        requires = "relative_to",
+        help = "Write the locations of tracks in the --relative-to directory as URLs below this one
+e.g. http://nas.local/media to stream the library from a server"
+    )]
+    base_url: Option<String>,
+
+    #[arg(
+        long,
+        help = "File with additional roots, one per line
+blank lines and lines starting with # are ignored"
+    )]
+    roots_from: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Scan the roots and print a summary instead of writing the playlist"
+    )]
+    dry_run: bool,
+
+    #[arg(
+        long,
+        help = "Read metadata with ffprobe, which adds support for flv files
+the built-in parsers are used if ffprobe is not installed"
+    )]
+    use_ffprobe: bool,
+
+    #[arg(
+        long,
+        help = "File to cache metadata in between runs
+files that have not been modified since the previous run are not parsed again"
+    )]
+    cache: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Only scan files with this extension instead of all supported formats"
+    )]
+    include_ext: Vec<String>,
+
+    #[arg(long, help = "Never scan files with this extension")]
+    exclude_ext: Vec<String>,
+
+    #[arg(
+        long,
+        value_parser = Regex::new,
+        help = "Skip files whose name matches this regular expression, ignoring case, e.g. 'sample|trailer'
+files that match are skipped regardless of their extension"
+    )]
+    exclude_pattern: Vec<Regex>,
+
+    #[arg(
+        long,
+        help = "Attach subtitle files with the same name as a media file to its track"
+    )]
+    subtitles: bool,
+
+    #[arg(
+        long,
+        help = "Use an image with the same name as a media file or poster, cover or folder.jpg
+in its directory as the cover art of its track"
+    )]
+    art: bool,
+
+    #[arg(
+        long,
+        help = "Use the title in an NFO file with the same name as a media file or movie.nfo
+in its directory as the title of its track"
+    )]
+    nfo_titles: bool,
+
+    #[arg(
+        long,
+        help = "Show progress on stderr while scanning, if it is a terminal"
+    )]
+    progress: bool,
+
+    #[arg(
+        long,
+        help = "Existing XSPF playlist to merge with
+its tracks are kept and only files that aren't part of it yet are added"
+    )]
+    merge: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Check that the files of an existing XSPF playlist still exist instead of scanning
+missing files are reported and the exit code is 2 if there are any"
+    )]
+    check: Option<PathBuf>,
+
+    #[arg(
+        long,
+        requires = "check",
+        help = "Write the checked playlist without its missing files to the output"
+    )]
+    prune_missing: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = Sort::Name,
+        help = "Order of the files in each directory, mtime lists the newest files first
+directories are sorted by name, or by their newest file with mtime"
+    )]
+    sort: Sort,
+
+    #[arg(
+        long,
+        help = "Reverse the order of files and directories, directories are still listed first"
+    )]
+    reverse: bool,
+
+    #[arg(
+        long,
+        help = "Shuffle the files and directories in each directory instead of sorting them
+the seed is printed so the same order can be generated again with --seed"
+    )]
+    shuffle: bool,
+
+    #[arg(
+        long,
+        help = "List the roots in the order they're given instead of sorting them with their contents"
+    )]
+    preserve_root_order: bool,
+
+    #[arg(
+        long,
+        requires = "shuffle",
+        help = "Seed of --shuffle, the same seed gives the same order"
+    )]
+    seed: Option<u64>,
+
+    #[arg(
+        long,
+        help = "Add files that are found more than once, e.g. through overlapping roots, as a single track"
+    )]
+    dedupe: bool,
+
+    #[arg(
+        long,
+        help = "Remove release tags like [1080p] or REPACK from the titles of directories
+dots and underscores are replaced with spaces"
+    )]
+    clean_titles: bool,
+
+    #[arg(
+        long,
+        help = "Append a counter to titles that are the same as another in their directory
+e.g. the second Extras becomes Extras (2)"
+    )]
+    disambiguate: bool,
+
+    #[arg(
+        long,
+        help = "Use the file name without extension as the title of files without an embedded title"
+    )]
+    strip_extension_from_title: bool,
+
+    #[arg(
+        long,
+        requires = "strip_extension_from_title",
+        help = "Replace dots and underscores with spaces in titles from --strip-extension-from-title"
+    )]
+    title_spaces: bool,
+
+    #[arg(
+        long,
+        visible_alias = "absolute",
+        help = "Resolve roots to absolute paths without symbolic links before scanning
+so the track locations don't depend on the working directory"
+    )]
+    canonicalize: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = Unit::Milliseconds,
+        help = "Unit of the durations in an XSPF playlist
+VLC expects milliseconds"
+    )]
+    duration_unit: Unit,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = Indent::Tabs,
+        help = "Indentation of an XSPF playlist"
+    )]
+    indent: Indent,
+
+    #[arg(
+        long,
+        help = "Number of tabs or spaces per level of indentation
+defaults to one tab or four spaces"
+    )]
+    indent_width: Option<usize>,
+
+    #[arg(
+        long,
+        help = "Write an XSPF playlist without indentation and line breaks"
+    )]
+    compact: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = OutputEncoding::Utf8,
+        help = "Encoding of titles in an XSPF playlist, ascii writes other characters as references
+like &#233; for players that can't read UTF-8"
+    )]
+    output_encoding: OutputEncoding,
+
+    #[arg(
+        long,
+        help = "Write standard XSPF without the VLC extensions for other players
+the playlist is a flat list of tracks without chapters and track options"
+    )]
+    no_vlc_extensions: bool,
+
+    #[arg(
+        long,
+        help = "Write an XSPF playlist without the directory tree, e.g. to shuffle all tracks together"
+    )]
+    flatten: bool,
+
+    #[arg(
+        long,
+        requires = "output",
+        help = "Write a playlist for each root to the output directory, named after the root"
+    )]
+    split: bool,
+
+    #[arg(
+        long,
+        requires = "output",
+        conflicts_with = "relative_to",
+        help = "Write the locations of tracks relative to the directory of the output
+so the playlist keeps working when it's copied together with the library"
+    )]
+    portable: bool,
+
+    #[arg(
+        long,
+        requires = "output",
+        conflicts_with = "dry_run",
+        help = "Keep running and regenerate the playlist when files below the roots change
+On Linux changes are reported by inotify, elsewhere, or if there are too many directories to watch,
+all roots are walked every --watch-interval, which reads the metadata of every file and directory"
+    )]
+    watch: bool,
+
+    #[arg(
+        long,
+        value_parser = parse_duration,
+        default_value = "2s",
+        help = "How long changes must settle before the playlist is regenerated in watch mode
+Also how often the roots are walked when they're polled for changes"
+    )]
+    watch_interval: u64,
+
+    #[arg(
+        long,
+        requires = "watch",
+        help = "Poll the roots for changes in watch mode instead of using inotify
+Needed for network shares, which don't report changes made by other machines"
+    )]
+    watch_poll: bool,
+
+    #[arg(
+        long,
+        value_parser = parse_duration,
+        help = "Skip files whose metadata can't be read within this time, e.g. 10s
+useful for network shares where a single file can stall the scan"
+    )]
+    file_timeout: Option<u64>,
+
+    #[arg(
+        long,
+        help = "Number of files whose metadata is read at the same time, default is the number of cores
+e.g. 2 for spinning disks or network shares that slow down with parallel reads"
+    )]
+    threads: Option<NonZeroUsize>,
+
+    #[arg(short, long, conflicts_with = "verbose", help = "Only report errors")]
+    quiet: bool,
+
+    #[arg(
+        short,
+        long,
+        help = "Report every skipped file and the reason it was skipped"
+    )]
+    verbose: bool,
+
+    #[arg(
+        long,
+        help = "TOML file with defaults for the other options, e.g. root = [\"/media\"]
+.vlc-playlist-gen.toml in the working directory is used if it exists"
+    )]
+    config: Option<PathBuf>,
 }
 
-impl Playlist {
-    fn tracks(&self) -> impl Iterator<Item = &Track> {
-        self.track_list.tracks.iter()
+// Parses durations like `90`, `30s`, `1h30m` or `500ms` into milliseconds, a number without a unit
+// is in seconds.
+fn parse_duration(s: &str) -> Result<u64, String> {
+    let invalid = || format!("invalid duration `{}`", s);
+    if s.is_empty() {
+        return Err(invalid());
     }
 
-    fn nodes(&self) -> &[PlaylistNode] {
-        &self.nodes
+    if let Ok(secs) = s.parse::<u64>() {
+        return secs.checked_mul(1000).ok_or_else(invalid);
     }
+
+    let mut millis: u64 = 0;
+    let mut rest = s;
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .ok_or_else(invalid)?;
+        let value: u64 = rest[..digits].parse().map_err(|_| invalid())?;
+        rest = &rest[digits..];
+
+        let unit_len = rest
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let factor = match &rest[..unit_len] {
+            "h" => 3_600_000,
+            "m" => 60_000,
+            "s" => 1000,
+            "ms" => 1,
+            _ => return Err(invalid()),
+        };
+        rest = &rest[unit_len..];
+
+        millis = value
+            .checked_mul(factor)
+            .and_then(|v| millis.checked_add(v))
+            .ok_or_else(invalid)?;
+    }
+
+    Ok(millis)
 }
 
-enum PendingNode {
-    Dir {
-        title: String,
-        node_paths: Vec<PathBuf>,
-    },
-    File(usize, OsString),
+// Parses sizes like `4096`, `500K` or `1.5G` into bytes, suffixes are multiples of 1024 and may be
+// followed by `B` or `iB`.
+fn parse_size(s: &str) -> Result<u64, String> {
+    let invalid = || format!("invalid size `{}`", s);
+
+    let upper = s.to_ascii_uppercase();
+    let number = upper
+        .strip_suffix("IB")
+        .or_else(|| upper.strip_suffix('B'))
+        .unwrap_or(&upper);
+    let (number, factor) = match number.char_indices().last() {
+        Some((i, 'K')) => (&number[..i], 1u64 << 10),
+        Some((i, 'M')) => (&number[..i], 1 << 20),
+        Some((i, 'G')) => (&number[..i], 1 << 30),
+        Some((i, 'T')) => (&number[..i], 1 << 40),
+        _ => (number, 1),
+    };
+
+    let number: f64 = number.trim().parse().map_err(|_| invalid())?;
+    if !number.is_finite() || number < 0.0 {
+        return Err(invalid());
+    }
+    Ok((number * factor as f64).round() as u64)
 }
 
-struct PendingNodeMap {
-    nodes: HashMap<PathBuf, PendingNode>,
-    roots: Vec<PathBuf>,
+// Parses a date like `2024-01-01` into the number of days since the Unix epoch.
+fn parse_date(s: &str) -> Option<i64> {
+    let mut parts = s.splitn(3, '-');
+    let year: i64 = parts.next()?.parse().ok()?;
+    let month: i64 = parts.next()?.parse().ok()?;
+    let day: i64 = parts.next()?.parse().ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    // Days from civil, counting years from March so the leap day is the last day of a year
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    Some(era * 146_097 + day_of_era - 719_468)
 }
 
-impl PendingNodeMap {
-    fn new(roots: Vec<PathBuf>) -> Self {
-        let mut nodes = HashMap::new();
+// Parses the time for `--newer-than` and `--older-than`, either an age before now like `7d`, `2w`
+// or any duration accepted by `parse_duration`, or a date like `2024-01-01` at midnight UTC.
+fn parse_time_point(s: &str) -> Result<SystemTime, String> {
+    let invalid = || format!("invalid age or date `{}`", s);
 
-        for path in roots.iter() {
-            let filename = path
-                .file_name()
-                .unwrap_or(OsStr::new(""))
-                .to_str()
-                .unwrap_or("?");
+    if s.contains('-') {
+        let days = parse_date(s).ok_or_else(invalid)?;
+        let secs = Duration::from_secs(days.unsigned_abs() * 86_400);
+        return if days >= 0 {
+            UNIX_EPOCH.checked_add(secs)
+        } else {
+            UNIX_EPOCH.checked_sub(secs)
+        }
+        .ok_or_else(invalid);
+    }
 
-            nodes.insert(
-                path.clone(),
-                PendingNode::Dir {
-                    title: filename.into(),
-                    node_paths: vec![],
-                },
-            );
+    let age = match s.strip_suffix(['d', 'w']) {
+        Some(count) => {
+            let days = if s.ends_with('w') { 7 } else { 1 };
+            count
+                .parse::<u64>()
+                .ok()
+                .and_then(|count| count.checked_mul(days * 86_400_000))
+                .ok_or_else(invalid)?
         }
+        None => parse_duration(s).map_err(|_| invalid())?,
+    };
 
-        PendingNodeMap { nodes, roots }
+    SystemTime::now()
+        .checked_sub(Duration::from_millis(age))
+        .ok_or_else(invalid)
+}
+fn count_dirs(nodes: &[PlaylistNode]) -> usize {
+    let mut count = 0;
+    let mut stack: Vec<&PlaylistNode> = nodes.iter().collect();
+    while let Some(node) = stack.pop() {
+        if let PlaylistNode::Dir { ref nodes, .. } = node {
+            count += 1;
+            stack.extend(nodes);
+        }
     }
+    count
+}
 
-    fn node_for_dir_of(&mut self, path: &Path) -> &mut PendingNode {
-        let mut ancestors = path.ancestors().skip(1);
-        let parent_path = ancestors.next().unwrap();
-        if !self.nodes.contains_key(parent_path) {
-            let name = parent_path.file_name().unwrap_or(OsStr::new("?"));
-            self.nodes.insert(
-                parent_path.to_path_buf(),
-                PendingNode::Dir {
-                    title: name.to_str().unwrap_or("?").into(),
-                    node_paths: vec![],
-                },
-            );
+// Formats a size in bytes with the largest binary unit it has at least one of, e.g. `1.5 GiB`.
+fn format_size(bytes: u64) -> String {
+    let mut size = bytes as f64;
+    let mut unit = "bytes";
+    for next in ["KiB", "MiB", "GiB", "TiB"] {
+        if size < 1024.0 {
+            break;
+        }
+        size /= 1024.0;
+        unit = next;
+    }
+
+    if unit == "bytes" {
+        format!("{} {}", bytes, unit)
+    } else {
+        format!("{:.1} {}", size, unit)
+    }
+}
 
-            self.node_for_dir_of(parent_path);
+fn print_summary(playlist: &Playlist) {
+    let mut per_format = BTreeMap::new();
+    let mut total_duration = 0;
+    let mut total_size = 0;
+    for track in playlist.tracks() {
+        *per_format
+            .entry(track.format().unwrap_or_default())
+            .or_insert(0) += 1;
+        total_duration += track.duration();
+        total_size += track.size().unwrap_or(0);
+    }
+
+    println!("Files: {}", playlist.tracks().count());
+    for (format, count) in per_format {
+        println!("  {}: {}", format, count);
+    }
+    println!("Total duration: {}", format_duration(total_duration));
+    println!("Total size: {}", format_size(total_size));
+    println!("Directories: {}", count_dirs(playlist.nodes()));
+}
+
+// Reads one root per line, blank lines and lines starting with `#` are ignored.
+fn read_roots(path: &Path) -> io::Result<Vec<PathBuf>> {
+    let roots = fs::read_to_string(path)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|root| expand_home(Path::new(root)))
+        .collect();
+
+    Ok(roots)
+}
+
+// The home directory of another user, from the user database.
+#[cfg(unix)]
+fn user_home(user: &str) -> Option<PathBuf> {
+    fs::read_to_string("/etc/passwd")
+        .ok()?
+        .lines()
+        .map(|line| line.split(':').collect::<Vec<_>>())
+        .find(|fields| fields.len() > 5 && fields[0] == user)
+        .map(|fields| PathBuf::from(fields[5]))
+}
+
+#[cfg(not(unix))]
+fn user_home(_user: &str) -> Option<PathBuf> {
+    None
+}
+
+// Expands a leading `~` or `~user` to the home directory like a shell does, paths from the
+// configuration file or a launcher never pass through one. Paths whose home directory is unknown
+// are returned as they are.
+fn expand_home(path: &Path) -> PathBuf {
+    let mut components = path.components();
+    let Some(Component::Normal(first)) = components.next() else {
+        return path.to_path_buf();
+    };
+    let Some(user) = first.to_str().and_then(|first| first.strip_prefix('~')) else {
+        return path.to_path_buf();
+    };
+
+    let home = if user.is_empty() {
+        env::var_os("HOME")
+            .or_else(|| env::var_os("USERPROFILE"))
+            .map(PathBuf::from)
+    } else {
+        user_home(user)
+    };
+
+    match home {
+        Some(home) => home.join(components.as_path()),
+        None => path.to_path_buf(),
+    }
+}
+
+// Removes the roots that don't exist or aren't a file or directory, returns `false` if any were
+// removed.
+fn check_roots(roots: &mut Vec<PathBuf>) -> bool {
+    let count = roots.len();
+    roots.retain(|root| {
+        if root.is_dir() || root.is_file() {
+            return true;
+        }
+
+        if root.exists() {
+            eprintln!("Error: root {} is not a file or directory", root.display());
+        } else {
+            eprintln!("Error: root {} does not exist", root.display());
         }
+        false
+    });
 
-        let node = self.nodes.get_mut(parent_path).unwrap();
-        match node {
-            PendingNode::Dir {
-                ref mut node_paths, ..
-            } => node_paths.push(path.into()),
-            _ => unreachable!(),
+    roots.len() == count
+}
+
+// Returns `false` if any of the roots can't be scanned, the playlist is still generated from the
+// other roots.
+fn run(args: Args) -> Result<bool, PlaylistError> {
+    if args.quiet {
+        set_verbosity(Verbosity::Quiet);
+    } else if args.verbose {
+        set_verbosity(Verbosity::Verbose);
+    }
+
+    if let Some(ref path) = args.check {
+        return check_playlist(path, &args);
+    }
+
+    let mut ignore_patterns = vec![];
+    for path in &args.ignore_file {
+        let contents =
+            fs::read_to_string(path).map_err(|e| PlaylistError::ReadIgnoreFile(path.clone(), e))?;
+        ignore_patterns.extend(contents.lines().map(String::from));
+    }
+
+    let shuffle = args.shuffle.then(|| {
+        args.seed.unwrap_or_else(|| {
+            let seed = RandomState::new().build_hasher().finish();
+            if !args.quiet {
+                eprintln!("Shuffling with seed {}", seed);
+            }
+            seed
+        })
+    });
+
+    let options = ScanOptions {
+        max_depth: args.max_depth,
+        min_duration: args.min_duration,
+        max_duration: args.max_duration,
+        skip_unknown_duration: args.skip_unknown_duration,
+        skip_names: args.skip_name.clone(),
+        follow_symlinks: args.follow_symlinks,
+        hidden: args.hidden,
+        use_ffprobe: args.use_ffprobe,
+        include_extensions: (!args.include_ext.is_empty()).then(|| args.include_ext.clone()),
+        exclude_extensions: args.exclude_ext.clone(),
+        exclude_patterns: args.exclude_pattern.clone(),
+        subtitles: args.subtitles,
+        art: args.art,
+        nfo_titles: args.nfo_titles,
+        progress: args.progress,
+        sort: args.sort.into(),
+        reverse: args.reverse,
+        shuffle,
+        preserve_root_order: args.preserve_root_order,
+        dedupe: args.dedupe,
+        clean_titles: args.clean_titles,
+        disambiguate_titles: args.disambiguate,
+        strip_title_extension: args.strip_extension_from_title,
+        title_spaces: args.title_spaces,
+        canonicalize: args.canonicalize,
+        ignore_patterns,
+        no_ignore: args.no_ignore,
+        file_timeout: args.file_timeout.map(Duration::from_millis),
+        threads: args.threads,
+        limit: args.limit,
+        min_file_size: args.min_file_size,
+        scan_archives: args.scan_archives,
+        newer_than: args.newer_than,
+        older_than: args.older_than,
+    };
+
+    let mut roots = args.root.clone();
+    if let Some(ref path) = args.roots_from {
+        roots.extend(read_roots(path).map_err(|e| PlaylistError::ReadRoots(path.clone(), e))?);
+    }
+
+    // A pattern that matches nothing is reported like a root that doesn't exist
+    let mut matched = true;
+    let mut roots: Vec<_> = roots
+        .iter()
+        .flat_map(|root| {
+            let expanded = expand_glob(root);
+            if expanded.is_empty() {
+                eprintln!("Error: root pattern {} matches nothing", root.display());
+                matched = false;
+            }
+            expanded
+        })
+        .collect();
+    // Roots can only be compared to the directory of the output once both are resolved in the
+    // same way
+    if args.portable {
+        for root in roots.iter_mut() {
+            if let Ok(resolved) = root.canonicalize().or_else(|_| path::absolute(&root)) {
+                *root = resolved;
+            }
         }
+    }
+    let roots_valid = check_roots(&mut roots) && matched;
+    let skip: Vec<_> = args
+        .skip
+        .iter()
+        .flat_map(|skip| {
+            let expanded = expand_glob(skip);
+            if expanded.is_empty() && !args.quiet {
+                eprintln!("Warning: skip pattern {} matches nothing", skip.display());
+            }
+            expanded
+        })
+        .collect();
+
+    // Without a cache file the cache is only kept in memory, which speeds up watch mode
+    let mut cache = args
+        .cache
+        .as_ref()
+        .map(MetadataCache::load)
+        .unwrap_or_default();
+    let (playlist, errors) = build_playlist(&args, &options, &roots, &skip, &mut cache)?;
+    report_scan_errors(&errors, &args);
+    let complete = roots_valid && errors.is_empty();
 
-        node
+    if args.dry_run {
+        print_summary(&playlist);
+        return Ok(complete);
     }
 
-    fn push_file(&mut self, path: &Path, index: usize) {
-        self.node_for_dir_of(path);
-        self.nodes.insert(
-            path.into(),
-            PendingNode::File(index, path.file_name().unwrap().to_os_string()),
+    write_output(playlist, &args)?;
+
+    if args.watch {
+        watch(&args, &options, &roots, &skip, &mut cache)?;
+    }
+
+    Ok(complete)
+}
+
+// Reads a playlist written with the same options, relative locations are resolved against the
+// directory they were written relative to, which is the playlist's own with `--portable`.
+fn read_playlist(path: &Path, args: &Args) -> io::Result<Playlist> {
+    let file = File::open(path)?;
+    let base = match args.relative_to {
+        Some(ref dir) if !args.portable => Some(dir.as_path()),
+        _ => path.parent(),
+    };
+    parse_xml(BufReader::new(file), base, args.base_url.as_deref())
+}
+
+// Reports the tracks of a playlist whose file no longer exists, and writes the playlist without
+// them with `--prune-missing`. Returns `false` if any of them are missing.
+fn check_playlist(path: &Path, args: &Args) -> Result<bool, PlaylistError> {
+    let read_check = |e| PlaylistError::ReadCheck(path.to_path_buf(), e);
+    let mut playlist = read_playlist(path, args).map_err(read_check)?;
+
+    // Files in an archive exist as long as the archive does
+    let exists = |track: &Track| track.archive().unwrap_or(track.location()).is_file();
+    let missing: Vec<_> = playlist.tracks().filter(|track| !exists(track)).collect();
+    if !args.quiet {
+        for track in &missing {
+            eprintln!("Warning: {} does not exist", track.location().display());
+        }
+        eprintln!(
+            "{} of {} tracks are missing",
+            missing.len(),
+            playlist.tracks().count()
         );
     }
 
-    fn into_nodes(self) -> Vec<PlaylistNode> {
-        let mut nodes = Vec::with_capacity(self.roots.len());
+    let complete = missing.is_empty();
+    if args.prune_missing {
+        playlist.retain_tracks(exists);
+        write_output(playlist, args)?;
+    }
 
-        for root in &self.roots {
-            let node = PlaylistNode::new(root.as_path(), &self);
-            nodes.push(node)
+    Ok(complete)
+}
+
+// Lists the files and directories that couldn't be scanned with `--verbose`, otherwise only how
+// many there were.
+fn report_scan_errors(errors: &[ScanError], args: &Args) {
+    if errors.is_empty() || args.quiet {
+        return;
+    }
+
+    if args.verbose {
+        eprintln!("{} files or directories can't be scanned:", errors.len());
+        for error in errors {
+            eprintln!("  {}", error);
         }
+    } else {
+        eprintln!(
+            "Warning: {} files or directories can't be scanned, use --verbose to list them",
+            errors.len()
+        );
+    }
+}
 
-        nodes
+fn build_playlist(
+    args: &Args,
+    options: &ScanOptions,
+    roots: &[PathBuf],
+    skip: &[PathBuf],
+    cache: &mut MetadataCache,
+) -> Result<(Playlist, Vec<ScanError>), PlaylistError> {
+    let (mut playlist, errors) = generate_playlist_cached(roots, skip, options, cache);
+    if let Some(ref path) = args.cache {
+        cache
+            .save(path)
+            .map_err(|e| PlaylistError::WriteCache(path.clone(), e))?;
     }
+
+    if let Some(ref path) = args.merge {
+        let read_merge = |e| PlaylistError::ReadMerge(path.clone(), e);
+        let mut existing = read_playlist(path, args).map_err(read_merge)?;
+        existing.merge(playlist);
+        playlist = existing;
+    }
+
+    Ok((playlist, errors))
+}
+
+// Writes a playlist to a file or stdout, which get the same bytes.
+fn write_buffered<W: Write>(writer: W, playlist: Playlist, args: &Args) -> io::Result<()> {
+    let mut writer = BufWriter::new(writer);
+    write_playlist(&mut writer, playlist, args)?;
+    writer.flush()
 }
 
-fn mkv_meta<P: AsRef<Path>>(path: P) -> Option<Track> {
-    let path = path.as_ref();
-    let file = File::open(path).ok()?;
-    let mkv = Matroska::open(file).ok()?;
+// The playlist is written to a temporary file next to the output first, so the output is never
+// left half-written.
+fn write_file(path: &Path, playlist: Playlist, args: &Args) -> Result<(), PlaylistError> {
+    let mut temp_name = OsString::from(".");
+    temp_name.push(path.file_name().unwrap_or_default());
+    temp_name.push(format!(".{}.tmp", process::id()));
+    let temp_path = path.with_file_name(temp_name);
 
-    let duration = mkv.info.duration;
-    let title = mkv.info.title;
+    let file =
+        File::create(&temp_path).map_err(|e| PlaylistError::CreateOutput(path.to_path_buf(), e))?;
+    let written = write_buffered(file, playlist, args)
+        .map_err(PlaylistError::Write)
+        .and_then(|_| {
+            fs::rename(&temp_path, path)
+                .map_err(|e| PlaylistError::ReplaceOutput(path.to_path_buf(), e))
+        });
+
+    if written.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    written
+}
 
-    let track = Track {
-        location: path.to_path_buf(),
-        duration: duration.map(|d| d.as_millis() as usize).unwrap_or(0),
-        title: title.unwrap_or_else(|| {
-            path.file_name()
-                .unwrap()
-                .to_str()
-                .unwrap_or("<No title available>")
-                .into()
-        }),
+// The name a root is written to with `--split`, without extension. Characters that can't be part of
+// a file name are replaced.
+fn split_file_stem(node: &PlaylistNode) -> String {
+    let name = match node {
+        PlaylistNode::Dir { ref title, .. } => title.clone(),
+        PlaylistNode::File(_, ref name) => Path::new(name)
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned(),
     };
 
-    Some(track)
+    let stem: String = name
+        .trim_matches('/')
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c => c,
+        })
+        .collect();
+    if stem.is_empty() {
+        "root".into()
+    } else {
+        stem
+    }
+}
+
+fn write_output(playlist: Playlist, args: &Args) -> Result<(), PlaylistError> {
+    match args.output {
+        Some(ref dir) if args.split => {
+            fs::create_dir_all(dir).map_err(|e| PlaylistError::CreateOutput(dir.clone(), e))?;
+
+            let mut names = HashSet::new();
+            for playlist in playlist.split() {
+                let stem = split_file_stem(&playlist.nodes()[0]);
+                let ext = args.format.extension();
+                let mut name = format!("{}.{}", stem, ext);
+                // Roots with the same name are numbered
+                let mut n = 2;
+                while !names.insert(name.clone()) {
+                    name = format!("{} ({}).{}", stem, n, ext);
+                    n += 1;
+                }
+
+                write_file(&dir.join(name), playlist, args)?;
+            }
+        }
+        Some(ref path) => write_file(path, playlist, args)?,
+        None => write_buffered(io::stdout().lock(), playlist, args)?,
+    }
+
+    Ok(())
 }
 
-fn mp4_meta<P: AsRef<Path>>(path: P) -> Option<Track> {
-    let path = path.as_ref();
-    let file = File::open(path).ok()?;
-    let size = file.metadata().ok()?.len();
-    let reader = BufReader::new(file);
+// The path, modification time and size of every entry below the roots. Renaming, adding or
+// removing an entry changes the modification time of its directory.
+fn snapshot(roots: &[PathBuf], follow_symlinks: bool) -> Vec<(PathBuf, Option<SystemTime>, u64)> {
+    roots
+        .iter()
+        .flat_map(|root| WalkDir::new(root).follow_links(follow_symlinks))
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let meta = entry.metadata().ok()?;
+            Some((entry.into_path(), meta.modified().ok(), meta.len()))
+        })
+        .collect()
+}
 
-    let mp4 = Mp4Reader::read_header(reader, size).ok()?;
+// Regenerates the playlist when the files below the roots have changed and settled, until the
+// process is stopped or an error occurs. On Linux changes are reported by inotify, elsewhere, with
+// `--watch-poll` or if that's not possible, e.g. because there are more directories than it can
+// watch, the roots are polled.
+fn watch(
+    args: &Args,
+    options: &ScanOptions,
+    roots: &[PathBuf],
+    skip: &[PathBuf],
+    cache: &mut MetadataCache,
+) -> Result<(), PlaylistError> {
+    if !args.watch_poll {
+        #[cfg(target_os = "linux")]
+        {
+            let e = watch_events(args, options, roots, skip, cache)?;
+            if !args.quiet {
+                eprintln!(
+                    "Warning: can't watch for file system events, polling the roots instead: {}",
+                    e
+                );
+            }
+        }
+    }
 
-    let duration = mp4.duration();
+    poll(args, options, roots, skip, cache)
+}
 
-    let track = Track {
-        location: path.to_path_buf(),
-        duration: duration.as_millis() as usize,
-        title: path
-            .file_name()
-            .unwrap()
-            .to_str()
-            .unwrap_or("<No title available>")
-            .into(),
+// Rebuilds and writes the playlist after a change.
+fn regenerate(
+    args: &Args,
+    options: &ScanOptions,
+    roots: &[PathBuf],
+    skip: &[PathBuf],
+    cache: &mut MetadataCache,
+) -> Result<(), PlaylistError> {
+    let (playlist, errors) = build_playlist(args, options, roots, skip, cache)?;
+    report_scan_errors(&errors, args);
+    let track_count = playlist.tracks().count();
+    write_output(playlist, args)?;
+    if !args.quiet {
+        eprintln!("Regenerated playlist with {} tracks", track_count);
+    }
+    Ok(())
+}
+
+// Whether `path` is written by this process, i.e. the output or its temporary file.
+#[cfg(target_os = "linux")]
+fn is_output(path: &Path, args: &Args) -> bool {
+    let Some(ref output) = args.output else {
+        return false;
+    };
+    if args.split {
+        return path.starts_with(output);
+    }
+
+    let (Some(name), Some(output_name)) = (path.file_name(), output.file_name()) else {
+        return false;
     };
+    let mut temp_prefix = OsString::from(".");
+    temp_prefix.push(output_name);
+    path.parent() == output.parent()
+        && (name == output_name
+            || (name
+                .as_encoded_bytes()
+                .starts_with(temp_prefix.as_encoded_bytes())
+                && name.as_encoded_bytes().ends_with(b".tmp")))
+}
+
+// Watches every directory below the roots, directories that were added since the last call are
+// added and those that are watched already are kept.
+#[cfg(target_os = "linux")]
+fn add_watches(
+    watcher: &mut inotify::Watcher,
+    roots: &[PathBuf],
+    follow_symlinks: bool,
+) -> io::Result<()> {
+    for root in roots {
+        watcher.add(root)?;
+        let dirs = WalkDir::new(root)
+            .follow_links(follow_symlinks)
+            .into_iter()
+            .filter_map(Result::ok)
+            .filter(|entry| entry.depth() > 0 && entry.file_type().is_dir());
+        for dir in dirs {
+            watcher.add(dir.path())?;
+        }
+    }
+    Ok(())
+}
+
+// Regenerates the playlist on file system events, once there were none for `--watch-interval`.
+// Returns the error of inotify when events can't be watched.
+#[cfg(target_os = "linux")]
+fn watch_events(
+    args: &Args,
+    options: &ScanOptions,
+    roots: &[PathBuf],
+    skip: &[PathBuf],
+    cache: &mut MetadataCache,
+) -> Result<io::Error, PlaylistError> {
+    let interval = Duration::from_millis(args.watch_interval);
+    let mut watcher = match inotify::Watcher::new() {
+        Ok(watcher) => watcher,
+        Err(e) => return Ok(e),
+    };
+    if let Err(e) = add_watches(&mut watcher, roots, options.follow_symlinks) {
+        return Ok(e);
+    }
+
+    let relevant = |path: &Path| !is_output(path, args);
+    loop {
+        match watcher.wait(None, relevant) {
+            Ok(true) => (),
+            Ok(false) => continue,
+            Err(e) => return Ok(e),
+        }
+
+        // Wait until nothing changes for an interval, e.g. when a file is still being copied
+        loop {
+            match watcher.wait(Some(interval), relevant) {
+                Ok(true) => (),
+                Ok(false) => break,
+                Err(e) => return Ok(e),
+            }
+        }
+
+        if let Err(e) = add_watches(&mut watcher, roots, options.follow_symlinks) {
+            return Ok(e);
+        }
+        regenerate(args, options, roots, skip, cache)?;
+    }
+}
 
-    Some(track)
-}
-
-// Track file metadata and directory structure while walking the directory tree.
-fn filter<'a>(
-    skip: &'a Vec<PathBuf>,
-    tracks: &'a mut Vec<Track>,
-    nodes: &'a mut PendingNodeMap,
-) -> impl 'a + FnMut(&DirEntry) -> bool {
-    |entry| {
-        let path = entry.path();
-        match entry.metadata() {
-            Ok(meta) => {
-                if meta.is_file() {
-                    let file_ext = path.extension();
-                    match file_ext {
-                        Some(ext) if ext == OsStr::new("mkv") => {
-                            if let Some(track) = mkv_meta(path) {
-                                nodes.push_file(path, tracks.len());
-                                tracks.push(track);
-                                return true;
-                            }
-                        }
-                        Some(ext) if ext == OsStr::new("mp4") => {
-                            if let Some(track) = mp4_meta(path) {
-                                nodes.push_file(path, tracks.len());
-                                tracks.push(track);
-                                return true;
-                            }
-                        }
-                        _ => return false,
-                    }
-
-                    false
-                } else if meta.is_dir() {
-                    skip.iter().find(|e| e.as_path() == path).is_none()
-                } else {
-                    false
+// Walks the roots every `--watch-interval` and regenerates the playlist once they have changed
+// and then stayed the same for an interval.
+fn poll(
+    args: &Args,
+    options: &ScanOptions,
+    roots: &[PathBuf],
+    skip: &[PathBuf],
+    cache: &mut MetadataCache,
+) -> Result<(), PlaylistError> {
+    let interval = Duration::from_millis(args.watch_interval);
+    let mut last = snapshot(roots, options.follow_symlinks);
+
+    loop {
+        thread::sleep(interval);
+        let mut current = snapshot(roots, options.follow_symlinks);
+        if current == last {
+            continue;
+        }
+
+        // Wait until nothing changes for an interval, e.g. when a file is still being copied
+        loop {
+            thread::sleep(interval);
+            let next = snapshot(roots, options.follow_symlinks);
+            if next == current {
+                break;
+            }
+            current = next;
+        }
+
+        regenerate(args, options, roots, skip, cache)?;
+        last = current;
+    }
+}
+
+// Converts a value from the configuration file into command-line arguments for `arg`.
+fn config_value_args(arg: &Arg, value: &Value, args: &mut Vec<OsString>) -> Result<(), String> {
+    let long = arg.get_long().unwrap();
+    match (value, arg.get_action()) {
+        (Value::Boolean(set), ArgAction::SetTrue) => {
+            if *set {
+                args.push(format!("--{}", long).into());
+            }
+        }
+        (Value::String(_) | Value::Integer(_), ArgAction::SetTrue) | (Value::Boolean(_), _) => {
+            return Err(format!("`{}` must be a boolean", long))
+        }
+        (Value::String(value), _) => args.push(format!("--{}={}", long, value).into()),
+        (Value::Integer(value), _) => args.push(format!("--{}={}", long, value).into()),
+        (Value::Array(values), ArgAction::Append) => {
+            for value in values {
+                match value {
+                    Value::Array(_) => return Err(format!("`{}` can't contain arrays", long)),
+                    value => config_value_args(arg, value, args)?,
                 }
             }
-            Err(_) => false,
         }
+        (Value::Array(_), _) => return Err(format!("`{}` only takes a single value", long)),
     }
+
+    Ok(())
 }
 
-#[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
-struct Args {
-    #[arg(
-        short,
-        long,
-        help = "Starting points for the scanner, glob patterns are not supported
-Each root is recursively scanned for mkv and mp4 files"
-    )]
-    root: Vec<PathBuf>,
+// Reads the configuration file and converts it into command-line arguments. Options that are set
+// on the command line, or that conflict with options on the command line, are left out so the
+// command line takes precedence.
+fn config_args(
+    path: &Path,
+    command: &Command,
+    matches: &ArgMatches,
+) -> Result<Vec<OsString>, String> {
+    let contents = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let pairs = parse_config(&contents).map_err(|e| e.to_string())?;
 
-    #[arg(
-        short,
-        long,
-        help = "Skipped directories, glob patterns are not supported"
-    )]
-    skip: Vec<PathBuf>,
+    let set_on_command_line =
+        |arg: &Arg| matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine);
 
-    #[arg(
-        short,
-        long,
-        help = "File to write playlist to
-if no file is provided the playlist is printed to stdout"
-    )]
-    output: Option<PathBuf>,
+    let mut args = vec![];
+    for (key, value) in pairs {
+        let long = key.replace('_', "-");
+        let arg = command
+            .get_arguments()
+            .find(|arg| arg.get_long() == Some(&long) && long != "config")
+            .ok_or_else(|| format!("unknown option `{}`", key))?;
+
+        let overridden = set_on_command_line(arg)
+            || command
+                .get_arguments()
+                .filter(|other| set_on_command_line(other))
+                .any(|other| {
+                    command.get_arg_conflicts_with(arg).contains(&other)
+                        || command.get_arg_conflicts_with(other).contains(&arg)
+                });
+        if !overridden {
+            config_value_args(arg, &value, &mut args)?;
+        }
+    }
+
+    Ok(args)
 }
+
+// `-` overrides an output from the configuration file with stdout, which only takes a single
+// playlist.
+fn output_to_stdout(args: &mut Args) -> Result<(), clap::Error> {
+    if args.output.as_deref() == Some(Path::new("-")) {
+        let needs_file = [
+            (args.split, "--split"),
+            (args.portable, "--portable"),
+            (args.watch, "--watch"),
+        ];
+        if let Some((_, option)) = needs_file.iter().find(|(set, _)| *set) {
+            return Err(Args::command().error(
+                ErrorKind::ArgumentConflict,
+                format!("{} can't be used when writing to stdout", option),
+            ));
+        }
+        args.output = None;
+    }
+
+    Ok(())
+}
+
+// Parses the command line, using the configuration file for options that aren't set on it.
+fn parse_args() -> Args {
+    let mut command = Args::command();
+    // The command line is only validated once the configuration file is included, as it can
+    // provide options the command line depends on
+    let matches = command
+        .clone()
+        .ignore_errors(true)
+        .try_get_matches()
+        .unwrap_or_else(|e| e.exit());
+
+    let path = match matches.get_one::<PathBuf>("config") {
+        Some(path) => path.clone(),
+        None if Path::new(DEFAULT_CONFIG).is_file() => PathBuf::from(DEFAULT_CONFIG),
+        None => return Args::parse(),
+    };
+
+    let config = match config_args(&path, &command, &matches) {
+        Ok(config) => config,
+        Err(e) => command
+            .error(
+                ErrorKind::InvalidValue,
+                format!("invalid configuration file {}: {}", path.display(), e),
+            )
+            .exit(),
+    };
+
+    // Options from the configuration file are inserted before the ones from the command line
+    let mut cli = env::args_os();
+    let args = cli.next().into_iter().chain(config).chain(cli);
+    Args::parse_from(args)
+}
+
 fn main() {
-    let args = Args::parse();
+    let mut args = parse_args();
+    args.root = args.root.iter().map(|root| expand_home(root)).collect();
+    args.skip = args.skip.iter().map(|skip| expand_home(skip)).collect();
+    args.output = args.output.as_deref().map(expand_home);
+
+    if let Err(e) = output_to_stdout(&mut args) {
+        e.exit();
+    }
+
+    match run(args) {
+        Ok(true) => (),
+        Ok(false) => process::exit(2),
+        Err(e) => {
+            match e.source() {
+                Some(source) => eprintln!("Error: {}: {}", e, source),
+                None => eprintln!("Error: {}", e),
+            }
+            process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vlc_playlist_generator::PlaylistBuilder;
+
+    fn args(arguments: &[&str]) -> Args {
+        Args::try_parse_from(["vlc-playlist-generator"].iter().chain(arguments)).unwrap()
+    }
 
-    let mut tracks = vec![];
-    let mut nodes = PendingNodeMap::new(args.root.clone());
+    fn playlist() -> Playlist {
+        let mut builder = PlaylistBuilder::new();
+        builder.add_track("/media/a.mkv", "A", 90_000);
+        builder.build()
+    }
 
-    {
-        let mut filter = filter(&args.skip, &mut tracks, &mut nodes);
+    #[test]
+    fn stdout_gets_the_bytes_of_a_file() {
+        let path = env::temp_dir().join(format!("vlc-playlist-generator-{}", process::id()));
+        for format in Format::value_variants() {
+            let format = format.to_possible_value().unwrap();
+            let args = args(&["--format", format.get_name()]);
 
-        for root in args.root {
-            let walker = WalkDir::new(root).into_iter();
-            for _entry in walker.filter_entry(&mut filter) {}
+            let mut stdout = vec![];
+            write_buffered(&mut stdout, playlist(), &args).unwrap();
+            write_file(&path, playlist(), &args).unwrap();
+            assert_eq!(stdout, fs::read(&path).unwrap());
         }
+        fs::remove_file(path).unwrap();
     }
 
-    let track_list = TrackList { tracks };
-    let mut nodes = nodes.into_nodes();
+    #[test]
+    fn dash_is_stdout() {
+        let mut dash = args(&["--output", "-"]);
+        output_to_stdout(&mut dash).unwrap();
+        assert_eq!(dash.output, None);
 
-    // Sort nodes alphabetically, files after dirs
-    nodes.sort();
-    for n in nodes.as_mut_slice() {
-        n.sort();
+        let mut file = args(&["--output", "a.xspf"]);
+        output_to_stdout(&mut file).unwrap();
+        assert_eq!(file.output, Some("a.xspf".into()));
+
+        let mut split = args(&["--output", "-", "--split"]);
+        let err = output_to_stdout(&mut split).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ArgumentConflict);
     }
 
-    let playlist = Playlist { track_list, nodes };
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn output_files_are_recognized() {
+        let file = args(&["--output", "/media/list.xspf"]);
+        assert!(is_output(Path::new("/media/list.xspf"), &file));
+        assert!(is_output(Path::new("/media/.list.xspf.123.tmp"), &file));
+        assert!(!is_output(Path::new("/media/a.mkv"), &file));
+        assert!(!is_output(Path::new("/media/sub/list.xspf"), &file));
 
-    if let Some(path) = args.output {
-        let file = File::create(path).expect("Playlist file cannot be created");
-        let mut writer = BufWriter::new(file);
-        into_xml(&mut writer, playlist);
-    } else {
-        let mut data = vec![];
-        into_xml(&mut data, playlist);
-        println!(
-            "{}",
-            String::from_utf8(data).expect("Non-UTF8 encoded data")
-        )
+        let split = args(&["--output", "/media/lists", "--split"]);
+        assert!(is_output(Path::new("/media/lists/Movies.xspf"), &split));
+        assert!(!is_output(Path::new("/media/list.xspf"), &split));
+        assert!(!is_output(Path::new("/media/list.xspf"), &args(&[])));
+    }
+    #[test]
+    fn durations_are_parsed() {
+        assert_eq!(parse_duration("90"), Ok(90_000));
+        assert_eq!(parse_duration("30s"), Ok(30_000));
+        assert_eq!(parse_duration("1h30m"), Ok(5_400_000));
+        assert_eq!(parse_duration("500ms"), Ok(500));
+        assert_eq!(parse_duration("1m500ms"), Ok(60_500));
+
+        for invalid in ["", "m", "1x", "1.5s", "-1", "s30", "18446744073709552h"] {
+            assert_eq!(
+                parse_duration(invalid),
+                Err(format!("invalid duration `{}`", invalid))
+            );
+        }
+    }
+    #[test]
+    fn sizes_are_parsed() {
+        assert_eq!(parse_size("4096"), Ok(4096));
+        assert_eq!(parse_size("500K"), Ok(500 << 10));
+        assert_eq!(parse_size("1.5G"), Ok(3 << 29));
+        assert_eq!(parse_size("2mib"), Ok(2 << 20));
+        assert_eq!(parse_size("1TB"), Ok(1 << 40));
+        assert_eq!(parse_size("10B"), Ok(10));
+
+        for invalid in ["", "K", "-1M", "1X", "inf", "NaN"] {
+            assert_eq!(
+                parse_size(invalid),
+                Err(format!("invalid size `{}`", invalid))
+            );
+        }
+    }
+    #[test]
+    fn time_points_are_parsed() {
+        assert_eq!(parse_date("1970-01-01"), Some(0));
+        assert_eq!(parse_date("1969-12-31"), Some(-1));
+        assert_eq!(parse_date("2000-03-01"), Some(11_017));
+        assert_eq!(parse_date("2024-02-29"), Some(19_782));
+        for invalid in [
+            "2024-13-01",
+            "2024-01-32",
+            "2024-00-01",
+            "2024-01",
+            "x-01-01",
+        ] {
+            assert_eq!(parse_date(invalid), None);
+        }
+
+        assert_eq!(
+            parse_time_point("1970-01-02"),
+            Ok(UNIX_EPOCH + Duration::from_secs(86_400))
+        );
+        assert_eq!(
+            parse_time_point("1969-12-31"),
+            Ok(UNIX_EPOCH - Duration::from_secs(86_400))
+        );
+
+        // Ages are relative to the time they're parsed
+        let age = |s| {
+            let time = parse_time_point(s).unwrap();
+            SystemTime::now().duration_since(time).unwrap()
+        };
+        let day = Duration::from_secs(86_400);
+        assert!(age("7d") >= 7 * day && age("7d") < 7 * day + Duration::from_secs(60));
+        assert!(age("2w") >= 14 * day && age("2w") < 14 * day + Duration::from_secs(60));
+        assert!(age("90m") >= Duration::from_secs(5400));
+
+        for invalid in ["", "w", "7x", "1d2", "2024-13-01", "99999999999999999999d"] {
+            assert_eq!(
+                parse_time_point(invalid),
+                Err(format!("invalid age or date `{}`", invalid))
+            );
+        }
+    }
+    #[test]
+    fn home_directories_are_expanded() {
+        if let Some(home) = env::var_os("HOME") {
+            let home = PathBuf::from(home);
+            assert_eq!(expand_home(Path::new("~")), home);
+            assert_eq!(
+                expand_home(Path::new("~/Videos/a.mkv")),
+                home.join("Videos/a.mkv")
+            );
+        }
+        #[cfg(unix)]
+        {
+            let root = user_home("root").unwrap();
+            assert_eq!(expand_home(Path::new("~root/media")), root.join("media"));
+        }
+
+        // Unknown users and a `~` later in the path are left alone
+        for path in ["~no-such-user-here/a", "a/~/b", "/~root", "a~/b"] {
+            assert_eq!(expand_home(Path::new(path)), Path::new(path));
+        }
     }
 }
@@ -0,0 +1,229 @@
+use std::{
+    fs::File,
+    io::{BufReader, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+static SEGMENT_ID: u32 = 0x1853_8067;
+static INFO_ID: u32 = 0x1549_a966;
+static TIMECODE_SCALE_ID: u32 = 0x2a_d7b1;
+static CLUSTER_ID: u32 = 0x1f43_b675;
+static TIMECODE_ID: u32 = 0xe7;
+static SIMPLE_BLOCK_ID: u32 = 0xa3;
+static BLOCK_GROUP_ID: u32 = 0xa0;
+static BLOCK_ID: u32 = 0xa1;
+static BLOCK_DURATION_ID: u32 = 0x9b;
+// Timestamps are in milliseconds unless the segment info says otherwise
+static DEFAULT_TIMECODE_SCALE: u64 = 1_000_000;
+// The last cluster is searched for in this many bytes at the end of the file
+static TAIL_SIZE: u64 = 1 << 20;
+
+// Reads an EBML variable-length integer from the start of `data` and returns it with its length.
+// Element ids keep their length marker, sizes don't. A size with all bits set is unknown.
+fn read_vint(data: &[u8], keep_marker: bool) -> Option<(u64, usize)> {
+    let first = *data.first()?;
+    let len = first.leading_zeros() as usize + 1;
+    if len > 8 || data.len() < len {
+        return None;
+    }
+
+    let marker = if keep_marker { 0 } else { 0x80 >> (len - 1) };
+    let value = data[1..len]
+        .iter()
+        .fold((first & !marker) as u64, |acc, &b| (acc << 8) | b as u64);
+    Some((value, len))
+}
+
+fn read_uint(data: &[u8]) -> u64 {
+    data.iter().fold(0, |acc, &b| (acc << 8) | b as u64)
+}
+
+// Reads the id and size of the element at the start of `data`, and the length of its header.
+fn element_header(data: &[u8]) -> Option<(u32, Option<u64>, usize)> {
+    let (id, id_len) = read_vint(data, true)?;
+    let (size, size_len) = read_vint(&data[id_len..], false)?;
+    let unknown = size == (1 << (7 * size_len)) - 1;
+    Some((id as u32, (!unknown).then_some(size), id_len + size_len))
+}
+
+// Reads the element header at the current position of the file.
+fn read_element_header<R: Read + Seek>(reader: &mut R) -> Option<(u32, Option<u64>)> {
+    let start = reader.stream_position().ok()?;
+    let mut header = vec![];
+    reader.take(12).read_to_end(&mut header).ok()?;
+    let (id, size, header_len) = element_header(&header)?;
+    reader
+        .seek(SeekFrom::Start(start + header_len as u64))
+        .ok()?;
+    Some((id, size))
+}
+
+// The timestamp scale from the segment info, the elements of the segment are read until the
+// first cluster.
+fn timecode_scale<R: Read + Seek>(reader: &mut R) -> Option<u64> {
+    loop {
+        let (id, size) = read_element_header(reader)?;
+        if id == SEGMENT_ID {
+            break;
+        }
+        reader
+            .seek(SeekFrom::Current(i64::try_from(size?).ok()?))
+            .ok()?;
+    }
+
+    loop {
+        let (id, size) = read_element_header(reader)?;
+        if id == CLUSTER_ID {
+            return Some(DEFAULT_TIMECODE_SCALE);
+        }
+
+        let size = size?;
+        if id != INFO_ID {
+            reader
+                .seek(SeekFrom::Current(i64::try_from(size).ok()?))
+                .ok()?;
+            continue;
+        }
+
+        // The size is read into memory as far as the file has data, a corrupt header can declare
+        // far more than that
+        let mut info = vec![];
+        reader.take(size).read_to_end(&mut info).ok()?;
+        if (info.len() as u64) < size {
+            return None;
+        }
+        let mut pos = 0;
+        while let Some((id, Some(size), header_len)) = element_header(&info[pos..]) {
+            let start = pos + header_len;
+            let end = start.checked_add(usize::try_from(size).ok()?)?;
+            let data = info.get(start..end)?;
+            if id == TIMECODE_SCALE_ID {
+                return Some(read_uint(data));
+            }
+            pos = end;
+        }
+        return Some(DEFAULT_TIMECODE_SCALE);
+    }
+}
+
+// The end of the latest block in a cluster, relative to the start of the segment. The cluster may
+// be cut off at the end of `data`, blocks are read as far as they're available.
+fn cluster_end(data: &[u8]) -> Option<u64> {
+    let mut timecode = None;
+    let mut end: Option<i64> = None;
+    let mut block_end = |block: &[u8], duration: u64| {
+        let (_, track_len) = read_vint(block, false)?;
+        let offset = block.get(track_len..track_len + 2)?;
+        let offset = (i16::from_be_bytes([offset[0], offset[1]]) as i64)
+            .checked_add(i64::try_from(duration).ok()?)?;
+        end = Some(end.map_or(offset, |end| end.max(offset)));
+        Some(())
+    };
+
+    let mut pos = 0;
+    while let Some((id, Some(size), header_len)) = element_header(&data[pos..]) {
+        let start = pos + header_len;
+        let next = start.saturating_add(usize::try_from(size).unwrap_or(usize::MAX));
+        let contents = &data[start.min(data.len())..data.len().min(next)];
+        if id == TIMECODE_ID {
+            timecode = Some(read_uint(contents));
+        } else if id == SIMPLE_BLOCK_ID {
+            block_end(contents, 0);
+        } else if id == BLOCK_GROUP_ID {
+            let (mut block, mut duration) = (None, 0);
+            let mut group_pos = 0;
+            while let Some((id, Some(size), header_len)) = element_header(&contents[group_pos..]) {
+                let start = group_pos + header_len;
+                let Some(child) = usize::try_from(size)
+                    .ok()
+                    .and_then(|size| contents.get(start..start.checked_add(size)?))
+                else {
+                    break;
+                };
+                if id == BLOCK_ID {
+                    block = Some(child);
+                } else if id == BLOCK_DURATION_ID {
+                    duration = read_uint(child);
+                }
+                group_pos = start + child.len();
+            }
+            if let Some(block) = block {
+                block_end(block, duration);
+            }
+        }
+
+        pos = next;
+        if pos >= data.len() {
+            break;
+        }
+    }
+
+    let end = i64::try_from(timecode?)
+        .ok()?
+        .checked_add(end.unwrap_or(0))?;
+    Some(end.max(0) as u64)
+}
+
+// Estimates the duration in milliseconds of a Matroska file without a duration in its segment
+// info, from the end of the last block in the last cluster.
+pub(crate) fn cluster_duration(path: &Path) -> Option<u64> {
+    let file = File::open(path).ok()?;
+    let size = file.metadata().ok()?.len();
+    let mut reader = BufReader::new(file);
+    let scale = timecode_scale(&mut reader)?;
+
+    let tail_start = size.saturating_sub(TAIL_SIZE);
+    reader.seek(SeekFrom::Start(tail_start)).ok()?;
+    let mut tail = vec![];
+    reader.read_to_end(&mut tail).ok()?;
+
+    // Block data can contain the cluster id by chance, so earlier candidates are tried until one
+    // has a timestamp
+    let cluster_id = CLUSTER_ID.to_be_bytes();
+    let mut search_end = tail.len();
+    while let Some(pos) = tail[..search_end]
+        .windows(4)
+        .rposition(|window| window == cluster_id)
+    {
+        search_end = pos;
+        let Some((_, size_len)) = read_vint(&tail[pos + 4..], false) else {
+            continue;
+        };
+        if let Some(end) = cluster_end(&tail[pos + 4 + size_len..]) {
+            return Some(end.checked_mul(scale)? / 1_000_000);
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn huge_info_size_is_rejected() {
+        // A segment of unknown size with an info element that claims 2^48 bytes
+        let mut data = vec![
+            0x18, 0x53, 0x80, 0x67, 0x01, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        ];
+        data.extend([
+            0x15, 0x49, 0xa9, 0x66, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00,
+        ]);
+        data.extend([0x2a, 0xd7, 0xb1, 0x81, 0x01]);
+        assert_eq!(timecode_scale(&mut Cursor::new(data)), None);
+    }
+
+    #[test]
+    fn overflowing_timestamps_are_unknown() {
+        // A cluster with a timestamp of u64::MAX and one simple block
+        let mut data = vec![0xe7, 0x88];
+        data.extend(u64::MAX.to_be_bytes());
+        data.extend([0xa3, 0x84, 0x81, 0x00, 0x00, 0x00]);
+        assert_eq!(cluster_end(&data), None);
+
+        data[2] = 0;
+        assert_eq!(cluster_end(&data), Some(u64::MAX >> 8));
+    }
+}
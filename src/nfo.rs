@@ -0,0 +1,41 @@
+use std::{fs, path::Path};
+
+use crate::xml_reader::{tokenize, Event};
+
+pub(crate) static NFO_EXTENSION: &str = "nfo";
+// Name of the NFO file of a movie that's in a directory of its own
+pub(crate) static MOVIE_NFO: &str = "movie.nfo";
+
+// Reads the title from a Kodi-style NFO file, i.e. the `<title>` element directly below the root
+// element like `<movie>` or `<episodedetails>`. `None` if the file isn't XML or has no title.
+pub(crate) fn nfo_title(path: &Path) -> Option<String> {
+    let data = fs::read_to_string(path).ok()?;
+
+    let mut depth = 0;
+    let mut title: Option<String> = None;
+    for event in tokenize(&data).ok()? {
+        match event {
+            Event::Start { name, .. } => {
+                if depth == 1 && name == "title" {
+                    title = Some(String::new());
+                }
+                depth += 1;
+            }
+            Event::End { name } => {
+                depth -= 1;
+                if depth == 1 && name == "title" {
+                    break;
+                }
+            }
+            Event::Text(text) => {
+                if let (2, Some(title)) = (depth, title.as_mut()) {
+                    title.push_str(&text);
+                }
+            }
+        }
+    }
+
+    title
+        .map(|title| title.trim().to_string())
+        .filter(|title| !title.is_empty())
+}
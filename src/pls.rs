@@ -0,0 +1,80 @@
+use std::io::{self, Write};
+
+use crate::{xml::plain_location, Playlist, PlaylistNode};
+
+static PLS_HEADER: &str = "[playlist]";
+static PLS_VERSION: &str = "Version=2";
+
+pub fn into_pls<W: Write>(writer: &mut W, playlist: Playlist) -> io::Result<()> {
+    // PLS has no notion of nodes, so the tree is flattened in playlist order
+    let indices: Vec<usize> = playlist
+        .nodes()
+        .iter()
+        .flat_map(PlaylistNode::track_indices)
+        .collect();
+
+    writeln!(writer, "{}", PLS_HEADER)?;
+    writeln!(writer, "NumberOfEntries={}", indices.len())?;
+
+    // Entries are numbered from 1, an unknown length is written as -1
+    for (entry, idx) in indices.into_iter().enumerate() {
+        let track = playlist.track(idx);
+        let entry = entry + 1;
+        writeln!(writer, "File{}={}", entry, plain_location(track))?;
+        // A line break in the title would end the key, the rest would be read as another line
+        let title = track.title().replace(['\r', '\n'], " ");
+        writeln!(writer, "Title{}={}", entry, title)?;
+        match track.duration() {
+            0 => writeln!(writer, "Length{}=-1", entry)?,
+            duration => writeln!(writer, "Length{}={}", entry, duration / 1000)?,
+        }
+    }
+
+    writeln!(writer, "{}", PLS_VERSION)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PlaylistBuilder;
+
+    #[test]
+    fn line_breaks_in_titles_are_spaces() {
+        let mut builder = PlaylistBuilder::new();
+        builder.add_track("/media/a.mkv", "Line\r\nbreak\nhere", 90_000);
+
+        let mut pls = vec![];
+        into_pls(&mut pls, builder.build()).unwrap();
+        let text = String::from_utf8(pls).unwrap();
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(lines[3], "Title1=Line  break here");
+        assert_eq!(lines.len(), 6);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn locations_are_file_uris() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt, path::Path};
+
+        let names = [OsStr::new("a\nb.mkv"), OsStr::from_bytes(b"c\xff.mkv")];
+        let mut builder = PlaylistBuilder::new();
+        for name in names {
+            builder.add_track(Path::new("/media").join(name), "Title", 90_000);
+        }
+
+        let mut out = vec![];
+        into_pls(&mut out, builder.build()).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let locations: Vec<_> = text
+            .lines()
+            .filter(|line| line.starts_with("File"))
+            .collect();
+        assert_eq!(
+            locations,
+            [
+                "File1=file:///media/a%0Ab.mkv",
+                "File2=file:///media/c%FF.mkv"
+            ]
+        );
+    }
+}
@@ -0,0 +1,60 @@
+use std::{
+    io::{self, IsTerminal, Write},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+const UPDATE_INTERVAL: Duration = Duration::from_millis(100);
+
+// Reports scan progress on a single line of stderr, so it doesn't mix with a playlist written to
+// stdout. Updates are throttled, and nothing is printed if stderr isn't a terminal.
+pub(crate) struct Progress {
+    enabled: bool,
+    shown: bool,
+    last_update: Option<Instant>,
+}
+
+impl Progress {
+    pub(crate) fn new(enabled: bool) -> Self {
+        Progress {
+            enabled: enabled && io::stderr().is_terminal(),
+            shown: false,
+            last_update: None,
+        }
+    }
+
+    fn update(&mut self, line: impl FnOnce() -> String) {
+        if !self.enabled {
+            return;
+        }
+
+        let now = Instant::now();
+        if self
+            .last_update
+            .is_some_and(|last| now.duration_since(last) < UPDATE_INTERVAL)
+        {
+            return;
+        }
+
+        self.last_update = Some(now);
+        self.shown = true;
+        let mut stderr = io::stderr().lock();
+        write!(stderr, "\r\x1b[K{}", line()).ok();
+        stderr.flush().ok();
+    }
+
+    pub(crate) fn walking(&mut self, files: usize, dir: &Path) {
+        self.update(|| format!("Found {} files, scanning {}", files, dir.display()));
+    }
+
+    pub(crate) fn reading(&mut self, done: usize, total: usize) {
+        self.update(|| format!("Read metadata of {}/{} files", done, total));
+    }
+
+    pub(crate) fn finish(&mut self) {
+        if self.shown {
+            eprint!("\r\x1b[K");
+            self.shown = false;
+        }
+    }
+}
@@ -0,0 +1,552 @@
+use std::{fmt, mem};
+
+// Counted repetitions are expanded into copies of the repeated expression, these limits keep the
+// compiled program small.
+static MAX_REPEAT: u32 = 1000;
+static MAX_PROGRAM_SIZE: usize = 100_000;
+// Groups and repetitions nest the syntax tree, which is parsed and compiled recursively
+static MAX_NESTING: usize = 250;
+
+// The characters a single character of the text is matched against.
+#[derive(Clone)]
+enum Class {
+    Any,
+    Ranges {
+        ranges: Vec<(char, char)>,
+        negated: bool,
+    },
+}
+
+impl Class {
+    fn matches(&self, c: char, ignore_case: bool) -> bool {
+        let Class::Ranges { ranges, negated } = self else {
+            return true;
+        };
+
+        let contains = |c: char| ranges.iter().any(|&(start, end)| start <= c && c <= end);
+        let mut found = contains(c);
+        if ignore_case && !found {
+            let mut lower = c.to_lowercase();
+            let mut upper = c.to_uppercase();
+            found = (lower.len() == 1 && contains(lower.next().unwrap()))
+                || (upper.len() == 1 && contains(upper.next().unwrap()));
+        }
+        found != *negated
+    }
+}
+
+fn literal(c: char) -> Class {
+    Class::Ranges {
+        ranges: vec![(c, c)],
+        negated: false,
+    }
+}
+
+// The characters that aren't in any of the sorted, non-overlapping `ranges`.
+fn complement(ranges: &[(char, char)]) -> Vec<(char, char)> {
+    let mut result = vec![];
+    let mut next = Some('\0');
+    for &(start, end) in ranges {
+        if let Some(first) = next.filter(|&first| first < start) {
+            result.push((
+                first,
+                char::from_u32(start as u32 - 1).unwrap_or('\u{d7ff}'),
+            ));
+        }
+        next = char::from_u32(end as u32 + 1).or_else(|| char::from_u32(end as u32 + 0x801));
+    }
+    if let Some(first) = next {
+        result.push((first, char::MAX));
+    }
+    result
+}
+
+enum Node {
+    Char(Class),
+    Start,
+    End,
+    Concat(Vec<Node>),
+    Alt(Vec<Node>),
+    Repeat {
+        node: Box<Node>,
+        min: u32,
+        max: Option<u32>,
+    },
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    // The number of groups and repetitions around the current position
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        Some(c)
+    }
+
+    fn nest(&mut self) -> Result<(), String> {
+        self.depth += 1;
+        if self.depth > MAX_NESTING {
+            return Err(format!(
+                "pattern is nested deeper than {} levels",
+                MAX_NESTING
+            ));
+        }
+        Ok(())
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        let found = self.peek() == Some(c);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn alternation(&mut self) -> Result<Node, String> {
+        let mut branches = vec![self.concatenation()?];
+        while self.eat('|') {
+            branches.push(self.concatenation()?);
+        }
+
+        if branches.len() == 1 {
+            Ok(branches.pop().unwrap())
+        } else {
+            Ok(Node::Alt(branches))
+        }
+    }
+
+    fn concatenation(&mut self) -> Result<Node, String> {
+        let mut nodes = vec![];
+        while !matches!(self.peek(), None | Some('|' | ')')) {
+            let atom = self.atom()?;
+            nodes.push(self.repetitions(atom)?);
+        }
+        Ok(Node::Concat(nodes))
+    }
+
+    fn atom(&mut self) -> Result<Node, String> {
+        let node = match self.next().unwrap() {
+            '(' => {
+                if self.eat('?') && !self.eat(':') {
+                    return Err("only non-capturing groups `(?:...)` are supported".into());
+                }
+                self.nest()?;
+                let node = self.alternation()?;
+                if !self.eat(')') {
+                    return Err("unclosed group".into());
+                }
+                self.depth -= 1;
+                node
+            }
+            '.' => Node::Char(Class::Any),
+            '^' => Node::Start,
+            '$' => Node::End,
+            '[' => Node::Char(self.set()?),
+            '\\' => {
+                let (ranges, negated) = self.escape()?;
+                Node::Char(Class::Ranges { ranges, negated })
+            }
+            '*' | '+' | '?' | '{' => return Err("repetition without an expression".into()),
+            c => Node::Char(literal(c)),
+        };
+        Ok(node)
+    }
+
+    fn repetitions(&mut self, mut node: Node) -> Result<Node, String> {
+        let depth = self.depth;
+        loop {
+            let (min, max) = if self.eat('*') {
+                (0, None)
+            } else if self.eat('+') {
+                (1, None)
+            } else if self.eat('?') {
+                (0, Some(1))
+            } else if self.eat('{') {
+                self.counts()?
+            } else {
+                self.depth = depth;
+                return Ok(node);
+            };
+            self.nest()?;
+            // A lazy repetition matches the same texts, only which part of it is matched differs
+            self.eat('?');
+
+            node = Node::Repeat {
+                node: Box::new(node),
+                min,
+                max,
+            };
+        }
+    }
+
+    // Reads the `m}`, `m,}` or `m,n}` after the `{` of a counted repetition.
+    fn counts(&mut self) -> Result<(u32, Option<u32>), String> {
+        let number = |parser: &mut Parser| {
+            let start = parser.pos;
+            while parser.peek().is_some_and(|c| c.is_ascii_digit()) {
+                parser.pos += 1;
+            }
+            let digits: String = parser.chars[start..parser.pos].iter().collect();
+            (!digits.is_empty()).then(|| digits.parse::<u32>().unwrap_or(u32::MAX))
+        };
+
+        let min = number(self).ok_or("invalid repetition count")?;
+        let max = if self.eat(',') {
+            number(self)
+        } else {
+            Some(min)
+        };
+        if !self.eat('}') {
+            return Err("invalid repetition count".into());
+        }
+        if max.is_some_and(|max| max < min) {
+            return Err("repetition range is reversed".into());
+        }
+        if max.unwrap_or(min) > MAX_REPEAT {
+            return Err(format!("repetition count is larger than {}", MAX_REPEAT));
+        }
+        Ok((min, max))
+    }
+
+    // Reads the escape after a `\`, returns its characters and whether they're negated.
+    fn escape(&mut self) -> Result<(Vec<(char, char)>, bool), String> {
+        let c = self.next().ok_or("incomplete escape")?;
+        let ranges = match c.to_ascii_lowercase() {
+            'd' => vec![('0', '9')],
+            'w' => vec![('0', '9'), ('A', 'Z'), ('_', '_'), ('a', 'z')],
+            's' => vec![('\t', '\r'), (' ', ' ')],
+            _ => {
+                let c = match c {
+                    'n' => '\n',
+                    'r' => '\r',
+                    't' => '\t',
+                    c if !c.is_alphanumeric() => c,
+                    c => return Err(format!("unknown escape `\\{}`", c)),
+                };
+                return Ok((vec![(c, c)], false));
+            }
+        };
+        Ok((ranges, c.is_ascii_uppercase()))
+    }
+
+    // Reads a `[...]` set after its `[`.
+    fn set(&mut self) -> Result<Class, String> {
+        let negated = self.eat('^');
+        let mut ranges = vec![];
+        let mut first = true;
+        loop {
+            let c = self.next().ok_or("unclosed character class")?;
+            if c == ']' && !first {
+                break;
+            }
+            first = false;
+
+            let start = if c == '\\' {
+                let (escaped, negated) = self.escape()?;
+                match escaped[..] {
+                    [(c, end)] if c == end && !negated => c,
+                    _ if negated => {
+                        ranges.extend(complement(&escaped));
+                        continue;
+                    }
+                    _ => {
+                        ranges.extend(escaped);
+                        continue;
+                    }
+                }
+            } else {
+                c
+            };
+
+            let is_range = self.peek() == Some('-')
+                && self.chars.get(self.pos + 1).is_some_and(|&end| end != ']');
+            if !is_range {
+                ranges.push((start, start));
+                continue;
+            }
+
+            self.pos += 1;
+            let end = match self.next().unwrap() {
+                '\\' => match self.escape()? {
+                    (escaped, false) if escaped.len() == 1 && escaped[0].0 == escaped[0].1 => {
+                        escaped[0].0
+                    }
+                    _ => return Err("invalid character class range".into()),
+                },
+                end => end,
+            };
+            if end < start {
+                return Err("character class range is reversed".into());
+            }
+            ranges.push((start, end));
+        }
+
+        Ok(Class::Ranges { ranges, negated })
+    }
+}
+
+#[derive(Clone)]
+enum Inst {
+    Char(Class),
+    Split(usize, usize),
+    Jump(usize),
+    Start,
+    End,
+    Match,
+}
+
+fn compile(node: &Node, program: &mut Vec<Inst>) -> Result<(), String> {
+    if program.len() > MAX_PROGRAM_SIZE {
+        return Err("pattern is too large".into());
+    }
+
+    match node {
+        Node::Char(class) => program.push(Inst::Char(class.clone())),
+        Node::Start => program.push(Inst::Start),
+        Node::End => program.push(Inst::End),
+        Node::Concat(nodes) => {
+            for node in nodes {
+                compile(node, program)?;
+            }
+        }
+        Node::Alt(branches) => {
+            let mut jumps = vec![];
+            let (last, rest) = branches.split_last().unwrap();
+            for branch in rest {
+                let split = program.len();
+                program.push(Inst::Split(split + 1, 0));
+                compile(branch, program)?;
+                jumps.push(program.len());
+                program.push(Inst::Jump(0));
+                program[split] = Inst::Split(split + 1, program.len());
+            }
+            compile(last, program)?;
+            for jump in jumps {
+                program[jump] = Inst::Jump(program.len());
+            }
+        }
+        Node::Repeat { node, min, max } => {
+            for _ in 0..*min {
+                compile(node, program)?;
+            }
+
+            let mut splits = vec![];
+            match max {
+                None => {
+                    let split = program.len();
+                    program.push(Inst::Split(split + 1, 0));
+                    compile(node, program)?;
+                    program.push(Inst::Jump(split));
+                    splits.push(split);
+                }
+                Some(max) => {
+                    for _ in *min..*max {
+                        splits.push(program.len());
+                        program.push(Inst::Split(0, 0));
+                        compile(node, program)?;
+                    }
+                }
+            }
+            for split in splits {
+                program[split] = Inst::Split(split + 1, program.len());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// A regular expression, matched anywhere in a text unless it's anchored with `^` or `$`.
+///
+/// The syntax is a subset of the `regex` crate: literals, `.`, sets like `[a-z]` and `[^0-9]`,
+/// the classes `\d`, `\w` and `\s` and their negations, `^` and `$`, alternation with `|`, groups
+/// with `(...)` or `(?:...)`, and the repetitions `*`, `+`, `?`, `{m}`, `{m,}` and `{m,n}`. Texts
+/// are matched in time linear in their length, whatever the pattern.
+#[derive(Clone)]
+pub struct Regex {
+    source: String,
+    program: Vec<Inst>,
+}
+
+impl Regex {
+    /// Parses `pattern`, the error describes why it isn't a valid regular expression.
+    pub fn new(pattern: &str) -> Result<Self, String> {
+        let mut parser = Parser {
+            chars: pattern.chars().collect(),
+            pos: 0,
+            depth: 0,
+        };
+        let node = parser.alternation()?;
+        if parser.pos < parser.chars.len() {
+            return Err("unmatched `)`".into());
+        }
+
+        let mut program = vec![];
+        compile(&node, &mut program)?;
+        program.push(Inst::Match);
+        Ok(Regex {
+            source: pattern.to_string(),
+            program,
+        })
+    }
+
+    /// The pattern this expression was parsed from.
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    /// Whether the expression matches any part of `text`.
+    pub fn is_match(&self, text: &str) -> bool {
+        self.matches(text, false)
+    }
+
+    pub(crate) fn is_match_ignoring_case(&self, text: &str) -> bool {
+        self.matches(text, true)
+    }
+
+    // Runs all threads of the program in lockstep over the text. `added` holds the position at
+    // which each instruction was last added, so it's added at most once per position.
+    fn matches(&self, text: &str, ignore_case: bool) -> bool {
+        let chars: Vec<char> = text.chars().collect();
+        let mut added = vec![usize::MAX; self.program.len()];
+        let mut current = vec![];
+        let mut next = vec![];
+        for pos in 0..=chars.len() {
+            // A new thread starts at every position, the match isn't anchored
+            if self.add_thread(&mut current, &mut added, 0, pos, chars.len()) {
+                return true;
+            }
+            let Some(&c) = chars.get(pos) else {
+                break;
+            };
+
+            for &pc in &current {
+                let Inst::Char(class) = &self.program[pc] else {
+                    continue;
+                };
+                if class.matches(c, ignore_case)
+                    && self.add_thread(&mut next, &mut added, pc + 1, pos + 1, chars.len())
+                {
+                    return true;
+                }
+            }
+            current.clear();
+            mem::swap(&mut current, &mut next);
+        }
+
+        false
+    }
+
+    // Adds the instructions that consume a character reachable from `pc` to `threads`, returns
+    // whether the match instruction is reachable.
+    fn add_thread(
+        &self,
+        threads: &mut Vec<usize>,
+        added: &mut [usize],
+        pc: usize,
+        pos: usize,
+        len: usize,
+    ) -> bool {
+        let mut stack = vec![pc];
+        while let Some(pc) = stack.pop() {
+            if added[pc] == pos {
+                continue;
+            }
+            added[pc] = pos;
+
+            match self.program[pc] {
+                Inst::Char(_) => threads.push(pc),
+                Inst::Split(a, b) => stack.extend([b, a]),
+                Inst::Jump(target) => stack.push(target),
+                Inst::Start if pos == 0 => stack.push(pc + 1),
+                Inst::End if pos == len => stack.push(pc + 1),
+                Inst::Start | Inst::End => {}
+                Inst::Match => return true,
+            }
+        }
+
+        false
+    }
+}
+
+impl fmt::Debug for Regex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Regex").field(&self.source).finish()
+    }
+}
+
+impl fmt::Display for Regex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_match(pattern: &str, text: &str) -> bool {
+        Regex::new(pattern).unwrap().is_match(text)
+    }
+
+    #[test]
+    fn expressions_match_anywhere() {
+        assert!(is_match("sample", "Movie.sample.mkv"));
+        assert!(is_match("sample|trailer", "Movie-trailer.mkv"));
+        assert!(!is_match("sample|trailer", "Movie.mkv"));
+        assert!(is_match(r"^\d{2}x\d+", "01x05 Pilot.mkv"));
+        assert!(!is_match(r"^\d{2}x\d+", "S01x05.mkv"));
+        assert!(is_match(r"\.(?:nfo|txt)$", "Movie.nfo"));
+        assert!(!is_match(r"\.(nfo|txt)$", "Movie.nfo.mkv"));
+        assert!(is_match("[^a-z ]+-[A-Z]{3,}", "x 42-ABCD"));
+        assert!(is_match(r"[\d\s]a", "1 a"));
+        assert!(!is_match(r"[^\D]b", "xb"));
+        assert!(is_match("a.c", "abc"));
+        assert!(is_match("", "anything"));
+        assert!(is_match("^$", ""));
+    }
+
+    #[test]
+    fn case_is_ignored_on_request() {
+        let regex = Regex::new("[s]ample|TRAILER").unwrap();
+        assert!(!regex.is_match("SAMPLE"));
+        assert!(regex.is_match_ignoring_case("SAMPLE"));
+        assert!(regex.is_match_ignoring_case("trailer"));
+    }
+
+    #[test]
+    fn nested_repetitions_stay_linear() {
+        let text = "a".repeat(5000);
+        assert!(!is_match("(a*)*b", &text));
+        assert!(is_match("(a|aa)+$", &text));
+    }
+
+    #[test]
+    fn invalid_patterns_are_rejected() {
+        for pattern in [
+            "(a", "a)", "[a", "*a", "a{2", "a{3,1}", "[z-a]", r"\q", "(?=a)",
+        ] {
+            assert!(Regex::new(pattern).is_err(), "{}", pattern);
+        }
+        assert!(Regex::new("(a{1000}){1000}").is_err());
+    }
+
+    #[test]
+    fn deep_nesting_is_rejected() {
+        let nested = |depth: usize| format!("{}a{}", "(".repeat(depth), ")".repeat(depth));
+        assert!(is_match(&nested(MAX_NESTING), "a"));
+        assert!(Regex::new(&nested(MAX_NESTING + 1)).is_err());
+        assert!(Regex::new(&nested(1_000_000)).is_err());
+        assert!(Regex::new(&format!("a{}", "?".repeat(1_000_000))).is_err());
+        assert!(is_match(&format!("a{}", "*".repeat(MAX_NESTING)), "a"));
+    }
+}
@@ -0,0 +1,91 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::log::warning;
+
+// Extension of the files with VLC options for the media file they're named after, e.g.
+// `Movie.mkv.vlcopts` for `Movie.mkv`.
+pub(crate) static OPTIONS_EXTENSION: &str = "vlcopts";
+
+pub(crate) fn sidecar_path(path: &Path) -> PathBuf {
+    let mut sidecar = path.as_os_str().to_os_string();
+    sidecar.push(".");
+    sidecar.push(OPTIONS_EXTENSION);
+    sidecar.into()
+}
+
+// Parses a time in seconds, `90` or `90.5`, or with minutes and hours, `1:30` or `1:02:03.5`,
+// into milliseconds.
+fn parse_time(time: &str) -> Option<u64> {
+    let mut parts = time.rsplit(':');
+    let secs: f64 = parts.next()?.parse().ok()?;
+    let millis = (secs * 1000.0).round();
+    // Times that don't fit in milliseconds are rejected rather than saturated
+    if !millis.is_finite() || millis < 0.0 || millis >= u64::MAX as f64 {
+        return None;
+    }
+
+    let mut millis = millis as u64;
+    for factor in [60_000u64, 3_600_000] {
+        let Some(part) = parts.next() else {
+            return Some(millis);
+        };
+        millis = part
+            .parse::<u64>()
+            .ok()?
+            .checked_mul(factor)?
+            .checked_add(millis)?;
+    }
+
+    parts.next().is_none().then_some(millis)
+}
+
+// Reads the start and stop time from an options file. Each line sets an option, like
+// `start-time=1:30` or `stop-time=5400`, blank lines and lines starting with `#` are ignored.
+pub(crate) fn read_time_options(path: &Path) -> (Option<u64>, Option<u64>) {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) => {
+            warning!("{} can't be read: {}", path.display(), e);
+            return (None, None);
+        }
+    };
+
+    let (mut start, mut stop) = (None, None);
+    for line in contents.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (option, time) = match line.split_once('=') {
+            Some((option, value)) => (option.trim(), parse_time(value.trim())),
+            None => (line, None),
+        };
+        match (option, time) {
+            ("start-time", Some(time)) => start = Some(time),
+            ("stop-time", Some(time)) => stop = Some(time),
+            _ => warning!("{}: ignored invalid option `{}`", path.display(), line),
+        }
+    }
+
+    (start, stop)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overflowing_times_are_invalid() {
+        assert_eq!(parse_time("1:02:03.5"), Some(3_723_500));
+        assert_eq!(parse_time(&format!("{}:00", u64::MAX)), None);
+        assert_eq!(
+            parse_time(&format!("{}:00:00", u64::MAX / 3_600_000 + 1)),
+            None
+        );
+        assert_eq!(parse_time("1e300"), None);
+        assert_eq!(parse_time("-1"), None);
+    }
+}
@@ -0,0 +1,104 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+
+// A directory below the system's temporary directory that's removed when it's dropped.
+pub(crate) struct TempDir(PathBuf);
+
+impl TempDir {
+    pub(crate) fn new() -> Self {
+        Self::new_in(&std::env::temp_dir())
+    }
+
+    // A directory below `parent`, which may be relative to the current directory.
+    pub(crate) fn new_in(parent: &Path) -> Self {
+        let id = NEXT_DIR.fetch_add(1, Ordering::Relaxed);
+        let path = parent.join(format!("vlc-playlist-test-{}-{}", process::id(), id));
+        fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.0
+    }
+
+    // Writes a file at `path` relative to the directory, its parents are created as needed.
+    pub(crate) fn file(&self, path: &str, data: &[u8]) -> PathBuf {
+        let path = self.0.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, data).unwrap();
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn ebml_element(id: &[u8], data: &[u8]) -> Vec<u8> {
+    assert!(data.len() < 0x7f);
+    [id, &[0x80 | data.len() as u8], data].concat()
+}
+
+// The smallest Matroska file the matroska crate accepts, a segment with only its info.
+pub(crate) fn mkv(duration_ms: f32) -> Vec<u8> {
+    let header = [
+        ebml_element(&[0x42, 0x86], &[1]),
+        ebml_element(&[0x42, 0xf7], &[1]),
+        ebml_element(&[0x42, 0xf2], &[4]),
+        ebml_element(&[0x42, 0xf3], &[8]),
+        ebml_element(&[0x42, 0x82], b"matroska"),
+        ebml_element(&[0x42, 0x87], &[4]),
+        ebml_element(&[0x42, 0x85], &[2]),
+    ]
+    .concat();
+    let info = [
+        ebml_element(&[0x2a, 0xd7, 0xb1], &[0x0f, 0x42, 0x40]),
+        ebml_element(&[0x44, 0x89], &duration_ms.to_be_bytes()),
+    ]
+    .concat();
+    let segment = ebml_element(&[0x15, 0x49, 0xa9, 0x66], &info);
+    [
+        ebml_element(&[0x1a, 0x45, 0xdf, 0xa3], &header),
+        ebml_element(&[0x18, 0x53, 0x80, 0x67], &segment),
+    ]
+    .concat()
+}
+
+pub(crate) fn mp4_box(kind: &[u8], data: &[u8]) -> Vec<u8> {
+    [&(8 + data.len() as u32).to_be_bytes(), kind, data].concat()
+}
+
+// An MP4 file with a movie header and no tracks.
+pub(crate) fn mp4(duration_ms: u32) -> Vec<u8> {
+    mp4_with_udta(duration_ms, &[])
+}
+
+// An MP4 file with a movie header and these boxes in its user data, which is left out if there
+// are none.
+pub(crate) fn mp4_with_udta(duration_ms: u32, udta: &[u8]) -> Vec<u8> {
+    let mut mvhd = vec![0; 12];
+    mvhd.extend(1000u32.to_be_bytes());
+    mvhd.extend(duration_ms.to_be_bytes());
+    mvhd.extend([0, 1, 0, 0, 1, 0]);
+    mvhd.extend([0; 10]);
+    for value in [0x10000u32, 0, 0, 0, 0x10000, 0, 0, 0, 0x4000_0000] {
+        mvhd.extend(value.to_be_bytes());
+    }
+    mvhd.extend([0; 24]);
+    mvhd.extend(1u32.to_be_bytes());
+
+    let ftyp = mp4_box(b"ftyp", b"isom\0\0\0\0isom");
+    let mut moov = mp4_box(b"mvhd", &mvhd);
+    if !udta.is_empty() {
+        moov.extend(mp4_box(b"udta", udta));
+    }
+    [ftyp, mp4_box(b"moov", &moov)].concat()
+}
@@ -0,0 +1,73 @@
+// Release tags that are removed from titles, compared case-insensitively with whole words.
+static RELEASE_TAGS: &[&str] = &[
+    "480p", "576p", "720p", "1080p", "1080i", "2160p", "4k", "uhd", "hdr", "hdr10", "dv", "x264",
+    "x265", "h264", "h265", "hevc", "avc", "xvid", "divx", "10bit", "8bit", "bluray", "bdrip",
+    "brrip", "remux", "web", "webrip", "web-dl", "webdl", "hdtv", "hdrip", "dvdrip", "dvd",
+    "repack", "proper", "rerip", "internal", "limited", "aac", "ac3", "dts", "flac", "mp3",
+];
+
+/// Cleans up a directory name for display, as done with the `clean_titles` scan option:
+///
+/// 1. Groups in square or curly brackets are removed, e.g. `[1080p]` or `{tag}`.
+/// 2. Dots and underscores are replaced with spaces, so `Some.Show_S01` becomes `Some Show S01`.
+/// 3. Words that are common release tags are removed, like `REPACK`, `1080p`, `x264` or
+///    `BluRay`.
+/// 4. Runs of whitespace are collapsed into a single space, and dashes left at the start or end
+///    are removed.
+///
+/// Parentheses are kept because they usually contain a year, and an unclosed bracket is kept
+/// as-is. If nothing is left of the name, it's returned unchanged.
+pub fn clean_title(name: &str) -> String {
+    let mut unbracketed = String::with_capacity(name.len());
+    // The closing bracket of the current group and its start in `name`
+    let mut group = None;
+    for (idx, c) in name.char_indices() {
+        match (group, c) {
+            (None, '[') => group = Some((']', idx)),
+            (None, '{') => group = Some(('}', idx)),
+            (None, '.' | '_') => unbracketed.push(' '),
+            (None, c) => unbracketed.push(c),
+            (Some((end, _)), c) if c == end => {
+                group = None;
+                unbracketed.push(' ');
+            }
+            (Some(_), _) => (),
+        }
+    }
+
+    // An unclosed bracket is kept as-is
+    if let Some((_, start)) = group {
+        unbracketed.push_str(&name[start..]);
+    }
+
+    let mut words: Vec<&str> = unbracketed
+        .split_whitespace()
+        .filter(|word| {
+            // Tags are often attached to the group name, like `x264-[GRP]`
+            let bare = word.trim_matches('-');
+            !RELEASE_TAGS
+                .iter()
+                .any(|tag| tag.eq_ignore_ascii_case(bare))
+        })
+        .collect();
+
+    // Drop dashes that only separated removed tags
+    while words
+        .last()
+        .is_some_and(|word| word.chars().all(|c| c == '-'))
+    {
+        words.pop();
+    }
+    while words
+        .first()
+        .is_some_and(|word| word.chars().all(|c| c == '-'))
+    {
+        words.remove(0);
+    }
+
+    if words.is_empty() {
+        name.into()
+    } else {
+        words.join(" ")
+    }
+}
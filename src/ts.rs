@@ -0,0 +1,182 @@
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use crate::{fallback_title, Track};
+
+static SYNC_BYTE: u8 = 0x47;
+static PACKET_SIZE: usize = 188;
+// Number of bytes at the start and end of the file that are searched for timestamps
+static SCAN_SIZE: u64 = 1 << 20;
+// PTS values are 33-bit counters of a 90 kHz clock
+static PTS_WRAP: u64 = 1 << 33;
+
+// Returns the offset of the packet within each unit, `.m2ts` files prefix every packet with a
+// 4 byte timestamp. `None` if the data isn't a transport stream.
+fn packet_offset(data: &[u8]) -> Option<usize> {
+    [0, 4].into_iter().find(|&offset| {
+        let unit = PACKET_SIZE + offset;
+        let packets: Vec<u8> = (0..3)
+            .filter_map(|n| data.get(offset + n * unit).copied())
+            .collect();
+        packets.len() == 3 && packets.iter().all(|&b| b == SYNC_BYTE)
+    })
+}
+
+// Reads the PTS of a packet that starts a PES packet, along with its PID.
+fn packet_pts(packet: &[u8]) -> Option<(u16, u64)> {
+    if packet[0] != SYNC_BYTE || packet[1] & 0x40 == 0 {
+        return None;
+    }
+
+    let pid = u16::from_be_bytes([packet[1] & 0x1F, packet[2]]);
+    let payload_start = match (packet[3] >> 4) & 0x03 {
+        0x01 => 4,
+        0x03 => 5 + packet[4] as usize,
+        _ => return None,
+    };
+
+    let pes = packet.get(payload_start..)?;
+    if pes.get(..3)? != [0, 0, 1] || pes.get(7)? & 0x80 == 0 {
+        return None;
+    }
+
+    let p = pes.get(9..14)?;
+    let pts = ((p[0] as u64 >> 1) & 0x07) << 30
+        | (p[1] as u64) << 22
+        | (p[2] as u64 >> 1) << 15
+        | (p[3] as u64) << 7
+        | p[4] as u64 >> 1;
+    Some((pid, pts))
+}
+
+// All timestamps in `data`, sorted by the order of the packets.
+fn timestamps(data: &[u8], offset: usize) -> Vec<(u16, u64)> {
+    let unit = PACKET_SIZE + offset;
+    let Some(start) = (0..unit.min(data.len())).find(|&start| {
+        data.get(start + offset) == Some(&SYNC_BYTE)
+            && data.get(start + offset + unit) == Some(&SYNC_BYTE)
+    }) else {
+        return vec![];
+    };
+
+    data[start..]
+        .chunks_exact(unit)
+        .filter_map(|chunk| packet_pts(&chunk[offset..]))
+        .collect()
+}
+
+fn read_range(file: &mut File, start: u64, len: u64) -> Option<Vec<u8>> {
+    file.seek(SeekFrom::Start(start)).ok()?;
+    let mut data = vec![];
+    file.take(len).read_to_end(&mut data).ok()?;
+    Some(data)
+}
+
+// Estimates the duration from the first and last presentation timestamp of the first stream that
+// has one, the duration is 0 if it can't be determined. Files that aren't transport streams are
+// skipped.
+pub(crate) fn ts_meta<P: AsRef<Path>>(path: P) -> Option<Track> {
+    let path = path.as_ref();
+    let mut file = File::open(path).ok()?;
+    let size = file.metadata().ok()?.len();
+
+    let head = read_range(&mut file, 0, SCAN_SIZE)?;
+    let offset = packet_offset(&head)?;
+
+    let duration = timestamps(&head, offset)
+        .first()
+        .copied()
+        .and_then(|(pid, first)| {
+            let tail_start = size.saturating_sub(SCAN_SIZE);
+            let tail = read_range(&mut file, tail_start, SCAN_SIZE)?;
+            let (_, last) = timestamps(&tail, offset)
+                .into_iter()
+                .rfind(|&(p, _)| p == pid)?;
+            Some((last + PTS_WRAP - first) % PTS_WRAP / 90)
+        })
+        .unwrap_or(0);
+
+    Some(Track::new(
+        path.to_path_buf(),
+        fallback_title(path),
+        duration,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    // A packet of `pid`, that starts a PES packet with a timestamp if there is one.
+    fn packet(pid: u16, pts: Option<u64>) -> Vec<u8> {
+        let mut packet = vec![SYNC_BYTE, (pid >> 8) as u8 & 0x1F, pid as u8, 0x10];
+        if let Some(pts) = pts {
+            packet[1] |= 0x40;
+            packet.extend([0, 0, 1, 0xE0, 0, 0, 0x80, 0x80, 5]);
+            packet.extend([
+                0x21 | (pts >> 29) as u8 & 0x0E,
+                (pts >> 22) as u8,
+                (pts >> 14) as u8 | 0x01,
+                (pts >> 7) as u8,
+                (pts << 1) as u8 | 0x01,
+            ]);
+        }
+        packet.resize(PACKET_SIZE, 0xFF);
+        packet
+    }
+
+    fn duration(dir: &TempDir, name: &str, packets: &[Vec<u8>]) -> Option<u64> {
+        let path = dir.file(name, &packets.concat());
+        ts_meta(path).map(|track| track.duration())
+    }
+
+    #[test]
+    fn durations_are_estimated_from_timestamps() {
+        let dir = TempDir::new();
+        let packets = [
+            packet(0x100, None),
+            packet(0x100, Some(90_000)),
+            packet(0x101, Some(10_000)),
+            packet(0x100, None),
+            packet(0x100, Some(90_000 + 90 * 5000)),
+            packet(0x101, Some(90_000 + 90 * 9000)),
+        ];
+        assert_eq!(duration(&dir, "a.ts", &packets), Some(5000));
+
+        // The counter wraps around
+        let packets = [
+            packet(0x100, Some(PTS_WRAP - 90 * 1000)),
+            packet(0x100, None),
+            packet(0x100, Some(90 * 1000)),
+        ];
+        assert_eq!(duration(&dir, "b.ts", &packets), Some(2000));
+
+        // `.m2ts` packets are prefixed with a timestamp of their own
+        let packets: Vec<_> = [
+            packet(0x100, Some(0)),
+            packet(0x100, None),
+            packet(0x100, Some(90 * 3000)),
+        ]
+        .into_iter()
+        .map(|packet| [vec![0; 4], packet].concat())
+        .collect();
+        assert_eq!(duration(&dir, "c.m2ts", &packets), Some(3000));
+    }
+
+    #[test]
+    fn other_files_are_skipped() {
+        let dir = TempDir::new();
+        let packets = [
+            packet(0x100, None),
+            packet(0x100, None),
+            packet(0x100, None),
+        ];
+        assert_eq!(duration(&dir, "a.ts", &packets), Some(0));
+        assert_eq!(duration(&dir, "b.ts", &[vec![0; 3 * PACKET_SIZE]]), None);
+        assert_eq!(duration(&dir, "c.ts", &[packets[0][..100].to_vec()]), None);
+    }
+}
@@ -0,0 +1,161 @@
+use std::{
+    fs::File,
+    io::{BufReader, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+// Reads the header of the box at the current position and returns its type and the size of its
+// contents. The contents of a box with size 0 extend to the end of `end`.
+fn read_box_header<R: Read + Seek>(reader: &mut R, end: u64) -> Option<([u8; 4], u64)> {
+    let start = reader.stream_position().ok()?;
+    let mut header = [0; 8];
+    reader.read_exact(&mut header).ok()?;
+    let name = header[4..].try_into().ok()?;
+
+    let (size, header_size) = match u32::from_be_bytes(header[..4].try_into().ok()?) {
+        0 => (end - start, 8),
+        1 => {
+            let mut size = [0; 8];
+            reader.read_exact(&mut size).ok()?;
+            (u64::from_be_bytes(size), 16)
+        }
+        size => (size as u64, 8),
+    };
+
+    Some((name, size.checked_sub(header_size)?))
+}
+
+// Moves the reader to the contents of the first box named `name` before `end`, and returns the
+// position its contents end at.
+fn find_box<R: Read + Seek>(reader: &mut R, name: &[u8; 4], end: u64) -> Option<u64> {
+    loop {
+        if reader.stream_position().ok()? >= end {
+            return None;
+        }
+
+        let (box_name, size) = read_box_header(reader, end)?;
+        let contents_end = reader.stream_position().ok()?.checked_add(size)?;
+        if &box_name == name {
+            return Some(contents_end);
+        }
+        reader.seek(SeekFrom::Start(contents_end)).ok()?;
+    }
+}
+
+// Reads the title from the 3GPP `titl` box in the user data of an MP4 file, which some tools
+// write instead of or in addition to the iTunes-style `©nam` item.
+pub(crate) fn udta_title(path: &Path) -> Option<String> {
+    let file = File::open(path).ok()?;
+    let size = file.metadata().ok()?.len();
+    let mut reader = BufReader::new(file);
+
+    let moov_end = find_box(&mut reader, b"moov", size)?;
+    let udta_end = find_box(&mut reader, b"udta", moov_end)?;
+    let titl_end = find_box(&mut reader, b"titl", udta_end)?;
+
+    // The title follows the version, flags and language of the box
+    reader.seek(SeekFrom::Current(6)).ok()?;
+    let len = titl_end.checked_sub(reader.stream_position().ok()?)?;
+    let mut data = vec![];
+    reader.take(len).read_to_end(&mut data).ok()?;
+
+    // The title is UTF-8, or UTF-16 if it starts with a byte order mark
+    let title = match data.strip_prefix(&[0xfe, 0xff]) {
+        Some(utf16) => {
+            let units: Vec<u16> = utf16
+                .chunks_exact(2)
+                .map(|unit| u16::from_be_bytes([unit[0], unit[1]]))
+                .take_while(|&unit| unit != 0)
+                .collect();
+            String::from_utf16_lossy(&units)
+        }
+        None => {
+            let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+            String::from_utf8_lossy(&data[..end]).into_owned()
+        }
+    };
+
+    let title = title.trim();
+    (!title.is_empty()).then(|| title.to_string())
+}
+
+// Reads the artist from the iTunes-style `©ART` item in the metadata of an MP4 file, which the
+// mp4 crate doesn't expose.
+pub(crate) fn ilst_artist(path: &Path) -> Option<String> {
+    let file = File::open(path).ok()?;
+    let size = file.metadata().ok()?.len();
+    let mut reader = BufReader::new(file);
+
+    let moov_end = find_box(&mut reader, b"moov", size)?;
+    let udta_end = find_box(&mut reader, b"udta", moov_end)?;
+    let meta_end = find_box(&mut reader, b"meta", udta_end)?;
+    // The items follow the version and flags of the `meta` box
+    reader.seek(SeekFrom::Current(4)).ok()?;
+    let ilst_end = find_box(&mut reader, b"ilst", meta_end)?;
+    let item_end = find_box(&mut reader, b"\xa9ART", ilst_end)?;
+    let data_end = find_box(&mut reader, b"data", item_end)?;
+
+    // The text follows the type and locale of the `data` box
+    reader.seek(SeekFrom::Current(8)).ok()?;
+    let len = data_end.checked_sub(reader.stream_position().ok()?)?;
+    let mut data = vec![];
+    reader.take(len).read_to_end(&mut data).ok()?;
+
+    let artist = String::from_utf8_lossy(&data);
+    let artist = artist.trim();
+    (!artist.is_empty()).then(|| artist.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{mp4, mp4_box, mp4_with_udta, TempDir};
+
+    // A `titl` box with this title after its version, flags and language.
+    fn titl(title: &[u8]) -> Vec<u8> {
+        mp4_box(b"titl", &[&[0, 0, 0, 0, 0x15, 0xc7], title].concat())
+    }
+
+    #[test]
+    fn titles_are_read_from_titl_boxes() {
+        let dir = TempDir::new();
+        let title = |name: &str, data: &[u8]| udta_title(&dir.file(name, data));
+
+        let utf8 = mp4_with_udta(1000, &titl("Ünïcode\0".as_bytes()));
+        assert_eq!(title("a.mp4", &utf8), Some("Ünïcode".into()));
+
+        let utf16: Vec<u8> = [0xfe, 0xff]
+            .into_iter()
+            .chain("Wide".encode_utf16().flat_map(u16::to_be_bytes))
+            .chain([0, 0])
+            .collect();
+        assert_eq!(
+            title("b.mp4", &mp4_with_udta(1000, &titl(&utf16))),
+            Some("Wide".into())
+        );
+
+        // Other user data comes before it
+        let udta = [mp4_box(b"cprt", b"\0\0\0\0\0\0(c)"), titl(b" Spaced ")].concat();
+        assert_eq!(
+            title("c.mp4", &mp4_with_udta(1000, &udta)),
+            Some("Spaced".into())
+        );
+    }
+
+    #[test]
+    fn missing_or_empty_titles_are_none() {
+        let dir = TempDir::new();
+        let title = |name: &str, data: &[u8]| udta_title(&dir.file(name, data));
+
+        assert_eq!(title("a.mp4", &mp4(1000)), None);
+        assert_eq!(title("b.mp4", &mp4_with_udta(1000, &titl(b"  \0"))), None);
+        let other = mp4_box(b"cprt", b"\0\0\0\0\0\0(c)");
+        assert_eq!(title("c.mp4", &mp4_with_udta(1000, &other)), None);
+
+        // A box that's smaller than its own header ends the search
+        let mut invalid = mp4_with_udta(1000, &titl(b"Title"));
+        let len = invalid.len();
+        invalid[len - 19..len - 15].copy_from_slice(&4u32.to_be_bytes());
+        assert_eq!(title("d.mp4", &invalid), None);
+    }
+}
@@ -1,18 +1,35 @@
-use std::io::Write;
+use std::{
+    borrow::Cow,
+    ffi::OsStr,
+    fmt,
+    io::{self, Write},
+    ops::Range,
+    path::{Component, Path, PathBuf, Prefix},
+};
 
-use crate::{Playlist, PlaylistNode};
+use url_escape::percent_encoding::{percent_encode, AsciiSet};
+
+use crate::{format_duration, log::warning, Chapter, Playlist, PlaylistNode, Track};
 
 static XML_HEADER: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>";
 static PLAYLIST_START_TAG: &str = "<playlist xmlns=\"http://xspf.org/ns/0/\" xmlns:vlc=\"http://www.videolan.org/vlc/playlist/ns/0/\" version=\"1\">";
+static STANDARD_PLAYLIST_START_TAG: &str =
+    "<playlist xmlns=\"http://xspf.org/ns/0/\" version=\"1\">";
 static PLAYLIST_END_TAG: &str = "</playlist>";
 static TITLE_START_TAG: &str = "<title>";
 static TITLE_END_TAG: &str = "</title>";
+static CREATOR_START_TAG: &str = "<creator>";
+static CREATOR_END_TAG: &str = "</creator>";
+static ANNOTATION_START_TAG: &str = "<annotation>";
+static ANNOTATION_END_TAG: &str = "</annotation>";
 static TRACKLIST_START_TAG: &str = "<trackList>";
 static TRACKLIST_END_TAG: &str = "</trackList>";
 static TRACK_START_TAG: &str = "<track>";
 static TRACK_END_TAG: &str = "</track>";
 static LOCATION_START_TAG: &str = "<location>";
 static LOCATION_END_TAG: &str = "</location>";
+static IMAGE_START_TAG: &str = "<image>";
+static IMAGE_END_TAG: &str = "</image>";
 static DURATION_START_TAG: &str = "<duration>";
 static DURATION_END_TAG: &str = "</duration>";
 static EXTENSION_START_TAG: &str =
@@ -20,95 +37,942 @@ static EXTENSION_START_TAG: &str =
 static EXTENSION_END_TAG: &str = "</extension>";
 static VLC_ID_START_TAG: &str = "<vlc:id>";
 static VLC_ID_END_TAG: &str = "</vlc:id>";
+static VLC_OPTION_START_TAG: &str = "<vlc:option>";
+static VLC_OPTION_END_TAG: &str = "</vlc:option>";
+static VLC_TOTAL_DURATION_START_TAG: &str = "<vlc:total-duration>";
+static VLC_TOTAL_DURATION_END_TAG: &str = "</vlc:total-duration>";
+static VLC_TRACK_COUNT_START_TAG: &str = "<vlc:track-count>";
+static VLC_TRACK_COUNT_END_TAG: &str = "</vlc:track-count>";
+
+// Characters that must be percent-encoded within a single path segment of a URI. Separators are
+// encoded because they can't be part of a segment, `&` because the URI is embedded in XML as-is.
+const PATH_SEGMENT: &AsciiSet = &url_escape::PATH
+    .add(b'%')
+    .add(b'/')
+    .add(b'\\')
+    .add(b'&')
+    .add(b'[')
+    .add(b']')
+    .add(b'^')
+    .add(b'|');
+
+// Encodes the raw bytes of the segment so file names that aren't valid UTF-8 are preserved exactly.
+// On Windows these bytes are the UTF-16 file name converted to UTF-8.
+fn encode_path_segment(segment: &OsStr) -> String {
+    percent_encode(segment.as_encoded_bytes(), PATH_SEGMENT).to_string()
+}
+
+// Converts a path into an RFC 8089 file URI, e.g. `/home/me/a.mkv` becomes `file:///home/me/a.mkv`,
+// `C:\Movies\a.mkv` becomes `file:///C:/Movies/a.mkv` and `\\server\share\a.mkv` becomes
+// `file://server/share/a.mkv`. Relative paths are made absolute first, the first segment would be
+// read as the host otherwise.
+pub(crate) fn file_uri(path: &Path) -> String {
+    let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let mut uri = String::from("file://");
+
+    for component in path.components() {
+        match component {
+            Component::Prefix(prefix) => match prefix.kind() {
+                Prefix::Disk(drive) | Prefix::VerbatimDisk(drive) => {
+                    uri.push('/');
+                    uri.push(drive as char);
+                    uri.push(':');
+                }
+                Prefix::UNC(server, share) | Prefix::VerbatimUNC(server, share) => {
+                    uri.push_str(&encode_path_segment(server));
+                    uri.push('/');
+                    uri.push_str(&encode_path_segment(share));
+                }
+                Prefix::Verbatim(segment) | Prefix::DeviceNS(segment) => {
+                    uri.push('/');
+                    uri.push_str(&encode_path_segment(segment));
+                }
+            },
+            Component::RootDir => uri.push('/'),
+            Component::CurDir | Component::ParentDir | Component::Normal(_) => {
+                if !uri.ends_with('/') {
+                    uri.push('/');
+                }
+                uri.push_str(&encode_path_segment(component.as_os_str()));
+            }
+        }
+    }
+
+    uri
+}
+
+// Converts a relative path into a relative URI reference, e.g. `Series/a b.mkv` becomes
+// `Series/a%20b.mkv`.
+fn relative_uri(path: &Path) -> String {
+    let segments: Vec<_> = path
+        .components()
+        .map(|component| encode_path_segment(component.as_os_str()))
+        .collect();
+
+    // A colon in the first segment would be read as the end of a scheme
+    if segments.first().is_some_and(|s| s.contains(':')) {
+        format!("./{}", segments.join("/"))
+    } else {
+        segments.join("/")
+    }
+}
+
+// Joins a relative path to a base URL, e.g. `Series/a b.mkv` and `http://nas.local/media` become
+// `http://nas.local/media/Series/a%20b.mkv`.
+fn url_location(base_url: &str, path: &Path) -> String {
+    let mut url = base_url.trim_end_matches('/').to_string();
+    for component in path.components() {
+        url.push('/');
+        url.push_str(&encode_path_segment(component.as_os_str()));
+    }
+
+    url
+}
+
+// Files in a zip archive are opened through VLC's zip access, e.g.
+// `zip:///music/live.zip!/01%20Intro.mp3`. They're always written with an absolute location.
+pub(crate) fn archive_uri(archive: &Path, location: &Path) -> String {
+    let inner = location.strip_prefix(archive).unwrap_or(location);
+    // `!` ends the archive path, so it's encoded in it
+    let mut uri = file_uri(archive)
+        .replacen("file://", "zip://", 1)
+        .replace('!', "%21");
+    uri.push('!');
+    for component in inner.components() {
+        uri.push('/');
+        uri.push_str(&encode_path_segment(component.as_os_str()));
+    }
+
+    uri
+}
+
+// The location of a track in the flat formats. They're file URIs, so paths with line breaks or
+// that aren't valid UTF-8 are written exactly and on a single line.
+pub(crate) fn plain_location(track: &Track) -> String {
+    match track.archive() {
+        Some(archive) => archive_uri(archive, track.location()),
+        None => file_uri(track.location()),
+    }
+}
+
+fn location_uri(path: &Path, options: &XmlOptions) -> String {
+    let Some(ref base) = options.relative_to else {
+        return file_uri(path);
+    };
+
+    match path.strip_prefix(base) {
+        Ok(relative) => match options.base_url {
+            Some(ref url) => url_location(url, relative),
+            None => relative_uri(relative),
+        },
+        Err(_) => {
+            warning!(
+                "{} is not in {}, using an absolute location",
+                path.display(),
+                base.display()
+            );
+            file_uri(path)
+        }
+    }
+}
+
+// Line breaks are written as character references, so titles that contain them can't change the
+// line structure of the output and every line ends with a single `\n` on all platforms.
+fn escape_line_breaks(text: Cow<'_, str>) -> Cow<'_, str> {
+    if text.contains(['\r', '\n']) {
+        text.replace('\r', "&#13;").replace('\n', "&#10;").into()
+    } else {
+        text
+    }
+}
+
+fn encode_text(text: &str) -> Cow<'_, str> {
+    escape_line_breaks(html_escape::encode_text(text))
+}
+
+fn encode_attribute(text: &str) -> Cow<'_, str> {
+    escape_line_breaks(html_escape::encode_double_quoted_attribute(text))
+}
 
-fn nodes_into_xml<W: Write>(writer: &mut W, nodes: &[PlaylistNode], indent: usize) {
-    for node in nodes {
+// Writes a line indented by `indent` levels, or without indentation and line break if the output
+// is compact.
+fn write_line<W: Write>(
+    writer: &mut W,
+    options: &XmlOptions,
+    indent: usize,
+    line: fmt::Arguments,
+) -> io::Result<()> {
+    if !options.compact {
+        write!(writer, "{}", options.indent.repeat(indent))?;
+    }
+    if options.escape_non_ascii {
+        write!(writer, "{}", escape_non_ascii(&line.to_string()))?;
+    } else {
+        writer.write_fmt(line)?;
+    }
+    if !options.compact {
+        writeln!(writer)?;
+    }
+
+    Ok(())
+}
+
+fn escape_non_ascii(text: &str) -> Cow<'_, str> {
+    if text.is_ascii() {
+        return text.into();
+    }
+
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if c.is_ascii() {
+            escaped.push(c);
+        } else {
+            escaped.push_str(&format!("&#{};", c as u32));
+        }
+    }
+    escaped.into()
+}
+
+// Files with chapters are written as a node with the item for the whole file, followed by an item
+// for each chapter. Directories are written with an explicit stack of the nodes left in each open
+// directory, so deeply nested directories can't overflow the call stack.
+fn nodes_into_xml<W: Write>(
+    writer: &mut W,
+    playlist: &Playlist,
+    nodes: &[PlaylistNode],
+    ids: &VlcIds,
+    indent: usize,
+    options: &XmlOptions,
+) -> io::Result<()> {
+    let mut stack = vec![nodes.iter()];
+    while let Some(remaining) = stack.last_mut() {
+        let Some(node) = remaining.next() else {
+            stack.pop();
+            if !stack.is_empty() {
+                let indent = indent + stack.len() - 1;
+                write_line(writer, options, indent, format_args!("</vlc:node>"))?;
+            }
+            continue;
+        };
+
+        let indent = indent + stack.len() - 1;
         match node {
             PlaylistNode::File(idx, _) => {
-                for _ in 0..indent {
-                    write!(writer, "\t").unwrap();
+                let track = playlist.track(*idx);
+                if track.chapters().is_empty() {
+                    write_line(
+                        writer,
+                        options,
+                        indent,
+                        format_args!("<vlc:item tid=\"{}\"/>", idx),
+                    )?;
+                    continue;
+                }
+
+                write_line(
+                    writer,
+                    options,
+                    indent,
+                    format_args!("<vlc:node title=\"{}\">", encode_attribute(track.title())),
+                )?;
+
+                write_line(
+                    writer,
+                    options,
+                    indent + 1,
+                    format_args!("<vlc:item tid=\"{}\"/>", idx),
+                )?;
+                for id in ids.chapters(*idx) {
+                    write_line(
+                        writer,
+                        options,
+                        indent + 1,
+                        format_args!("<vlc:item tid=\"{}\"/>", id),
+                    )?;
                 }
-                writeln!(writer, "<vlc:item tid=\"{}\"/>", idx).unwrap();
+
+                write_line(writer, options, indent, format_args!("</vlc:node>"))?;
             }
             PlaylistNode::Dir {
                 ref title,
                 ref nodes,
+                ..
             } => {
-                for _ in 0..indent {
-                    write!(writer, "\t").unwrap();
-                }
+                write_line(
+                    writer,
+                    options,
+                    indent,
+                    format_args!("<vlc:node title=\"{}\">", encode_attribute(title)),
+                )?;
+
+                stack.push(nodes.iter());
+            }
+        }
+    }
 
-                writeln!(writer, "<vlc:node title=\"{}\">", title).unwrap();
+    Ok(())
+}
 
-                nodes_into_xml(writer, nodes, indent + 1);
+// The VLC ids of the chapters of a playlist, numbered after the tracks. The id of a track is its
+// index in the track list, which is also the index nodes refer to, so the `<vlc:id>` of each track
+// and the `tid` of the items that refer to it agree. These indices are dense after filtering too:
+// scanning only adds the tracks it includes, and `Playlist::retain_tracks` renumbers the tracks it
+// keeps, so the track ids are always `0..n`.
+struct VlcIds {
+    chapters: Vec<Range<usize>>,
+}
 
-                for _ in 0..indent {
-                    write!(writer, "\t").unwrap();
-                }
+impl VlcIds {
+    fn new(playlist: &Playlist) -> Self {
+        let mut next = playlist.tracks().count();
+        let chapters = playlist
+            .tracks()
+            .map(|track| {
+                let start = next;
+                next += track.chapters().len();
+                start..next
+            })
+            .collect();
 
-                writeln!(writer, "</vlc:node>").unwrap();
-            }
-        }
+        VlcIds { chapters }
+    }
+
+    fn chapters(&self, idx: usize) -> Range<usize> {
+        self.chapters[idx].clone()
     }
 }
 
-pub(crate) fn into_xml<W: Write>(writer: &mut W, playlist: Playlist) {
-    writeln!(writer, "{}", XML_HEADER).unwrap();
-    writeln!(writer, "{}", PLAYLIST_START_TAG).unwrap();
-    writeln!(
-        writer,
-        "\t{}Media Library{}",
-        TITLE_START_TAG, TITLE_END_TAG
-    )
-    .unwrap();
-    writeln!(writer, "\t{}", TRACKLIST_START_TAG).unwrap();
+// Formats milliseconds as the seconds VLC expects in its start and stop time options.
+pub(crate) fn option_secs(millis: u64) -> String {
+    format!("{}.{:03}", millis / 1000, millis % 1000)
+}
 
-    for (idx, track) in playlist.tracks().enumerate() {
-        writeln!(writer, "\t\t{}", TRACK_START_TAG).unwrap();
-        writeln!(
-            writer,
-            "\t\t\t{}file://{}{}",
+fn track_into_xml<W: Write>(
+    writer: &mut W,
+    track: &Track,
+    id: usize,
+    title: &str,
+    duration: u64,
+    vlc_options: &[String],
+    options: &XmlOptions,
+) -> io::Result<()> {
+    write_line(writer, options, 2, format_args!("{}", TRACK_START_TAG))?;
+    write_line(
+        writer,
+        options,
+        3,
+        format_args!(
+            "{}{}{}",
             LOCATION_START_TAG,
-            url_escape::encode_component(&track.location().to_string_lossy()),
+            match track.archive() {
+                Some(archive) => archive_uri(archive, track.location()),
+                None => location_uri(track.location(), options),
+            },
             LOCATION_END_TAG
-        )
-        .unwrap();
+        ),
+    )?;
+
+    write_line(
+        writer,
+        options,
+        3,
+        format_args!("{}{}{}", TITLE_START_TAG, encode_text(title), TITLE_END_TAG),
+    )?;
 
-        writeln!(
+    if let Some(creator) = track.creator() {
+        write_line(
             writer,
-            "\t\t\t{}{}{}",
-            TITLE_START_TAG,
-            html_escape::encode_text(track.title()),
-            TITLE_END_TAG
-        )
-        .unwrap();
+            options,
+            3,
+            format_args!(
+                "{}{}{}",
+                CREATOR_START_TAG,
+                encode_text(creator),
+                CREATOR_END_TAG
+            ),
+        )?;
+    }
+
+    if let Some(annotation) = track.annotation() {
+        write_line(
+            writer,
+            options,
+            3,
+            format_args!(
+                "{}{}{}",
+                ANNOTATION_START_TAG,
+                encode_text(annotation),
+                ANNOTATION_END_TAG
+            ),
+        )?;
+    }
 
-        writeln!(
+    if let Some(image) = track.image() {
+        write_line(
             writer,
-            "\t\t\t{}{}{}",
+            options,
+            3,
+            format_args!(
+                "{}{}{}",
+                IMAGE_START_TAG,
+                location_uri(image, options),
+                IMAGE_END_TAG
+            ),
+        )?;
+    }
+
+    write_line(
+        writer,
+        options,
+        3,
+        format_args!(
+            "{}{}{}",
             DURATION_START_TAG,
-            track.duration(),
+            options.duration_unit.convert(duration),
             DURATION_END_TAG
-        )
-        .unwrap();
+        ),
+    )?;
+
+    if !options.vlc_extensions {
+        return write_line(writer, options, 2, format_args!("{}", TRACK_END_TAG));
+    }
+
+    write_line(writer, options, 3, format_args!("{}", EXTENSION_START_TAG))?;
+    write_line(
+        writer,
+        options,
+        4,
+        format_args!("{}{}{}", VLC_ID_START_TAG, id, VLC_ID_END_TAG),
+    )?;
+
+    for option in vlc_options {
+        write_line(
+            writer,
+            options,
+            4,
+            format_args!(
+                "{}{}{}",
+                VLC_OPTION_START_TAG,
+                encode_text(option),
+                VLC_OPTION_END_TAG
+            ),
+        )?;
+    }
+    write_line(writer, options, 3, format_args!("{}", EXTENSION_END_TAG))?;
+
+    write_line(writer, options, 2, format_args!("{}", TRACK_END_TAG))
+}
+
+/// The unit durations are written in.
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DurationUnit {
+    /// Milliseconds, as required by the XSPF specification and expected by VLC.
+    #[default]
+    Milliseconds,
+    /// Whole seconds, for players that expect durations in seconds.
+    Seconds,
+}
+
+impl DurationUnit {
+    fn convert(self, millis: u64) -> u64 {
+        match self {
+            DurationUnit::Milliseconds => millis,
+            DurationUnit::Seconds => millis / 1000,
+        }
+    }
+}
+
+/// Options that control how a playlist is written as XSPF.
+pub struct XmlOptions {
+    /// Title of the playlist.
+    pub title: String,
+    /// Write track locations relative to this directory. Tracks outside of it keep an absolute
+    /// location.
+    pub relative_to: Option<PathBuf>,
+    /// URL the locations relative to `relative_to` are appended to instead of writing relative
+    /// locations, e.g. to stream the library from a server.
+    pub base_url: Option<String>,
+    /// Unit of the track durations.
+    pub duration_unit: DurationUnit,
+    /// Write the playlist without indentation and line breaks.
+    pub compact: bool,
+    /// The indentation of each level, a tab by default.
+    pub indent: String,
+    /// Write the VLC extensions, i.e. the directory tree, the chapters and the options of tracks
+    /// like subtitles and start times. Without them the playlist is standard XSPF with a flat list
+    /// of tracks.
+    pub vlc_extensions: bool,
+    /// Leave out the directory tree, so the playlist is a flat list of all tracks and chapters.
+    pub flatten: bool,
+    /// Write non-ASCII characters as numeric character references like `&#233;`, for players
+    /// that can't read UTF-8. VLC reads both.
+    pub escape_non_ascii: bool,
+}
+
+impl Default for XmlOptions {
+    fn default() -> Self {
+        XmlOptions {
+            title: "Media Library".into(),
+            relative_to: None,
+            base_url: None,
+            duration_unit: DurationUnit::default(),
+            compact: false,
+            indent: "\t".into(),
+            vlc_extensions: true,
+            flatten: false,
+            escape_non_ascii: false,
+        }
+    }
+}
+
+/// Writes the playlist as XSPF. The output is UTF-8 without a byte order mark and lines end with
+/// `\n` on every platform.
+///
+/// # Example
+///
+/// Titles and locations are escaped, so the output reads back as the same playlist:
+///
+/// ```
+/// use vlc_playlist_generator::{into_xml, parse_xml, PlaylistBuilder, PlaylistNode, XmlOptions};
+///
+/// let mut builder = PlaylistBuilder::new();
+/// builder
+///     .add_directory("Tom & Jerry <\"Classics\">")
+///     .add_track("/media/a&b/<1>.mkv", "Café 日本 & \"more\"", 1_500)
+///     .end_directory();
+///
+/// let mut xml = vec![];
+/// into_xml(&mut xml, builder.build(), &XmlOptions::default()).unwrap();
+///
+/// let playlist = parse_xml(xml.as_slice(), None, None).unwrap();
+/// let track = playlist.tracks().next().unwrap();
+/// assert_eq!(track.title(), "Café 日本 & \"more\"");
+/// assert_eq!(track.location().to_str(), Some("/media/a&b/<1>.mkv"));
+/// assert!(matches!(
+///     playlist.nodes(),
+///     [PlaylistNode::Dir { title, .. }] if title == "Tom & Jerry <\"Classics\">"
+/// ));
+/// ```
+///
+/// The items of the directory tree refer to the ids of the tracks, which match their position in
+/// the track list also after tracks were removed, e.g. by splitting the playlist:
+///
+/// ```
+/// use vlc_playlist_generator::{into_xml, parse_xml, PlaylistBuilder, PlaylistNode, XmlOptions};
+///
+/// let mut builder = PlaylistBuilder::new();
+/// builder
+///     .add_directory("Movies")
+///     .add_track("/media/movies/a.mkv", "A", 0)
+///     .end_directory()
+///     .add_directory("Series")
+///     .add_track("/media/series/b.mkv", "B", 0)
+///     .add_track("/media/series/c.mkv", "C", 0)
+///     .end_directory();
+/// let series = builder.build().split().pop().unwrap();
+///
+/// let mut xml = vec![];
+/// into_xml(&mut xml, series, &XmlOptions::default()).unwrap();
+///
+/// let playlist = parse_xml(xml.as_slice(), None, None).unwrap();
+/// let [PlaylistNode::Dir { nodes, .. }] = playlist.nodes() else {
+///     panic!("expected a single directory");
+/// };
+/// let locations: Vec<_> = nodes
+///     .iter()
+///     .map(|node| match node {
+///         PlaylistNode::File(idx, _) => playlist.track(*idx).location().to_str().unwrap(),
+///         PlaylistNode::Dir { .. } => panic!("expected only files"),
+///     })
+///     .collect();
+/// assert_eq!(locations, ["/media/series/b.mkv", "/media/series/c.mkv"]);
+/// ```
+///
+/// Non-ASCII characters are written as UTF-8, or as character references with
+/// `escape_non_ascii`. Both read back as the same title:
+///
+/// ```
+/// use vlc_playlist_generator::{into_xml, parse_xml, PlaylistBuilder, XmlOptions};
+///
+/// for escape_non_ascii in [false, true] {
+///     let mut builder = PlaylistBuilder::new();
+///     builder.add_track("/media/a.mkv", "Café 日本", 0);
+///     let options = XmlOptions {
+///         escape_non_ascii,
+///         ..XmlOptions::default()
+///     };
+///
+///     let mut xml = vec![];
+///     into_xml(&mut xml, builder.build(), &options).unwrap();
+///     let text = String::from_utf8(xml).unwrap();
+///     if escape_non_ascii {
+///         assert!(text.is_ascii());
+///         assert!(text.contains("<title>Caf&#233; &#26085;&#26412;</title>"));
+///     } else {
+///         assert!(text.contains("<title>Café 日本</title>"));
+///     }
+///
+///     let playlist = parse_xml(text.as_bytes(), None, None).unwrap();
+///     assert_eq!(playlist.tracks().next().unwrap().title(), "Café 日本");
+/// }
+/// ```
+pub fn into_xml<W: Write>(
+    writer: &mut W,
+    playlist: Playlist,
+    options: &XmlOptions,
+) -> io::Result<()> {
+    write_line(writer, options, 0, format_args!("{}", XML_HEADER))?;
+    let playlist_start_tag = if options.vlc_extensions {
+        PLAYLIST_START_TAG
+    } else {
+        STANDARD_PLAYLIST_START_TAG
+    };
+    write_line(writer, options, 0, format_args!("{}", playlist_start_tag))?;
+    write_line(
+        writer,
+        options,
+        1,
+        format_args!(
+            "{}{}{}",
+            TITLE_START_TAG,
+            encode_text(&options.title),
+            TITLE_END_TAG
+        ),
+    )?;
+    write_line(writer, options, 1, format_args!("{}", TRACKLIST_START_TAG))?;
+
+    let ids = VlcIds::new(&playlist);
+    let total_duration = playlist.tracks().map(Track::duration).sum();
+
+    // VLC only loads a single subtitle file through an option, its location is written like the
+    // track's
+    let subtitle_options = |track: &Track| -> Vec<String> {
+        track
+            .subtitles()
+            .first()
+            .map(|subtitle| format!("sub-file={}", location_uri(subtitle, options)))
+            .into_iter()
+            .collect()
+    };
 
-        writeln!(writer, "\t\t\t{}", EXTENSION_START_TAG).unwrap();
-        writeln!(
+    for (idx, track) in playlist.tracks().enumerate() {
+        let mut vlc_options = subtitle_options(track);
+        if let Some(start) = track.start_time() {
+            vlc_options.push(format!("start-time={}", option_secs(start)));
+        }
+        if let Some(stop) = track.stop_time() {
+            vlc_options.push(format!("stop-time={}", option_secs(stop)));
+        }
+
+        track_into_xml(
             writer,
-            "\t\t\t\t{}{}{}",
-            VLC_ID_START_TAG, idx, VLC_ID_END_TAG
-        )
-        .unwrap();
-        writeln!(writer, "\t\t\t{}", EXTENSION_END_TAG).unwrap();
+            track,
+            idx,
+            track.title(),
+            track.duration(),
+            &vlc_options,
+            options,
+        )?;
+    }
+
+    // Chapters are written as additional tracks that play part of the file, which requires the
+    // start and stop time options
+    let chapter_tracks = if options.vlc_extensions {
+        playlist.tracks().enumerate().collect()
+    } else {
+        vec![]
+    };
+    for (idx, track) in chapter_tracks {
+        let chapters = track.chapters();
+        for ((offset, chapter), id) in chapters.iter().enumerate().zip(ids.chapters(idx)) {
+            let end = chapters
+                .get(offset + 1)
+                .map(Chapter::start)
+                .unwrap_or_else(|| track.duration());
+
+            let mut vlc_options = subtitle_options(track);
+            vlc_options.push(format!("start-time={}", option_secs(chapter.start())));
+            if offset + 1 < chapters.len() {
+                vlc_options.push(format!("stop-time={}", option_secs(end)));
+            }
+
+            track_into_xml(
+                writer,
+                track,
+                id,
+                &format!("{} - {}", track.title(), chapter.title()),
+                end.saturating_sub(chapter.start()),
+                &vlc_options,
+                options,
+            )?;
+        }
+    }
+
+    write_line(writer, options, 1, format_args!("{}", TRACKLIST_END_TAG))?;
+    if options.vlc_extensions {
+        playlist_extension_into_xml(writer, &playlist, &ids, total_duration, options)?;
+    }
+
+    // Compact output still ends with a line break
+    write_line(writer, options, 0, format_args!("{}", PLAYLIST_END_TAG))?;
+    if options.compact {
+        writeln!(writer)?;
+    }
+
+    Ok(())
+}
+
+fn playlist_extension_into_xml<W: Write>(
+    writer: &mut W,
+    playlist: &Playlist,
+    ids: &VlcIds,
+    total_duration: u64,
+    options: &XmlOptions,
+) -> io::Result<()> {
+    let track_count = playlist.tracks().count();
+    write_line(writer, options, 1, format_args!("{}", EXTENSION_START_TAG))?;
+    write_line(
+        writer,
+        options,
+        2,
+        format_args!(
+            "<!-- {} tracks, {} -->",
+            track_count,
+            format_duration(total_duration)
+        ),
+    )?;
+    write_line(
+        writer,
+        options,
+        2,
+        format_args!(
+            "{}{}{}",
+            VLC_TOTAL_DURATION_START_TAG,
+            options.duration_unit.convert(total_duration),
+            VLC_TOTAL_DURATION_END_TAG
+        ),
+    )?;
+    write_line(
+        writer,
+        options,
+        2,
+        format_args!(
+            "{}{}{}",
+            VLC_TRACK_COUNT_START_TAG, track_count, VLC_TRACK_COUNT_END_TAG
+        ),
+    )?;
+    if !options.flatten {
+        nodes_into_xml(writer, playlist, playlist.nodes(), ids, 2, options)?;
+    }
+    write_line(writer, options, 1, format_args!("{}", EXTENSION_END_TAG))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        generate_playlist, parse_xml,
+        test_util::{mkv, TempDir},
+        xml_reader::tokenize,
+        ScanOptions,
+    };
+
+    #[test]
+    fn segments_are_percent_encoded() {
+        assert_eq!(
+            encode_path_segment(OsStr::new("a b#1.mkv")),
+            "a%20b%231.mkv"
+        );
+        assert_eq!(encode_path_segment(OsStr::new("a/b")), "a%2Fb");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn separators_are_kept() {
+        assert_eq!(
+            file_uri(Path::new("/home/me/Season 1/#2 a b.mkv")),
+            "file:///home/me/Season%201/%232%20a%20b.mkv"
+        );
+    }
+
+    #[test]
+    fn relative_paths_are_absolute() {
+        let cwd = std::env::current_dir().unwrap();
+        assert_eq!(
+            file_uri(Path::new("lib/x.mp3")),
+            file_uri(&cwd.join("lib/x.mp3"))
+        );
+        assert!(file_uri(Path::new("lib/x.mp3")).starts_with("file:///"));
+
+        // Tests run in the package directory, so the relative root is below it
+        let dir = TempDir::new_in(Path::new("target"));
+        let location = dir.file("lib/x.mkv", &mkv(1000.0));
+        let roots = [dir.path().join("lib")];
+        assert!(roots[0].is_relative());
+        let (playlist, _) = generate_playlist(&roots, &[], &ScanOptions::default());
 
-        writeln!(writer, "\t\t{}", TRACK_END_TAG).unwrap();
+        let mut xml = vec![];
+        into_xml(&mut xml, playlist, &XmlOptions::default()).unwrap();
+        let text = String::from_utf8(xml).unwrap();
+        let uri = file_uri(&cwd.join(location));
+        assert!(text.contains(&format!("<location>{}</location>", uri)));
     }
 
-    writeln!(writer, "\t{}", TRACKLIST_END_TAG).unwrap();
+    #[test]
+    fn names_with_markup_are_well_formed() {
+        let dir = TempDir::new();
+        dir.file("R&B <live>/Tom & Jerry.mkv", &mkv(1000.0));
+        dir.file("R&B <live>/a<b \"c\" 'd'.mkv", &mkv(1000.0));
+        let roots = [dir.path().to_path_buf()];
+        let (playlist, errors) = generate_playlist(&roots, &[], &ScanOptions::default());
+        assert!(errors.is_empty());
 
-    writeln!(writer, "\t{}", EXTENSION_START_TAG).unwrap();
-    nodes_into_xml(writer, playlist.nodes(), 2);
-    writeln!(writer, "\t{}", EXTENSION_END_TAG).unwrap();
+        let mut xml = vec![];
+        into_xml(&mut xml, playlist, &XmlOptions::default()).unwrap();
+        let text = String::from_utf8(xml).unwrap();
+        tokenize(&text).unwrap();
 
-    writeln!(writer, "{}", PLAYLIST_END_TAG).unwrap();
+        let playlist = parse_xml(text.as_bytes(), None, None).unwrap();
+        let mut titles: Vec<_> = playlist.tracks().map(|track| track.title()).collect();
+        titles.sort();
+        assert_eq!(titles, ["Tom & Jerry.mkv", "a<b \"c\" 'd'.mkv"]);
+        // The root is the only top level node, with the directory below it
+        let [PlaylistNode::Dir { nodes, .. }] = &playlist.nodes[..] else {
+            panic!("expected a single root");
+        };
+        let [PlaylistNode::Dir { title, .. }] = &nodes[..] else {
+            panic!("expected a single directory");
+        };
+        assert_eq!(title, "R&B <live>");
+    }
+
+    #[test]
+    fn filtered_ids_are_dense_and_match() {
+        let dir = TempDir::new();
+        dir.file("a/short.mkv", &mkv(500.0));
+        dir.file("a/1.mkv", &mkv(2000.0));
+        dir.file("a/b/2.mkv", &mkv(2000.0));
+        dir.file("c/gone.mkv", &mkv(2000.0));
+        dir.file("3.mkv", &mkv(2000.0));
+        let roots = [dir.path().to_path_buf()];
+        let options = ScanOptions {
+            min_duration: Some(1000),
+            ..ScanOptions::default()
+        };
+        let (mut playlist, _) = generate_playlist(&roots, &[], &options);
+        playlist.retain_tracks(|track| !track.location().ends_with("c/gone.mkv"));
+
+        let mut xml = vec![];
+        into_xml(&mut xml, playlist, &XmlOptions::default()).unwrap();
+        let text = String::from_utf8(xml).unwrap();
+        let values = |prefix: &str, suffix: char| -> Vec<usize> {
+            text.split(prefix)
+                .skip(1)
+                .map(|rest| rest.split(suffix).next().unwrap().parse().unwrap())
+                .collect()
+        };
+
+        let ids = values("<vlc:id>", '<');
+        let mut tids = values("tid=\"", '"');
+        tids.sort_unstable();
+        assert_eq!(ids, [0, 1, 2]);
+        assert_eq!(tids, ids);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn subtitles_are_located_like_tracks() {
+        use std::{fs, os::unix::ffi::OsStrExt};
+
+        let dir = TempDir::new();
+        dir.file("Season 1/a b.mkv", &mkv(1000.0));
+        dir.file("Season 1/a b.srt", b"");
+        let stem = OsStr::from_bytes(b"c\xff");
+        fs::write(dir.path().join(stem).with_extension("mkv"), mkv(1000.0)).unwrap();
+        fs::write(dir.path().join(stem).with_extension("srt"), b"").unwrap();
+        let roots = [dir.path().to_path_buf()];
+        let options = ScanOptions {
+            subtitles: true,
+            ..ScanOptions::default()
+        };
+        let xml_options = XmlOptions {
+            relative_to: Some(dir.path().to_path_buf()),
+            ..XmlOptions::default()
+        };
+
+        let (playlist, _) = generate_playlist(&roots, &[], &options);
+        let mut xml = vec![];
+        into_xml(&mut xml, playlist, &xml_options).unwrap();
+        let text = String::from_utf8(xml).unwrap();
+        assert!(text.contains("<vlc:option>sub-file=Season%201/a%20b.srt</vlc:option>"));
+        assert!(text.contains("<vlc:option>sub-file=c%FF.srt</vlc:option>"));
+
+        let playlist = parse_xml(text.as_bytes(), Some(dir.path()), None).unwrap();
+        let subtitles: Vec<_> = playlist.tracks().map(Track::subtitles).collect();
+        assert_eq!(
+            subtitles,
+            [
+                [dir.path().join("Season 1/a b.srt")],
+                [dir.path().join(stem).with_extension("srt")]
+            ]
+        );
+    }
+
+    // A scanned tree with nested directories, written with `options`.
+    fn tree_xml(dir: &TempDir, options: &XmlOptions) -> String {
+        for path in ["a/1.mkv", "a/b/2.mkv", "3.mkv"] {
+            dir.file(path, &mkv(1000.0));
+        }
+        let roots = [dir.path().to_path_buf()];
+        let (playlist, _) = generate_playlist(&roots, &[], &ScanOptions::default());
+
+        let mut xml = vec![];
+        into_xml(&mut xml, playlist, options).unwrap();
+        String::from_utf8(xml).unwrap()
+    }
+
+    #[test]
+    fn compact_output_has_no_whitespace() {
+        let dir = TempDir::new();
+        let pretty = tree_xml(&dir, &XmlOptions::default());
+        let options = XmlOptions {
+            compact: true,
+            ..XmlOptions::default()
+        };
+        let compact = tree_xml(&dir, &options);
+
+        // Only the final line break is kept
+        let body = compact.strip_suffix('\n').unwrap();
+        assert!(!body.contains(['\n', '\t']));
+        assert!(compact.len() < pretty.len());
+
+        let pretty = parse_xml(pretty.as_bytes(), None, None).unwrap();
+        let compact = parse_xml(compact.as_bytes(), None, None).unwrap();
+        assert!(pretty.nodes() == compact.nodes());
+        let tracks = |playlist: &Playlist| -> Vec<_> {
+            playlist
+                .tracks()
+                .map(|track| {
+                    let location = track.location().to_path_buf();
+                    (location, track.title().to_string(), track.duration())
+                })
+                .collect()
+        };
+        assert_eq!(tracks(&pretty), tracks(&compact));
+        assert_eq!(tracks(&compact).len(), 3);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn output_has_no_bom_or_carriage_returns() {
+        let dir = TempDir::new();
+        dir.file("Line\r\nbreak/a\rb.mkv", &mkv(1000.0));
+        let xml = tree_xml(&dir, &XmlOptions::default());
+
+        assert!(xml.starts_with("<?xml"));
+        assert!(!xml.contains('\r'));
+        assert!(xml.contains("a&#13;b.mkv"));
+        let playlist = parse_xml(xml.as_bytes(), None, None).unwrap();
+        assert!(playlist.tracks().any(|track| track.title() == "a\rb.mkv"));
+    }
 }
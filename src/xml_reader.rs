@@ -0,0 +1,583 @@
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+use url_escape::percent_encoding::percent_decode_str;
+
+use crate::{fallback_title, log::warning, Chapter, Playlist, PlaylistNode, Track, TrackList};
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+pub(crate) enum Event {
+    Start {
+        name: String,
+        attrs: Vec<(String, String)>,
+    },
+    End {
+        name: String,
+    },
+    Text(String),
+}
+
+// Checks that every `&` in `text` starts a character reference or one of the entity references
+// XML defines.
+fn check_references(text: &str) -> io::Result<()> {
+    for (pos, _) in text.match_indices('&') {
+        let rest = &text[pos + 1..];
+        let name = rest.find(';').map_or("", |end| &rest[..end]);
+        let valid = match name.strip_prefix('#') {
+            Some(num) => match num.strip_prefix('x') {
+                Some(hex) => !hex.is_empty() && hex.chars().all(|c| c.is_ascii_hexdigit()),
+                None => !num.is_empty() && num.chars().all(|c| c.is_ascii_digit()),
+            },
+            None => matches!(name, "amp" | "lt" | "gt" | "quot" | "apos"),
+        };
+        if !valid {
+            return Err(invalid("unescaped &"));
+        }
+    }
+
+    Ok(())
+}
+
+fn decode_text(text: &str) -> io::Result<String> {
+    check_references(text)?;
+    Ok(html_escape::decode_html_entities(text).into())
+}
+
+// The end of the tag at the start of `tag`, a `>` in a quoted attribute value doesn't end it.
+fn tag_end(tag: &str) -> Option<usize> {
+    let mut quote = None;
+    for (pos, c) in tag.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => (),
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '>' => return Some(pos),
+            None => (),
+        }
+    }
+
+    None
+}
+
+// A minimal XML tokenizer, it only supports what's needed to read XSPF playlists and NFO files.
+// Documents that aren't well-formed, like those with unescaped characters or unclosed elements,
+// are rejected.
+pub(crate) fn tokenize(data: &str) -> io::Result<Vec<Event>> {
+    let mut events = vec![];
+    let mut open: Vec<String> = vec![];
+    let mut rest = data;
+
+    while !rest.is_empty() {
+        let Some(start) = rest.find('<') else {
+            events.push(Event::Text(decode_text(rest)?));
+            break;
+        };
+
+        if start > 0 {
+            events.push(Event::Text(decode_text(&rest[..start])?));
+        }
+        rest = &rest[start..];
+
+        let end_marker = if rest.starts_with("<?") {
+            "?>"
+        } else if rest.starts_with("<!--") {
+            "-->"
+        } else if rest.starts_with("<![CDATA[") {
+            let end = rest
+                .find("]]>")
+                .ok_or_else(|| invalid("unclosed CDATA section"))?;
+            events.push(Event::Text(rest[9..end].into()));
+            rest = &rest[end + 3..];
+            continue;
+        } else {
+            ">"
+        };
+
+        let end = if end_marker == ">" {
+            tag_end(rest)
+        } else {
+            rest.find(end_marker)
+        }
+        .ok_or_else(|| invalid("unclosed tag"))?;
+        let tag = &rest[1..end];
+        rest = &rest[end + end_marker.len()..];
+
+        if tag.starts_with(['?', '!']) {
+            continue;
+        }
+
+        if let Some(name) = tag.strip_prefix('/') {
+            let name = name.trim();
+            if open.pop().as_deref() != Some(name) {
+                return Err(invalid("mismatched closing tag"));
+            }
+            events.push(Event::End { name: name.into() });
+            continue;
+        }
+
+        let (tag, empty) = match tag.strip_suffix('/') {
+            Some(tag) => (tag, true),
+            None => (tag, false),
+        };
+
+        let name_end = tag.find(char::is_whitespace).unwrap_or(tag.len());
+        let name = tag[..name_end].to_string();
+        let attrs = parse_attrs(&tag[name_end..])?;
+
+        events.push(Event::Start {
+            name: name.clone(),
+            attrs,
+        });
+        if empty {
+            events.push(Event::End { name });
+        } else {
+            open.push(name);
+        }
+    }
+
+    if !open.is_empty() {
+        return Err(invalid("unclosed element"));
+    }
+
+    Ok(events)
+}
+
+fn parse_attrs(mut attrs: &str) -> io::Result<Vec<(String, String)>> {
+    let mut parsed = vec![];
+
+    loop {
+        attrs = attrs.trim_start();
+        if attrs.is_empty() {
+            return Ok(parsed);
+        }
+
+        let eq = attrs
+            .find('=')
+            .ok_or_else(|| invalid("invalid attribute"))?;
+        let name = attrs[..eq].trim().to_string();
+        attrs = attrs[eq + 1..].trim_start();
+
+        let quote = attrs
+            .chars()
+            .next()
+            .filter(|&c| c == '"' || c == '\'')
+            .ok_or_else(|| invalid("unquoted attribute value"))?;
+        let end = attrs[1..]
+            .find(quote)
+            .ok_or_else(|| invalid("unclosed attribute value"))?;
+        let value = &attrs[1..end + 1];
+        if value.contains('<') {
+            return Err(invalid("unescaped < in attribute value"));
+        }
+        let value = decode_text(value)?;
+        attrs = &attrs[end + 2..];
+
+        parsed.push((name, value));
+    }
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    use std::os::unix::ffi::OsStringExt;
+
+    PathBuf::from(OsString::from_vec(bytes))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    PathBuf::from(OsString::from(String::from_utf8_lossy(&bytes).into_owned()))
+}
+
+// Converts a location back into a path, the reverse of `file_uri`, `relative_uri` and
+// `url_location`. Relative locations and those below `base_url` are resolved against `base`,
+// other URLs don't refer to a file and give `None`.
+fn location_path(location: &str, base: Option<&Path>, base_url: Option<&str>) -> Option<PathBuf> {
+    let below_base_url = base_url
+        .and_then(|url| location.strip_prefix(url.trim_end_matches('/')))
+        .and_then(|rest| rest.strip_prefix('/'));
+    if let Some(relative) = below_base_url {
+        return location_path(relative, base, None);
+    }
+
+    let Some(rest) = location.strip_prefix("file://") else {
+        if has_scheme(location) {
+            return None;
+        }
+        let path = path_from_bytes(percent_decode_str(location).collect());
+        return Some(match base {
+            Some(base) => base.join(path),
+            None => path,
+        });
+    };
+
+    let bytes: Vec<u8> = percent_decode_str(rest).collect();
+    let path = if cfg!(windows) {
+        // `/C:/a.mkv` is a drive path, `server/share/a.mkv` a UNC path
+        match bytes.as_slice() {
+            [b'/', drive, b':', ..] if drive.is_ascii_alphabetic() => {
+                path_from_bytes(bytes[1..].to_vec())
+            }
+            [b'/', ..] => path_from_bytes(bytes),
+            _ => path_from_bytes([b"\\".as_slice(), &bytes].concat()),
+        }
+    } else {
+        path_from_bytes(bytes)
+    };
+    Some(path)
+}
+
+// Whether a location starts with a URI scheme like `http:`. Relative locations whose first segment
+// contains a colon are written with a leading `./`, and a single letter is a Windows drive.
+fn has_scheme(location: &str) -> bool {
+    location.split_once(':').is_some_and(|(scheme, _)| {
+        scheme.len() > 1
+            && scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+            && scheme
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+    })
+}
+
+// The path of a track and the archive it's in. Files in a zip archive are located by the archive
+// and the path in it, e.g. `zip:///music/live.zip!/01%20Intro.mp3`.
+fn track_location(
+    location: &str,
+    base: Option<&Path>,
+    base_url: Option<&str>,
+) -> Option<(PathBuf, Option<PathBuf>)> {
+    match location.strip_prefix("zip://") {
+        Some(rest) => {
+            let (archive, inner) = rest.split_once('!')?;
+            let archive = location_path(&format!("file://{}", archive), None, None)?;
+            let inner =
+                path_from_bytes(percent_decode_str(inner.trim_start_matches('/')).collect());
+            Some((archive.join(inner), Some(archive)))
+        }
+        None => Some((location_path(location, base, base_url)?, None)),
+    }
+}
+
+#[derive(Default)]
+struct PendingTrack {
+    location: Option<String>,
+    title: Option<String>,
+    duration: Option<u64>,
+    id: Option<usize>,
+    subtitles: Vec<PathBuf>,
+    start: Option<u64>,
+    stop: Option<u64>,
+    image: Option<String>,
+    creator: Option<String>,
+    annotation: Option<String>,
+}
+
+// Parses the seconds of a start or stop time option into milliseconds.
+fn parse_option_secs(secs: &str) -> Option<u64> {
+    let secs: f64 = secs.parse().ok()?;
+    (secs >= 0.0).then(|| (secs * 1000.0).round() as u64)
+}
+
+/// Reads a playlist from XSPF, as written by [`into_xml`](crate::into_xml). Relative track
+/// locations, and those that start with `base_url`, are resolved against `base`. Tracks without a
+/// location or with a URL that isn't a file are skipped, and if the playlist has no VLC node tree
+/// all tracks are added to the top level.
+pub fn parse_xml<R: Read>(
+    mut reader: R,
+    base: Option<&Path>,
+    base_url: Option<&str>,
+) -> io::Result<Playlist> {
+    let mut data = String::new();
+    reader.read_to_string(&mut data)?;
+
+    let mut tracks: Vec<Track> = vec![];
+    let mut ids = HashMap::new();
+    let mut track: Option<PendingTrack> = None;
+    let mut elements: Vec<String> = vec![];
+    let mut node_stack: Vec<(String, Vec<PlaylistNode>)> = vec![(String::new(), vec![])];
+
+    for event in tokenize(&data)? {
+        match event {
+            Event::Start { name, attrs } => {
+                let attr = |key: &str| attrs.iter().find(|(k, _)| k == key).map(|(_, v)| v);
+                match name.as_str() {
+                    "track" => track = Some(PendingTrack::default()),
+                    "vlc:node" => {
+                        let title = attr("title").cloned().unwrap_or_default();
+                        node_stack.push((title, vec![]));
+                    }
+                    "vlc:item" => {
+                        let tid = attr("tid").and_then(|tid| tid.parse::<usize>().ok());
+                        if let Some(tid) = tid {
+                            let nodes = &mut node_stack.last_mut().unwrap().1;
+                            nodes.push(PlaylistNode::File(tid, OsString::new()));
+                        }
+                    }
+                    _ => (),
+                }
+                elements.push(name);
+            }
+            Event::End { name } => {
+                // `tokenize` already checked that the tags match
+                elements.pop();
+
+                match name.as_str() {
+                    "track" => {
+                        let Some(pending) = track.take() else {
+                            continue;
+                        };
+                        let Some(location) = pending.location else {
+                            continue;
+                        };
+
+                        let Some((location, archive)) = track_location(&location, base, base_url)
+                        else {
+                            warning!("Skipped {}: not a file location", location);
+                            continue;
+                        };
+
+                        // Chapters are written as tracks of the same file with a start time, the
+                        // first track of a file with a start time is the file itself
+                        if let Some(start) = pending.start {
+                            let parent = tracks.iter_mut().find(|t| t.location == location);
+                            if let Some(parent) = parent {
+                                let title = pending.title.unwrap_or_default();
+                                let title = title
+                                    .strip_prefix(&format!("{} - ", parent.title))
+                                    .map(String::from)
+                                    .unwrap_or(title);
+                                parent.chapters.push(Chapter { title, start });
+                                continue;
+                            }
+                        }
+
+                        let title = pending.title.unwrap_or_else(|| fallback_title(&location));
+
+                        ids.insert(pending.id.unwrap_or(tracks.len()), tracks.len());
+                        let image = pending
+                            .image
+                            .and_then(|image| location_path(&image, base, base_url));
+                        let duration = pending.duration.unwrap_or(0);
+                        tracks.push(Track {
+                            subtitles: pending.subtitles,
+                            start_time: pending.start,
+                            stop_time: pending.stop,
+                            image,
+                            archive,
+                            creator: pending.creator,
+                            annotation: pending.annotation,
+                            ..Track::new(location, title, duration)
+                        });
+                    }
+                    "vlc:node" if node_stack.len() > 1 => {
+                        let (title, nodes) = node_stack.pop().unwrap();
+                        let parent = &mut node_stack.last_mut().unwrap().1;
+                        parent.push(PlaylistNode::Dir {
+                            title,
+                            path: PathBuf::new(),
+                            nodes,
+                        });
+                    }
+                    _ => (),
+                }
+            }
+            Event::Text(text) => {
+                let (Some(pending), Some(element)) = (track.as_mut(), elements.last()) else {
+                    continue;
+                };
+
+                let text = text.trim();
+                match element.as_str() {
+                    "location" => pending.location = Some(text.into()),
+                    "title" => pending.title = Some(text.into()),
+                    "creator" => pending.creator = Some(text.into()),
+                    "annotation" => pending.annotation = Some(text.into()),
+                    "duration" => pending.duration = text.parse().ok(),
+                    "image" => pending.image = Some(text.into()),
+                    "vlc:id" => pending.id = text.parse().ok(),
+                    "vlc:option" => {
+                        if let Some(subtitle) = text.strip_prefix("sub-file=") {
+                            pending
+                                .subtitles
+                                .extend(location_path(subtitle, base, base_url));
+                        } else if let Some(start) = text.strip_prefix("start-time=") {
+                            pending.start = parse_option_secs(start);
+                        } else if let Some(stop) = text.strip_prefix("stop-time=") {
+                            pending.stop = parse_option_secs(stop);
+                        }
+                    }
+                    _ => (),
+                }
+            }
+        }
+    }
+
+    let (_, nodes) = node_stack.swap_remove(0);
+    let mut nodes = resolve_items(nodes, &ids, &tracks);
+    if nodes.is_empty() {
+        nodes = tracks
+            .iter()
+            .enumerate()
+            .map(|(idx, track)| PlaylistNode::File(idx, file_name(track)))
+            .collect();
+    }
+
+    Ok(Playlist {
+        track_list: TrackList { tracks },
+        nodes,
+    })
+}
+
+fn file_name(track: &Track) -> OsString {
+    track
+        .location()
+        .file_name()
+        .map(|name| name.to_os_string())
+        .unwrap_or_default()
+}
+
+// Items refer to tracks by their VLC id, which is replaced with the index of the track. Items
+// without a matching track, like those of chapters, are dropped. The node that groups the chapters
+// of a file is replaced by the file itself. The tree is rebuilt with an explicit stack of the
+// directories that are still being resolved.
+fn resolve_items(
+    nodes: Vec<PlaylistNode>,
+    ids: &HashMap<usize, usize>,
+    tracks: &[Track],
+) -> Vec<PlaylistNode> {
+    let mut stack = vec![(String::new(), PathBuf::new(), nodes.into_iter(), vec![])];
+    loop {
+        let (_, _, remaining, resolved) = stack.last_mut().unwrap();
+        match remaining.next() {
+            Some(PlaylistNode::File(tid, _)) => {
+                if let Some(&idx) = ids.get(&tid) {
+                    resolved.push(PlaylistNode::File(idx, file_name(&tracks[idx])));
+                }
+            }
+            Some(PlaylistNode::Dir { title, path, nodes }) => {
+                stack.push((title, path, nodes.into_iter(), vec![]));
+            }
+            None => {
+                let (title, path, _, mut nodes) = stack.pop().unwrap();
+                let Some((_, _, _, parent)) = stack.last_mut() else {
+                    return nodes;
+                };
+
+                if let [PlaylistNode::File(idx, _)] = nodes[..] {
+                    let track = &tracks[idx];
+                    if !track.chapters.is_empty() && track.title == title {
+                        parent.extend(nodes.pop());
+                        continue;
+                    }
+                }
+                parent.push(PlaylistNode::Dir { title, path, nodes });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{into_xml, xml::archive_uri, PlaylistBuilder, XmlOptions};
+
+    #[test]
+    fn malformed_documents_are_rejected() {
+        for data in [
+            "<a>Tom & Jerry</a>",
+            "<a>&nbsp;</a>",
+            "<a b=\"Tom & Jerry\"/>",
+            "<a b=\"a<b\"/>",
+            "<a><b></a></b>",
+            "<a><b/>",
+        ] {
+            assert!(tokenize(data).is_err(), "{}", data);
+        }
+    }
+
+    #[test]
+    fn references_are_decoded() {
+        let events = tokenize("<a b=\"&lt;&#38;&quot;\">&amp;&#x3c;&apos;</a>").unwrap();
+        let [Event::Start { attrs, .. }, Event::Text(text), Event::End { .. }] = &events[..] else {
+            panic!("unexpected events");
+        };
+        assert_eq!(attrs[0].1, "<&\"");
+        assert_eq!(text, "&<'");
+    }
+
+    // The locations of the tracks of `playlist` after writing and reading it.
+    fn round_trip(playlist: Playlist, options: &XmlOptions, base: Option<&Path>) -> Vec<PathBuf> {
+        let mut xml = vec![];
+        into_xml(&mut xml, playlist, options).unwrap();
+        let playlist = parse_xml(xml.as_slice(), base, options.base_url.as_deref()).unwrap();
+        playlist
+            .tracks()
+            .map(|track| track.location().to_path_buf())
+            .collect()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn locations_round_trip() {
+        let paths = [
+            "/media/Season 1/a&b.mkv",
+            "/media/c:d.mkv",
+            "/other/e#f.mkv",
+        ];
+        let playlist = || {
+            let mut builder = PlaylistBuilder::new();
+            for path in paths {
+                builder.add_track(path, "Title", 1000);
+            }
+            builder.build()
+        };
+        let expected: Vec<PathBuf> = paths.iter().map(PathBuf::from).collect();
+
+        assert_eq!(
+            round_trip(playlist(), &XmlOptions::default(), None),
+            expected
+        );
+        let relative = XmlOptions {
+            relative_to: Some("/media".into()),
+            ..XmlOptions::default()
+        };
+        let base = Some(Path::new("/media"));
+        assert_eq!(round_trip(playlist(), &relative, base), expected);
+        let url = XmlOptions {
+            base_url: Some("http://nas.local/media/".into()),
+            ..relative
+        };
+        assert_eq!(round_trip(playlist(), &url, base), expected);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn archive_locations_round_trip() {
+        let archive = Path::new("/music/live!.zip");
+        let location = archive.join("cd 1/01 a!b.mp3");
+        assert_eq!(
+            track_location(&archive_uri(archive, &location), None, None),
+            Some((location, Some(archive.to_path_buf())))
+        );
+    }
+
+    #[test]
+    fn other_urls_are_skipped() {
+        let xml = "<playlist><trackList>\
+            <track><location>https://example.com/a.mkv</location></track>\
+            <track><location>b.mkv</location></track>\
+            <track><location>http://nas.local/media/c.mkv</location></track>\
+            </trackList></playlist>";
+        let base = Path::new("media");
+        let playlist =
+            parse_xml(xml.as_bytes(), Some(base), Some("http://nas.local/media")).unwrap();
+        let locations: Vec<_> = playlist.tracks().map(Track::location).collect();
+        assert_eq!(locations, [base.join("b.mkv"), base.join("c.mkv")]);
+    }
+}
@@ -0,0 +1,257 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, Read, Seek, SeekFrom},
+    path::{Component, Path, PathBuf},
+};
+
+static END_OF_CENTRAL_DIR: u32 = 0x0605_4b50;
+static ZIP64_END_LOCATOR: u32 = 0x0706_4b50;
+static ZIP64_END_OF_CENTRAL_DIR: u32 = 0x0606_4b50;
+static CENTRAL_DIR_ENTRY: u32 = 0x0201_4b50;
+// Size of the end of central directory record without its comment
+static END_RECORD_SIZE: u64 = 22;
+
+// A file in a zip archive, with the size it's extracted to.
+pub(crate) struct ZipEntry {
+    pub(crate) path: PathBuf,
+    pub(crate) size: u64,
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+// Finds the offset and size of the central directory. The end record is at the end of the file,
+// followed by a comment of at most 64 KiB.
+fn central_dir<R: Read + Seek>(reader: &mut R) -> io::Result<(u64, u64)> {
+    let len = reader.seek(SeekFrom::End(0))?;
+    let tail_len = len.min(END_RECORD_SIZE + u16::MAX as u64);
+    let tail_start = len - tail_len;
+    reader.seek(SeekFrom::Start(tail_start))?;
+    let mut tail = vec![0; tail_len as usize];
+    reader.read_exact(&mut tail)?;
+    if tail.len() < END_RECORD_SIZE as usize {
+        return Err(invalid("not a zip archive"));
+    }
+
+    // Every candidate leaves room for a whole end record, and the locator ends where it begins
+    let end = (0..=tail.len() - END_RECORD_SIZE as usize)
+        .rev()
+        .find(|&i| u32_at(&tail, i) == END_OF_CENTRAL_DIR)
+        .ok_or_else(|| invalid("not a zip archive"))?;
+    let size = u32_at(&tail, end + 12);
+    let offset = u32_at(&tail, end + 16);
+    if offset != u32::MAX && size != u32::MAX {
+        return Ok((offset as u64, size as u64));
+    }
+
+    // ZIP64 archives store the real values in a second end record, which the locator before the
+    // regular end record points to
+    let locator = end
+        .checked_sub(20)
+        .filter(|&i| u32_at(&tail, i) == ZIP64_END_LOCATOR)
+        .ok_or_else(|| invalid("missing ZIP64 end of central directory locator"))?;
+    reader.seek(SeekFrom::Start(u64_at(&tail, locator + 8)))?;
+    let mut record = [0; 56];
+    reader.read_exact(&mut record)?;
+    if u32_at(&record, 0) != ZIP64_END_OF_CENTRAL_DIR {
+        return Err(invalid("invalid ZIP64 end of central directory"));
+    }
+
+    Ok((u64_at(&record, 48), u64_at(&record, 40)))
+}
+
+// Reads the extracted size from the ZIP64 extra field, which is only present if the size in the
+// entry itself doesn't fit in 32 bits.
+fn zip64_size(extra: &[u8]) -> Option<u64> {
+    let mut offset = 0;
+    while offset + 4 <= extra.len() {
+        let id = u16_at(extra, offset);
+        let len = u16_at(extra, offset + 2) as usize;
+        if id == 1 && len >= 8 {
+            return extra
+                .get(offset + 4..offset + 12)
+                .map(|size| u64::from_le_bytes(size.try_into().unwrap()));
+        }
+        offset += 4 + len;
+    }
+
+    None
+}
+
+// Only relative paths that stay inside the archive are accepted, directories are skipped.
+fn entry_path(name: &str) -> Option<PathBuf> {
+    let name = name.replace('\\', "/");
+    if name.ends_with('/') {
+        return None;
+    }
+
+    let path = PathBuf::from(name);
+    path.components()
+        .all(|component| matches!(component, Component::Normal(_)))
+        .then_some(path)
+}
+
+// Lists the files in a zip archive by reading its central directory, no data is extracted.
+pub(crate) fn zip_entries(path: &Path) -> io::Result<Vec<ZipEntry>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let (offset, size) = central_dir(&mut reader)?;
+    reader.seek(SeekFrom::Start(offset))?;
+    let mut data = vec![];
+    reader.take(size).read_to_end(&mut data)?;
+    if (data.len() as u64) < size {
+        return Err(invalid("truncated central directory"));
+    }
+
+    let mut entries = vec![];
+    let mut pos = 0;
+    while pos + 46 <= data.len() && u32_at(&data, pos) == CENTRAL_DIR_ENTRY {
+        let name_len = u16_at(&data, pos + 28) as usize;
+        let extra_len = u16_at(&data, pos + 30) as usize;
+        let comment_len = u16_at(&data, pos + 32) as usize;
+        let name_start = pos + 46;
+        let extra_start = name_start + name_len;
+        let next = extra_start + extra_len + comment_len;
+        if next > data.len() {
+            return Err(invalid("truncated central directory"));
+        }
+
+        // Names that aren't UTF-8 are usually in code page 437, which matches it for ASCII
+        let name = String::from_utf8_lossy(&data[name_start..extra_start]);
+        let size = match u32_at(&data, pos + 24) {
+            u32::MAX => zip64_size(&data[extra_start..extra_start + extra_len]).unwrap_or(0),
+            size => size as u64,
+        };
+        if let Some(path) = entry_path(&name) {
+            entries.push(ZipEntry { path, size });
+        }
+        pos = next;
+    }
+
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+    use std::io::Cursor;
+
+    // The central directory of an archive with files of the given sizes, without any file data.
+    // Sizes that don't fit in 32 bits are stored in a ZIP64 extra field.
+    fn central_dir_entries(files: &[(&str, u64)]) -> Vec<u8> {
+        let mut data = vec![];
+        for &(name, size) in files {
+            let extra = if size >= u32::MAX as u64 {
+                [
+                    &1u16.to_le_bytes()[..],
+                    &8u16.to_le_bytes(),
+                    &size.to_le_bytes(),
+                ]
+                .concat()
+            } else {
+                vec![]
+            };
+            data.extend(CENTRAL_DIR_ENTRY.to_le_bytes());
+            data.extend([0; 20]);
+            data.extend((size.min(u32::MAX as u64) as u32).to_le_bytes());
+            data.extend((name.len() as u16).to_le_bytes());
+            data.extend((extra.len() as u16).to_le_bytes());
+            data.extend([0; 14]);
+            data.extend(name.as_bytes());
+            data.extend(extra);
+        }
+        data
+    }
+
+    // An archive with the given files, with ZIP64 end records if `zip64` is set.
+    fn archive(files: &[(&str, u64)], zip64: bool) -> Vec<u8> {
+        let mut data = central_dir_entries(files);
+        let size = data.len() as u64;
+        let (offset, size32) = if zip64 {
+            let record = data.len() as u64;
+            data.extend(ZIP64_END_OF_CENTRAL_DIR.to_le_bytes());
+            data.extend([0; 36]);
+            data.extend(size.to_le_bytes());
+            data.extend(0u64.to_le_bytes());
+            data.extend(ZIP64_END_LOCATOR.to_le_bytes());
+            data.extend([0; 4]);
+            data.extend(record.to_le_bytes());
+            data.extend(1u32.to_le_bytes());
+            (u32::MAX, u32::MAX)
+        } else {
+            (0, size as u32)
+        };
+        data.extend(END_OF_CENTRAL_DIR.to_le_bytes());
+        data.extend([0; 8]);
+        data.extend(size32.to_le_bytes());
+        data.extend(offset.to_le_bytes());
+        data.extend([0; 2]);
+        data
+    }
+
+    fn entries(data: &[u8]) -> io::Result<Vec<(PathBuf, u64)>> {
+        let dir = TempDir::new();
+        let entries = zip_entries(&dir.file("a.zip", data))?;
+        Ok(entries
+            .into_iter()
+            .map(|entry| (entry.path, entry.size))
+            .collect())
+    }
+
+    #[test]
+    fn entries_are_listed() {
+        let data = archive(&[("a.mkv", 10), ("b/", 0), ("../c.mkv", 10)], false);
+        assert_eq!(entries(&data).unwrap(), [("a.mkv".into(), 10)]);
+    }
+
+    #[test]
+    fn empty_and_truncated_archives_are_invalid() {
+        for len in [0, 3, 4, 21] {
+            let err = central_dir(&mut Cursor::new(vec![0; len])).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+
+        let data = archive(&[("a.mkv", 10)], false);
+        for len in [0, 10, data.len() - 1] {
+            let err = entries(&data[..len]).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+
+        // An end record that claims more central directory than there is
+        let mut data = archive(&[("a.mkv", 10)], false);
+        let end = data.len() - END_RECORD_SIZE as usize;
+        data[end + 12..end + 16].copy_from_slice(&1000u32.to_le_bytes());
+        let err = entries(&data).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn zip64_sizes_are_read() {
+        let big = 5 << 30;
+        let data = archive(&[("a.mkv", big), ("b.mkv", 10)], true);
+        assert_eq!(
+            entries(&data).unwrap(),
+            [("a.mkv".into(), big), ("b.mkv".into(), 10)]
+        );
+
+        // The locator is required when the end record says the values are too large
+        let mut data = archive(&[("a.mkv", 10)], true);
+        let locator = data.len() - END_RECORD_SIZE as usize - 20;
+        data[locator] = 0;
+        let err = entries(&data).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}